@@ -0,0 +1,60 @@
+pub mod parser;
+pub mod types;
+
+use parser::lexer::Lexer;
+use parser::parser::Parser;
+use types::elements::{self, Renderable, TocEntry};
+use types::options::Options;
+use types::page::PageData;
+use types::sourcemap::SourceMap;
+
+/*
+ * Convert a Markdown string to an HTML fragment, without the surrounding <html> document
+ */
+pub fn markdown_to_html(source: &str, options: &Options) -> String {
+    markdown_to_html_with_source_map(source, options).0
+}
+
+/*
+ * Convert a Markdown string to an HTML fragment, along with the source map from each block of
+ * the fragment back to the Markdown
+ */
+pub fn markdown_to_html_with_source_map(source: &str, options: &Options) -> (String, SourceMap) {
+    render(&parse(source, options), options)
+}
+
+/*
+ * Convert a Markdown string to an HTML fragment, along with the data about the page a template
+ * may want, such as how many of its tasks are done
+ */
+pub fn markdown_to_html_with_page_data(source: &str, options: &Options) -> (String, PageData) {
+    let parser = parse(source, options);
+    (render(&parser, options).0, parser.page_data())
+}
+
+fn render(parser: &Parser, options: &Options) -> (String, SourceMap) {
+    let items: Vec<Box<dyn Renderable>> = parser
+        .tree
+        .children
+        .iter()
+        .map(|child| child.item.convert_to_renderable())
+        .collect();
+    let mut map = SourceMap::new();
+    let html = elements::render_blocks(&items, options, &mut map, 0);
+    (html, map)
+}
+
+/*
+ * The table of contents of a Markdown string, for templates that want to lay it out themselves
+ */
+pub fn table_of_contents(source: &str, options: &Options) -> Vec<TocEntry> {
+    parse(source, options).tree.toc(&options.toc_levels)
+}
+
+fn parse(source: &str, options: &Options) -> Parser {
+    let mut lexer = Lexer::new(source, options);
+    lexer.scan();
+    let mut parser = Parser::new(lexer, options);
+    parser.parse();
+    parser
+}
@@ -1,14 +1,13 @@
-mod parser;
-mod types;
+use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use clap::Parser;
-use parser::lexer::Lexer;
-
-use crate::types::elements;
-use crate::types::elements::Renderable;
+use palpad::parser::lexer::Lexer;
+use palpad::parser::{front_matter, parser, wiki};
+use palpad::types::elements;
+use palpad::types::options::{HtmlStyle, Options};
 
 #[derive(clap::Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -18,51 +17,157 @@ struct Args {
     path: String,
     #[arg(short, long)]
     csspath: Option<String>,
+    // Write void elements in XHTML style, e.g. <hr />
+    #[arg(long)]
+    xhtml: bool,
+    // Filter the HTML in the Markdown through an allowlist, for content that is not trusted
+    #[arg(long)]
+    safe: bool,
+    // Add data-sourcepos attributes giving the Markdown lines of every block
+    #[arg(long)]
+    sourcepos: bool,
+    // Add a ¶ link to every heading, pointing at the heading itself
+    #[arg(long)]
+    permalinks: bool,
+    // Also write a .map.json file next to every HTML file, mapping it back to the Markdown
+    #[arg(long)]
+    sourcemap: bool,
 }
-fn convert_file(filepath: &Path, csspath: Option<&String>) {
+fn convert_file(filepath: &Path, csspath: Option<&String>, options: &Options, sourcemap: bool) {
     match filepath.extension() {
         Some(ext) => {
             if !ext.eq("md") {
                 return;
             }
-        },
-        None => { return; }
-    }
-    if (filepath.extension().unwrap() != "md") {
-        return;
+        }
+        None => {
+            return;
+        }
     }
     println!("Running");
     let md = std::fs::read_to_string(filepath);
 
-    let mut lexer = Lexer::new(md.unwrap().as_str());
+    let mut lexer = Lexer::new(md.unwrap().as_str(), options);
     lexer.scan();
-    let mut parser = parser::parser::Parser::new(lexer);
+    let mut parser = parser::Parser::new(lexer, options);
     parser.parse();
+    for warning in parser.warnings() {
+        eprintln!("{}: {}", filepath.display(), warning);
+    }
     let root = parser.tree;
     let mut doc = elements::HTML::new();
-    if csspath.is_some() {
-        let head = elements::Head::new(format!("<link rel=\"stylesheet\" href=\"{}\">", csspath.unwrap()));
+    if let Some(csspath) = csspath {
+        let mut head = elements::Head::new();
+        head.items
+            .push(Box::new(elements::Stylesheet::new(csspath)));
         doc.items.push(Box::new(head));
     }
     for child in root.children {
         let ast = child.item;
         doc.items.push(ast.convert_to_renderable());
     }
-    let doc = doc.render();
+    let (doc, map) = doc.render_with_source_map(options);
     let html_filepath = Path::new(filepath).with_extension("html");
     let mut file = File::create(html_filepath).unwrap();
     file.write_all(doc.as_bytes()).unwrap();
+    if sourcemap {
+        let map_filepath = Path::new(filepath).with_extension("map.json");
+        let mut file = File::create(map_filepath).unwrap();
+        file.write_all(map.to_json().as_bytes()).unwrap();
+    }
+}
+
+// A Markdown page found in the directory being converted, which wiki links can point to
+struct Page {
+    path: PathBuf,
+    // The title from its front matter
+    title: Option<String>,
+}
+
+fn find_pages(dir: &Path, pages: &mut Vec<Page>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            find_pages(&path, pages);
+        } else if path.extension().is_some_and(|ext| ext == "md") {
+            let title = fs::read_to_string(&path)
+                .ok()
+                .and_then(|md| front_matter::parse(&md))
+                .and_then(|(front_matter, _)| front_matter.title().map(String::from));
+            pages.push(Page { path, title });
+        }
+    }
+}
+
+/*
+ * The pages wiki links in dir can go to, by title and by file stem, with their URLs relative to
+ * dir. A title wins over another page's file stem
+ */
+fn wiki_pages(pages: &[Page], dir: &Path) -> HashMap<String, String> {
+    let mut names = HashMap::new();
+    for page in pages {
+        if let Some(title) = &page.title {
+            names
+                .entry(wiki::page_name(title))
+                .or_insert_with(|| relative_url(dir, &page.path));
+        }
+    }
+    for page in pages {
+        if let Some(stem) = page.path.file_stem() {
+            names
+                .entry(wiki::page_name(&stem.to_string_lossy()))
+                .or_insert_with(|| relative_url(dir, &page.path));
+        }
+    }
+    names
+}
+
+// The URL of the HTML written for a Markdown page, relative to dir
+fn relative_url(dir: &Path, page: &Path) -> String {
+    let html = page.with_extension("html");
+    let dir: Vec<_> = dir.components().collect();
+    let page: Vec<_> = html.components().collect();
+    let common = dir.iter().zip(&page).take_while(|(a, b)| a == b).count();
+    let mut parts = vec!["..".to_string(); dir.len() - common];
+    parts.extend(
+        page[common..]
+            .iter()
+            .map(|part| encode_segment(&part.as_os_str().to_string_lossy())),
+    );
+    parts.join("/")
 }
 
-fn convert_dir(dir: &Path, csspath: Option<&String>) {
+// A path segment for a URL, with everything but letters, digits and `-._~` percent-encoded
+fn encode_segment(segment: &str) -> String {
+    let mut encoded = String::new();
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn convert_dir(
+    dir: &Path,
+    csspath: Option<&String>,
+    options: &Options,
+    sourcemap: bool,
+    pages: &[Page],
+) {
     if dir.is_dir() {
+        let mut options = options.clone();
+        options.wiki_pages = wiki_pages(pages, dir);
         for entry in fs::read_dir(dir).unwrap() {
             let entry = entry.unwrap();
             let path = entry.path();
             if path.is_dir() {
-                convert_dir(&path, csspath);
+                convert_dir(&path, csspath, &options, sourcemap, pages);
             } else {
-                convert_file(&path, csspath);
+                convert_file(&path, csspath, &options, sourcemap);
             }
         }
     }
@@ -71,9 +176,47 @@ fn convert_dir(dir: &Path, csspath: Option<&String>) {
 fn main() {
     let args = Args::parse();
     let path = Path::new(&args.path);
+    let mut options = if args.safe {
+        Options::safe()
+    } else {
+        Options::default()
+    };
+    if args.xhtml {
+        options.html_style = HtmlStyle::Xhtml;
+    }
+    // Pages give their titles in front matter, for wiki links to find them by
+    options.extensions.front_matter = true;
+    options.sourcepos = args.sourcepos;
+    options.heading_permalinks = args.permalinks;
     if path.is_file() {
-        convert_file(&path, args.csspath.clone().as_ref());
+        convert_file(path, args.csspath.as_ref(), &options, args.sourcemap);
     } else if path.is_dir() {
-        convert_dir(&path, args.csspath.clone().as_ref());
+        let mut pages = vec![];
+        find_pages(path, &mut pages);
+        convert_dir(
+            path,
+            args.csspath.as_ref(),
+            &options,
+            args.sourcemap,
+            &pages,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_urls() {
+        let dir = Path::new("docs/guide");
+        assert_eq!(
+            relative_url(dir, Path::new("docs/guide/Q&A #1?.md")),
+            "Q%26A%20%231%3F.html"
+        );
+        assert_eq!(
+            relative_url(dir, Path::new("docs/100% done/Über.md")),
+            "../100%25%20done/%C3%9Cber.html"
+        );
     }
 }
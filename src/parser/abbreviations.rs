@@ -0,0 +1,171 @@
+/*
+ * Abbreviations.
+ *
+ * A line such as `*[HTML]: Hyper Text Markup Language` defines an abbreviation, and every time
+ * the abbreviation appears as a whole word in the text of the document it is marked up with its
+ * meaning. Definitions can go anywhere, even after the text that uses them, so the text shares
+ * this table and only splits itself up once the whole document has been parsed.
+ */
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct Abbreviations {
+    // The meaning of each abbreviation. A later definition replaces an earlier one
+    titles: HashMap<String, String>,
+    // The length of the longest abbreviation, which is as far ahead as a match can reach
+    longest: usize,
+}
+
+impl Abbreviations {
+    pub fn new() -> Self {
+        Abbreviations::default()
+    }
+
+    pub fn define(&mut self, abbreviation: &str, title: &str) {
+        self.titles
+            .insert(abbreviation.to_string(), title.to_string());
+        self.longest = self.longest.max(abbreviation.len());
+    }
+
+    /*
+     * Split text into the plain text and the abbreviations in it. Each piece comes with the
+     * meaning of the abbreviation, if it is one
+     */
+    pub fn split(&self, text: &str) -> Vec<(String, Option<String>)> {
+        let mut pieces = vec![];
+        let mut plain = String::new();
+        let mut index = 0;
+        while let Some(c) = text[index..].chars().next() {
+            // Abbreviations are whole words, so only the start of a word can be one
+            let starts_word = !text[..index].chars().next_back().is_some_and(is_word_char);
+            let found = match starts_word {
+                true => self.longest_at(&text[index..]),
+                false => None,
+            };
+            match found {
+                Some((abbreviation, title)) => {
+                    if !plain.is_empty() {
+                        pieces.push((std::mem::take(&mut plain), None));
+                    }
+                    pieces.push((abbreviation.to_string(), Some(title.to_string())));
+                    index += abbreviation.len();
+                }
+                None => {
+                    plain.push(c);
+                    index += c.len_utf8();
+                }
+            }
+        }
+        if !plain.is_empty() {
+            pieces.push((plain, None));
+        }
+        pieces
+    }
+
+    /*
+     * The longest abbreviation at the start of text which ends at the end of a word. Only the
+     * pieces of text ending where a word does are looked up, so it doesn't matter how many
+     * abbreviations there are
+     */
+    fn longest_at(&self, text: &str) -> Option<(&str, &str)> {
+        (1..=self.longest.min(text.len()))
+            .rev()
+            .filter(|&end| {
+                text.is_char_boundary(end) && !text[end..].chars().next().is_some_and(is_word_char)
+            })
+            .find_map(|end| self.titles.get_key_value(&text[..end]))
+            .map(|(abbreviation, title)| (abbreviation.as_str(), title.as_str()))
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/*
+ * The abbreviation and its meaning, if the line is a definition: `*[abbreviation]: meaning`,
+ * indented by up to three spaces
+ */
+pub fn definition(line: &str) -> Option<(&str, &str)> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let rest = trimmed.strip_prefix("*[")?;
+    let end = rest.find("]:")?;
+    let abbreviation = &rest[..end];
+    if abbreviation.trim().is_empty() || abbreviation.contains('[') {
+        return None;
+    }
+    Some((abbreviation, rest[end + 2..].trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn definitions() {
+        assert_eq!(
+            definition("*[HTML]: Hyper Text Markup Language"),
+            Some(("HTML", "Hyper Text Markup Language"))
+        );
+        assert_eq!(
+            definition("   *[W3C]:World Wide Web Consortium "),
+            Some(("W3C", "World Wide Web Consortium"))
+        );
+        assert_eq!(definition("*[TBD]:"), Some(("TBD", "")));
+        assert_eq!(definition("    *[HTML]: indented too far"), None);
+        assert_eq!(definition("*[]: empty"), None);
+        assert_eq!(definition("*[HTML] no colon"), None);
+        assert_eq!(definition("* [HTML]: list item"), None);
+    }
+
+    #[test]
+    fn whole_words() {
+        let mut abbreviations = Abbreviations::new();
+        abbreviations.define("HTML", "Hyper Text Markup Language");
+        abbreviations.define("HTML5", "HTML, version 5");
+        let abbr = |text: &str, title: &str| (text.to_string(), Some(title.to_string()));
+        let plain = |text: &str| (text.to_string(), None);
+        assert_eq!(
+            abbreviations.split("HTML and HTML5, not XHTML or HTMLs."),
+            [
+                abbr("HTML", "Hyper Text Markup Language"),
+                plain(" and "),
+                abbr("HTML5", "HTML, version 5"),
+                plain(", not XHTML or HTMLs.")
+            ]
+        );
+        assert_eq!(
+            abbreviations.split("(HTML)"),
+            [
+                plain("("),
+                abbr("HTML", "Hyper Text Markup Language"),
+                plain(")")
+            ]
+        );
+        assert_eq!(abbreviations.split("none"), [plain("none")]);
+        abbreviations.define("HTML", "HyperText Markup Language");
+        assert_eq!(
+            abbreviations.split("HTML"),
+            [abbr("HTML", "HyperText Markup Language")]
+        );
+    }
+
+    #[test]
+    fn many_definitions() {
+        // Each word is only looked up, not compared with every definition
+        let mut abbreviations = Abbreviations::new();
+        for number in 0..5000 {
+            abbreviations.define(&format!("A{}", number), "a");
+            abbreviations.define(&format!("B{}", number), "b");
+        }
+        let text = "Bx A4999 ".repeat(20_000);
+        let start = std::time::Instant::now();
+        let pieces = abbreviations.split(&text);
+        let elapsed = start.elapsed();
+        assert_eq!(pieces.len(), 40_001);
+        assert!(elapsed.as_secs() < 2, "{:?}", elapsed);
+    }
+}
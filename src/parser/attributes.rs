@@ -0,0 +1,112 @@
+/*
+ * Attribute blocks.
+ *
+ * Pandoc and kramdown let the author give an element attributes in braces: `#id` for its id,
+ * `.name` for a class, and `key=value` for anything else, with the value in quotes if it has
+ * spaces. kramdown's form, with a colon after the opening brace, is read as well. The block goes
+ * at the end of a heading, on the line after any other block, or straight after a code span,
+ * link, image or `[bracketed span]`.
+ */
+use crate::types::elements::{self, Attributes};
+
+/*
+ * The attribute block at the start of text, as in `{#intro .wide data-x="1"}`, along with its
+ * length. A block with nothing in it doesn't count
+ */
+pub fn parse(text: &str) -> Option<(Attributes, usize)> {
+    let rest = text.strip_prefix('{')?;
+    let mut rest = rest.strip_prefix(':').unwrap_or(rest);
+    let mut attributes = Attributes::default();
+    loop {
+        rest = rest.trim_start_matches([' ', '\t']);
+        if let Some(after) = rest.strip_prefix('}') {
+            return match attributes.is_empty() {
+                true => None,
+                false => Some((attributes, text.len() - after.len())),
+            };
+        }
+        if let Some(after) = rest.strip_prefix('#') {
+            let (id, after) = name(after)?;
+            attributes.id = Some(id.to_string());
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('.') {
+            let (class, after) = name(after)?;
+            attributes.classes.push(class.to_string());
+            rest = after;
+        } else {
+            let end = rest.find('=')?;
+            let key = &rest[..end];
+            if !elements::is_attribute_name(key) {
+                return None;
+            }
+            let (value, after) = value(&rest[end + 1..])?;
+            match key {
+                "id" => attributes.id = Some(value.to_string()),
+                "class" => attributes
+                    .classes
+                    .extend(value.split_whitespace().map(String::from)),
+                _ => attributes.pairs.push((key.to_string(), value.to_string())),
+            }
+            rest = after;
+        }
+    }
+}
+
+// An id or class at the start of text, and the text after it
+fn name(text: &str) -> Option<(&str, &str)> {
+    let end = text
+        .find(|c: char| !(c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.')))
+        .unwrap_or(text.len());
+    match end {
+        0 => None,
+        end => Some(text.split_at(end)),
+    }
+}
+
+// A value, in quotes or running up to a space or the closing brace, and the text after it
+fn value(text: &str) -> Option<(&str, &str)> {
+    for quote in ['"', '\''] {
+        if let Some(rest) = text.strip_prefix(quote) {
+            let end = rest.find(quote)?;
+            return Some((&rest[..end], &rest[end + 1..]));
+        }
+    }
+    let end = text.find([' ', '\t', '}'])?;
+    Some(text.split_at(end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attribute_blocks() {
+        let (attributes, length) =
+            parse("{#intro .wide .dark data-x=\"a b\" lang=en} rest").unwrap();
+        assert_eq!(length, 41);
+        assert_eq!(attributes.id.as_deref(), Some("intro"));
+        assert_eq!(attributes.classes, ["wide", "dark"]);
+        assert_eq!(
+            attributes.pairs,
+            [
+                ("data-x".to_string(), "a b".to_string()),
+                ("lang".to_string(), "en".to_string())
+            ]
+        );
+        let (attributes, _) = parse("{: class='a b' id=x}").unwrap();
+        assert_eq!(attributes.classes, ["a", "b"]);
+        assert_eq!(attributes.id.as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn not_attribute_blocks() {
+        assert_eq!(parse("{}"), None);
+        assert_eq!(parse("{ }"), None);
+        assert_eq!(parse("{.a"), None);
+        assert_eq!(parse("{#}"), None);
+        assert_eq!(parse("{a b}"), None);
+        assert_eq!(parse("{1x=2}"), None);
+        assert_eq!(parse("{x=\"open}"), None);
+        assert_eq!(parse("{$x$}"), None);
+    }
+}
@@ -0,0 +1,248 @@
+/*
+ * Autolinks.
+ *
+ * CommonMark turns a URL or email address in angle brackets, such as `<https://example.com>`,
+ * into a link. GitHub goes further and also links the URLs, `www.` addresses and email
+ * addresses it finds in plain text, leaving out any punctuation which ends the sentence rather
+ * than the URL.
+ */
+
+/*
+ * An autolink at the start of text: `<` then an absolute URI or an email address, then `>`.
+ * Returns the destination of the link, its text and the length of the whole autolink
+ */
+pub fn angle_brackets(text: &str) -> Option<(String, String, usize)> {
+    let rest = text.strip_prefix('<')?;
+    let end = rest.find('>')?;
+    let inner = &rest[..end];
+    if is_uri(inner) {
+        return Some((inner.to_string(), inner.to_string(), end + 2));
+    }
+    if is_email(inner) {
+        return Some((format!("mailto:{}", inner), inner.to_string(), end + 2));
+    }
+    None
+}
+
+// A scheme of 2 to 32 characters, a colon, then anything but whitespace and angle brackets
+fn is_uri(text: &str) -> bool {
+    let Some((scheme, _)) = text.split_once(':') else {
+        return false;
+    };
+    let mut chars = scheme.chars();
+    (2..=32).contains(&scheme.len())
+        && chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '.' | '-'))
+        && !text.contains(|c: char| c.is_whitespace() || c.is_control() || c == '<')
+}
+
+// An email address as HTML5 defines it for <input type="email">
+fn is_email(text: &str) -> bool {
+    let Some((local, domain)) = text.split_once('@') else {
+        return false;
+    };
+    let local_char = |c: char| c.is_ascii_alphanumeric() || ".!#$%&'*+/=?^_`{|}~-".contains(c);
+    !local.is_empty()
+        && local.chars().all(local_char)
+        && domain.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/*
+ * The first URL, `www.` address or email address in plain text. Returns where it starts and
+ * ends, and the destination of the link
+ */
+pub fn find(text: &str) -> Option<(usize, usize, String)> {
+    let mut previous = None;
+    for (start, c) in text.char_indices() {
+        let rest = &text[start..];
+        // URLs need to start a word, or follow an opening parenthesis or emphasis
+        let boundary =
+            previous.is_none_or(|p: char| p.is_whitespace() || matches!(p, '*' | '_' | '~' | '('));
+        let link = match boundary {
+            true => www(rest).or_else(|| url(rest)),
+            false => None,
+        };
+        let link = link.or_else(|| match previous.is_some_and(is_email_local) {
+            true => None,
+            false => email(rest),
+        });
+        if let Some((length, destination)) = link {
+            return Some((start, start + length, destination));
+        }
+        previous = Some(c);
+    }
+    None
+}
+
+// `www.` then a domain, linking to it over http
+fn www(text: &str) -> Option<(usize, String)> {
+    if !text.starts_with("www.") {
+        return None;
+    }
+    let domain = domain(text)?;
+    let length = trim_trailing(text, domain + path(&text[domain..]));
+    Some((length, format!("http://{}", &text[..length])))
+}
+
+// `http://` or `https://` then a domain
+fn url(text: &str) -> Option<(usize, String)> {
+    let scheme = ["http://", "https://"]
+        .iter()
+        .find(|scheme| text.starts_with(*scheme))?
+        .len();
+    let domain = scheme + domain(&text[scheme..])?;
+    let length = trim_trailing(text, domain + path(&text[domain..]));
+    Some((length, text[..length].to_string()))
+}
+
+fn is_email_local(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '+')
+}
+
+/*
+ * An email address: letters, numbers and `.-_+` before the `@`, then a domain with at least
+ * one period in it, which can't end in `-` or `_`
+ */
+fn email(text: &str) -> Option<(usize, String)> {
+    let at = text.find(|c: char| !is_email_local(c))?;
+    if at == 0 || !text[at..].starts_with('@') {
+        return None;
+    }
+    let domain = &text[at + 1..];
+    let mut length = domain
+        .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        .unwrap_or(domain.len());
+    // A period at the end finishes the sentence
+    while domain[..length].ends_with('.') {
+        length -= 1;
+    }
+    let domain = &domain[..length];
+    if !domain.contains('.') || domain.ends_with(['-', '_']) {
+        return None;
+    }
+    let length = at + 1 + length;
+    Some((length, format!("mailto:{}", &text[..length])))
+}
+
+/*
+ * The length of the domain at the start of text: segments of letters, numbers, `_` and `-`
+ * separated by periods. The last two segments can't have underscores in them
+ */
+fn domain(text: &str) -> Option<usize> {
+    let length = text
+        .find(|c: char| !(c.is_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        .unwrap_or(text.len());
+    let domain = text[..length].trim_end_matches('.');
+    let segments: Vec<&str> = domain.split('.').collect();
+    if domain.is_empty()
+        || segments.iter().any(|segment| segment.is_empty())
+        || segments
+            .iter()
+            .rev()
+            .take(2)
+            .any(|segment| segment.contains('_'))
+    {
+        return None;
+    }
+    Some(length)
+}
+
+// The path after a domain runs up to whitespace or a `<`
+fn path(text: &str) -> usize {
+    text.find(|c: char| c.is_whitespace() || c == '<')
+        .unwrap_or(text.len())
+}
+
+/*
+ * Leave out the punctuation at the end of a link, which is more likely to belong to the
+ * sentence: closing parentheses that don't match an opening one, and things that look like the
+ * end of an entity such as `&amp;`. Returns the new length
+ */
+fn trim_trailing(text: &str, mut length: usize) -> usize {
+    loop {
+        let link = &text[..length];
+        match link.chars().next_back() {
+            Some('?' | '!' | '.' | ',' | ':' | '*' | '_' | '~' | '\'' | '"') => length -= 1,
+            Some(')') if link.matches(')').count() > link.matches('(').count() => length -= 1,
+            Some(';') => {
+                let name = link[..length - 1].trim_end_matches(|c: char| c.is_ascii_alphanumeric());
+                match name.ends_with('&') && name.len() < length - 1 {
+                    true => length = name.len() - 1,
+                    false => return length,
+                }
+            }
+            _ => return length,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn angle_bracket_autolinks() {
+        let link =
+            |text: &str| angle_brackets(text).map(|(destination, _, length)| (destination, length));
+        assert_eq!(
+            link("<https://example.com/a?b=c> d"),
+            Some(("https://example.com/a?b=c".to_string(), 27))
+        );
+        assert_eq!(
+            link("<irc://foo.bar:2233/baz>"),
+            Some(("irc://foo.bar:2233/baz".to_string(), 24))
+        );
+        assert_eq!(
+            link("<foo@bar.example.com>"),
+            Some(("mailto:foo@bar.example.com".to_string(), 21))
+        );
+        assert_eq!(link("<https://a b>"), None);
+        assert_eq!(link("<m:abc>"), None);
+        assert_eq!(link("<foo.bar.baz>"), None);
+        assert_eq!(link("<foo@bar-.com>"), None);
+    }
+
+    fn linked(text: &str) -> Option<(&str, String)> {
+        find(text).map(|(start, end, destination)| (&text[start..end], destination))
+    }
+
+    #[test]
+    fn extended_autolinks() {
+        assert_eq!(
+            linked("Visit www.commonmark.org/help for more."),
+            Some((
+                "www.commonmark.org/help",
+                "http://www.commonmark.org/help".to_string()
+            ))
+        );
+        assert_eq!(
+            linked("(see https://example.com/a_(b)))"),
+            Some((
+                "https://example.com/a_(b)",
+                "https://example.com/a_(b)".to_string()
+            ))
+        );
+        assert_eq!(
+            linked("www.google.com/search?q=commonmark&hl;"),
+            Some((
+                "www.google.com/search?q=commonmark",
+                "http://www.google.com/search?q=commonmark".to_string()
+            ))
+        );
+        assert_eq!(
+            linked("Hello, foo.bar+baz@example.com."),
+            Some((
+                "foo.bar+baz@example.com",
+                "mailto:foo.bar+baz@example.com".to_string()
+            ))
+        );
+        assert_eq!(linked("www.xxx.yyy._zzz"), None);
+        assert_eq!(linked("xhttps://example.com a@b.c- www"), None);
+        assert_eq!(linked("a.b-c_d@a.b_"), None);
+    }
+}
@@ -0,0 +1,630 @@
+/*
+ * Emoji shortcodes.
+ *
+ * GitHub writes emoji as their names between colons, such as `:rocket:`. The shortcodes known
+ * here are a table of the common ones, kept sorted by name so they can be looked up with a
+ * binary search. A site can add its own shortcodes, which show an image instead.
+ */
+use std::collections::HashMap;
+
+// What a shortcode stands for
+#[derive(Debug, Clone, PartialEq)]
+pub enum Replacement {
+    Character(&'static str),
+    // The URL of a custom emoji
+    Image(String),
+}
+
+/*
+ * The first known shortcode in text. Returns where it starts and ends, and what it stands for.
+ * Custom shortcodes take precedence over the built in ones
+ */
+pub fn find(text: &str, custom: &HashMap<String, String>) -> Option<(usize, usize, Replacement)> {
+    let mut start = text.find(':')?;
+    while let Some(length) = text[start + 1..].find(':') {
+        let end = start + 1 + length;
+        let name = &text[start + 1..end];
+        if is_name(name) {
+            if let Some(source) = custom.get(name) {
+                return Some((start, end + 1, Replacement::Image(source.clone())));
+            }
+            if let Some(character) = lookup(name) {
+                return Some((start, end + 1, Replacement::Character(character)));
+            }
+        }
+        // The closing colon may open the next shortcode
+        start = end;
+    }
+    None
+}
+
+// The emoji for a built in shortcode
+pub fn lookup(name: &str) -> Option<&'static str> {
+    EMOJI
+        .binary_search_by(|(shortcode, _)| shortcode.cmp(&name))
+        .ok()
+        .map(|index| EMOJI[index].1)
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '+' | '-'))
+}
+
+static EMOJI: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("100", "💯"),
+    ("1234", "🔢"),
+    ("8ball", "🎱"),
+    ("a", "🅰️"),
+    ("ab", "🆎"),
+    ("abc", "🔤"),
+    ("airplane", "✈️"),
+    ("alarm_clock", "⏰"),
+    ("alien", "👽"),
+    ("ambulance", "🚑"),
+    ("anchor", "⚓"),
+    ("angel", "👼"),
+    ("anger", "💢"),
+    ("angry", "😠"),
+    ("anguished", "😧"),
+    ("ant", "🐜"),
+    ("apple", "🍎"),
+    ("arrow_down", "⬇️"),
+    ("arrow_left", "⬅️"),
+    ("arrow_right", "➡️"),
+    ("arrow_up", "⬆️"),
+    ("art", "🎨"),
+    ("astonished", "😲"),
+    ("atom_symbol", "⚛️"),
+    ("avocado", "🥑"),
+    ("b", "🅱️"),
+    ("baby", "👶"),
+    ("balloon", "🎈"),
+    ("ballot_box_with_check", "☑️"),
+    ("bamboo", "🎍"),
+    ("banana", "🍌"),
+    ("bangbang", "‼️"),
+    ("bank", "🏦"),
+    ("bar_chart", "📊"),
+    ("baseball", "⚾"),
+    ("basketball", "🏀"),
+    ("bat", "🦇"),
+    ("bear", "🐻"),
+    ("bee", "🐝"),
+    ("beer", "🍺"),
+    ("beers", "🍻"),
+    ("beetle", "🐞"),
+    ("bell", "🔔"),
+    ("bike", "🚲"),
+    ("bird", "🐦"),
+    ("birthday", "🎂"),
+    ("black_circle", "⚫"),
+    ("blue_heart", "💙"),
+    ("blush", "😊"),
+    ("bomb", "💣"),
+    ("book", "📖"),
+    ("bookmark", "🔖"),
+    ("books", "📚"),
+    ("boom", "💥"),
+    ("bouquet", "💐"),
+    ("bow_and_arrow", "🏹"),
+    ("bowtie", "👔"),
+    ("boy", "👦"),
+    ("brain", "🧠"),
+    ("bread", "🍞"),
+    ("briefcase", "💼"),
+    ("broken_heart", "💔"),
+    ("bug", "🐛"),
+    ("bulb", "💡"),
+    ("bus", "🚌"),
+    ("butterfly", "🦋"),
+    ("cactus", "🌵"),
+    ("cake", "🍰"),
+    ("calendar", "📆"),
+    ("camera", "📷"),
+    ("candy", "🍬"),
+    ("car", "🚗"),
+    ("card_index", "📇"),
+    ("cat", "🐱"),
+    ("chart_with_downwards_trend", "📉"),
+    ("chart_with_upwards_trend", "📈"),
+    ("checkered_flag", "🏁"),
+    ("cherries", "🍒"),
+    ("chicken", "🐔"),
+    ("children_crossing", "🚸"),
+    ("christmas_tree", "🎄"),
+    ("clap", "👏"),
+    ("clapper", "🎬"),
+    ("clipboard", "📋"),
+    ("clock1", "🕐"),
+    ("closed_book", "📕"),
+    ("closed_lock_with_key", "🔐"),
+    ("cloud", "☁️"),
+    ("clown_face", "🤡"),
+    ("coffee", "☕"),
+    ("cold_sweat", "😰"),
+    ("collision", "💥"),
+    ("computer", "💻"),
+    ("confetti_ball", "🎊"),
+    ("confounded", "😖"),
+    ("confused", "😕"),
+    ("construction", "🚧"),
+    ("construction_worker", "👷"),
+    ("cookie", "🍪"),
+    ("cool", "🆒"),
+    ("cow", "🐮"),
+    ("crab", "🦀"),
+    ("credit_card", "💳"),
+    ("crown", "👑"),
+    ("cry", "😢"),
+    ("crystal_ball", "🔮"),
+    ("cupid", "💘"),
+    ("dart", "🎯"),
+    ("dash", "💨"),
+    ("date", "📅"),
+    ("deciduous_tree", "🌳"),
+    ("desktop_computer", "🖥️"),
+    ("disappointed", "😞"),
+    ("dizzy", "💫"),
+    ("dna", "🧬"),
+    ("dog", "🐶"),
+    ("dollar", "💵"),
+    ("dolphin", "🐬"),
+    ("door", "🚪"),
+    ("dragon", "🐉"),
+    ("dress", "👗"),
+    ("droplet", "💧"),
+    ("duck", "🦆"),
+    ("e-mail", "📧"),
+    ("ear", "👂"),
+    ("earth_africa", "🌍"),
+    ("earth_americas", "🌎"),
+    ("earth_asia", "🌏"),
+    ("egg", "🥚"),
+    ("eight", "8️⃣"),
+    ("elephant", "🐘"),
+    ("email", "📧"),
+    ("envelope", "✉️"),
+    ("euro", "💶"),
+    ("exclamation", "❗"),
+    ("expressionless", "😑"),
+    ("eyes", "👀"),
+    ("facepalm", "🤦"),
+    ("fast_forward", "⏩"),
+    ("fearful", "😨"),
+    ("file_folder", "📁"),
+    ("fire", "🔥"),
+    ("fire_engine", "🚒"),
+    ("fireworks", "🎆"),
+    ("fish", "🐟"),
+    ("fist", "✊"),
+    ("five", "5️⃣"),
+    ("flags", "🎏"),
+    ("flashlight", "🔦"),
+    ("floppy_disk", "💾"),
+    ("flushed", "😳"),
+    ("fog", "🌫️"),
+    ("football", "🏈"),
+    ("footprints", "👣"),
+    ("fork_and_knife", "🍴"),
+    ("four", "4️⃣"),
+    ("four_leaf_clover", "🍀"),
+    ("fox_face", "🦊"),
+    ("fries", "🍟"),
+    ("frog", "🐸"),
+    ("frowning", "😦"),
+    ("fuelpump", "⛽"),
+    ("full_moon", "🌕"),
+    ("game_die", "🎲"),
+    ("gear", "⚙️"),
+    ("gem", "💎"),
+    ("ghost", "👻"),
+    ("gift", "🎁"),
+    ("girl", "👧"),
+    ("globe_with_meridians", "🌐"),
+    ("goat", "🐐"),
+    ("golf", "⛳"),
+    ("grapes", "🍇"),
+    ("green_apple", "🍏"),
+    ("green_book", "📗"),
+    ("green_heart", "💚"),
+    ("grey_exclamation", "❕"),
+    ("grey_question", "❔"),
+    ("grimacing", "😬"),
+    ("grin", "😁"),
+    ("grinning", "😀"),
+    ("guitar", "🎸"),
+    ("hamburger", "🍔"),
+    ("hammer", "🔨"),
+    ("hammer_and_wrench", "🛠️"),
+    ("hand", "✋"),
+    ("handshake", "🤝"),
+    ("hankey", "💩"),
+    ("hatching_chick", "🐣"),
+    ("headphones", "🎧"),
+    ("heart", "❤️"),
+    ("heart_eyes", "😍"),
+    ("heartbeat", "💓"),
+    ("heavy_check_mark", "✔️"),
+    ("heavy_minus_sign", "➖"),
+    ("heavy_multiplication_x", "✖️"),
+    ("heavy_plus_sign", "➕"),
+    ("hedgehog", "🦔"),
+    ("hibiscus", "🌺"),
+    ("high_brightness", "🔆"),
+    ("hocho", "🔪"),
+    ("honey_pot", "🍯"),
+    ("horse", "🐴"),
+    ("hospital", "🏥"),
+    ("hotel", "🏨"),
+    ("hourglass", "⌛"),
+    ("house", "🏠"),
+    ("hugs", "🤗"),
+    ("hushed", "😯"),
+    ("ice_cream", "🍨"),
+    ("icecream", "🍦"),
+    ("id", "🆔"),
+    ("imp", "👿"),
+    ("inbox_tray", "📥"),
+    ("incoming_envelope", "📨"),
+    ("information_source", "ℹ️"),
+    ("innocent", "😇"),
+    ("iphone", "📱"),
+    ("jack_o_lantern", "🎃"),
+    ("jeans", "👖"),
+    ("joy", "😂"),
+    ("key", "🔑"),
+    ("keyboard", "⌨️"),
+    ("kiss", "💋"),
+    ("kissing", "😗"),
+    ("kissing_heart", "😘"),
+    ("kiwi_fruit", "🥝"),
+    ("koala", "🐨"),
+    ("label", "🏷️"),
+    ("ladybug", "🐞"),
+    ("laptop", "💻"),
+    ("large_blue_circle", "🔵"),
+    ("laughing", "😆"),
+    ("leaves", "🍃"),
+    ("ledger", "📒"),
+    ("lemon", "🍋"),
+    ("leopard", "🐆"),
+    ("link", "🔗"),
+    ("lion", "🦁"),
+    ("lips", "👄"),
+    ("lipstick", "💄"),
+    ("lock", "🔒"),
+    ("lollipop", "🍭"),
+    ("loudspeaker", "📢"),
+    ("love_letter", "💌"),
+    ("mag", "🔍"),
+    ("mag_right", "🔎"),
+    ("mailbox", "📫"),
+    ("man", "👨"),
+    ("map", "🗺️"),
+    ("maple_leaf", "🍁"),
+    ("mask", "😷"),
+    ("massage", "💆"),
+    ("meat_on_bone", "🍖"),
+    ("mega", "📣"),
+    ("memo", "📝"),
+    ("metal", "🤘"),
+    ("microphone", "🎤"),
+    ("microscope", "🔬"),
+    ("milky_way", "🌌"),
+    ("money_with_wings", "💸"),
+    ("moneybag", "💰"),
+    ("monkey", "🐒"),
+    ("monkey_face", "🐵"),
+    ("moon", "🌔"),
+    ("mortar_board", "🎓"),
+    ("mosquito", "🦟"),
+    ("motorcycle", "🏍️"),
+    ("mountain", "⛰️"),
+    ("mouse", "🐭"),
+    ("movie_camera", "🎥"),
+    ("muscle", "💪"),
+    ("mushroom", "🍄"),
+    ("musical_note", "🎵"),
+    ("nail_care", "💅"),
+    ("necktie", "👔"),
+    ("negative_squared_cross_mark", "❎"),
+    ("nerd_face", "🤓"),
+    ("neutral_face", "😐"),
+    ("new", "🆕"),
+    ("new_moon", "🌑"),
+    ("newspaper", "📰"),
+    ("nine", "9️⃣"),
+    ("no_entry", "⛔"),
+    ("no_entry_sign", "🚫"),
+    ("no_good", "🙅"),
+    ("no_mouth", "😶"),
+    ("nose", "👃"),
+    ("notebook", "📓"),
+    ("notes", "🎶"),
+    ("nut_and_bolt", "🔩"),
+    ("o", "⭕"),
+    ("ocean", "🌊"),
+    ("octopus", "🐙"),
+    ("ok", "🆗"),
+    ("ok_hand", "👌"),
+    ("ok_woman", "🙆"),
+    ("older_man", "👴"),
+    ("one", "1️⃣"),
+    ("onion", "🧅"),
+    ("open_book", "📖"),
+    ("open_file_folder", "📂"),
+    ("open_hands", "👐"),
+    ("open_mouth", "😮"),
+    ("orange", "🍊"),
+    ("orange_book", "📙"),
+    ("owl", "🦉"),
+    ("ox", "🐂"),
+    ("package", "📦"),
+    ("page_facing_up", "📄"),
+    ("page_with_curl", "📃"),
+    ("pager", "📟"),
+    ("paintbrush", "🖌️"),
+    ("palm_tree", "🌴"),
+    ("pancakes", "🥞"),
+    ("panda_face", "🐼"),
+    ("paperclip", "📎"),
+    ("parrot", "🦜"),
+    ("partying_face", "🥳"),
+    ("passport_control", "🛂"),
+    ("peach", "🍑"),
+    ("peanuts", "🥜"),
+    ("pear", "🍐"),
+    ("pen", "🖊️"),
+    ("pencil", "📝"),
+    ("pencil2", "✏️"),
+    ("penguin", "🐧"),
+    ("pensive", "😔"),
+    ("performing_arts", "🎭"),
+    ("persevere", "😣"),
+    ("phone", "☎️"),
+    ("pig", "🐷"),
+    ("pill", "💊"),
+    ("pineapple", "🍍"),
+    ("pizza", "🍕"),
+    ("point_down", "👇"),
+    ("point_left", "👈"),
+    ("point_right", "👉"),
+    ("point_up", "☝️"),
+    ("point_up_2", "👆"),
+    ("police_car", "🚓"),
+    ("poodle", "🐩"),
+    ("poop", "💩"),
+    ("popcorn", "🍿"),
+    ("postbox", "📮"),
+    ("potato", "🥔"),
+    ("pouting_cat", "😾"),
+    ("pray", "🙏"),
+    ("pushpin", "📌"),
+    ("puzzle_piece", "🧩"),
+    ("question", "❓"),
+    ("rabbit", "🐰"),
+    ("racehorse", "🐎"),
+    ("radio", "📻"),
+    ("rage", "😡"),
+    ("rainbow", "🌈"),
+    ("raised_hand", "✋"),
+    ("raised_hands", "🙌"),
+    ("raising_hand", "🙋"),
+    ("ram", "🐏"),
+    ("recycle", "♻️"),
+    ("red_circle", "🔴"),
+    ("relaxed", "☺️"),
+    ("relieved", "😌"),
+    ("repeat", "🔁"),
+    ("rewind", "⏪"),
+    ("ribbon", "🎀"),
+    ("rice", "🍚"),
+    ("ring", "💍"),
+    ("robot", "🤖"),
+    ("rocket", "🚀"),
+    ("rofl", "🤣"),
+    ("roll_eyes", "🙄"),
+    ("rooster", "🐓"),
+    ("rose", "🌹"),
+    ("rotating_light", "🚨"),
+    ("round_pushpin", "📍"),
+    ("running", "🏃"),
+    ("sake", "🍶"),
+    ("sandwich", "🥪"),
+    ("santa", "🎅"),
+    ("satellite", "📡"),
+    ("satisfied", "😆"),
+    ("saxophone", "🎷"),
+    ("school", "🏫"),
+    ("scissors", "✂️"),
+    ("scream", "😱"),
+    ("scroll", "📜"),
+    ("see_no_evil", "🙈"),
+    ("seedling", "🌱"),
+    ("seven", "7️⃣"),
+    ("shamrock", "☘️"),
+    ("shark", "🦈"),
+    ("sheep", "🐑"),
+    ("shell", "🐚"),
+    ("shield", "🛡️"),
+    ("ship", "🚢"),
+    ("shirt", "👕"),
+    ("shrug", "🤷"),
+    ("six", "6️⃣"),
+    ("skull", "💀"),
+    ("sleeping", "😴"),
+    ("sleepy", "😪"),
+    ("slightly_frowning_face", "🙁"),
+    ("slightly_smiling_face", "🙂"),
+    ("smile", "😄"),
+    ("smile_cat", "😸"),
+    ("smiley", "😃"),
+    ("smiling_imp", "😈"),
+    ("smirk", "😏"),
+    ("snail", "🐌"),
+    ("snake", "🐍"),
+    ("sneezing_face", "🤧"),
+    ("snowflake", "❄️"),
+    ("snowman", "⛄"),
+    ("sob", "😭"),
+    ("soccer", "⚽"),
+    ("sos", "🆘"),
+    ("sparkles", "✨"),
+    ("sparkling_heart", "💖"),
+    ("speak_no_evil", "🙊"),
+    ("speech_balloon", "💬"),
+    ("spider", "🕷️"),
+    ("spiral_notepad", "🗒️"),
+    ("squid", "🦑"),
+    ("star", "⭐"),
+    ("star2", "🌟"),
+    ("star_struck", "🤩"),
+    ("stars", "🌠"),
+    ("stop_sign", "🛑"),
+    ("stopwatch", "⏱️"),
+    ("strawberry", "🍓"),
+    ("stuck_out_tongue", "😛"),
+    ("stuck_out_tongue_winking_eye", "😜"),
+    ("sun_with_face", "🌞"),
+    ("sunflower", "🌻"),
+    ("sunglasses", "😎"),
+    ("sunny", "☀️"),
+    ("sunrise", "🌅"),
+    ("sushi", "🍣"),
+    ("sweat", "😓"),
+    ("sweat_drops", "💦"),
+    ("sweat_smile", "😅"),
+    ("taco", "🌮"),
+    ("tada", "🎉"),
+    ("tangerine", "🍊"),
+    ("taxi", "🚕"),
+    ("tea", "🍵"),
+    ("telephone", "☎️"),
+    ("telescope", "🔭"),
+    ("tennis", "🎾"),
+    ("tent", "⛺"),
+    ("thinking", "🤔"),
+    ("thought_balloon", "💭"),
+    ("three", "3️⃣"),
+    ("thumbsdown", "👎"),
+    ("thumbsup", "👍"),
+    ("ticket", "🎫"),
+    ("tiger", "🐯"),
+    ("timer_clock", "⏲️"),
+    ("tired_face", "😫"),
+    ("toilet", "🚽"),
+    ("tomato", "🍅"),
+    ("tongue", "👅"),
+    ("toolbox", "🧰"),
+    ("tophat", "🎩"),
+    ("tractor", "🚜"),
+    ("traffic_light", "🚥"),
+    ("train", "🚋"),
+    ("tram", "🚊"),
+    ("triangular_flag_on_post", "🚩"),
+    ("triangular_ruler", "📐"),
+    ("trident", "🔱"),
+    ("triumph", "😤"),
+    ("trolleybus", "🚎"),
+    ("trophy", "🏆"),
+    ("truck", "🚚"),
+    ("trumpet", "🎺"),
+    ("tulip", "🌷"),
+    ("turkey", "🦃"),
+    ("turtle", "🐢"),
+    ("tv", "📺"),
+    ("two", "2️⃣"),
+    ("two_hearts", "💕"),
+    ("umbrella", "☔"),
+    ("unamused", "😒"),
+    ("unicorn", "🦄"),
+    ("unlock", "🔓"),
+    ("up", "🆙"),
+    ("upside_down_face", "🙃"),
+    ("v", "✌️"),
+    ("vertical_traffic_light", "🚦"),
+    ("video_camera", "📹"),
+    ("video_game", "🎮"),
+    ("violin", "🎻"),
+    ("volcano", "🌋"),
+    ("vs", "🆚"),
+    ("walking", "🚶"),
+    ("warning", "⚠️"),
+    ("watch", "⌚"),
+    ("watermelon", "🍉"),
+    ("wave", "👋"),
+    ("wavy_dash", "〰️"),
+    ("weary", "😩"),
+    ("whale", "🐳"),
+    ("wheelchair", "♿"),
+    ("white_check_mark", "✅"),
+    ("white_circle", "⚪"),
+    ("white_flower", "💮"),
+    ("wilted_flower", "🥀"),
+    ("wind_chime", "🎐"),
+    ("wine_glass", "🍷"),
+    ("wink", "😉"),
+    ("wolf", "🐺"),
+    ("woman", "👩"),
+    ("world_map", "🗺️"),
+    ("worried", "😟"),
+    ("wrench", "🔧"),
+    ("writing_hand", "✍️"),
+    ("x", "❌"),
+    ("yellow_heart", "💛"),
+    ("yen", "💴"),
+    ("yum", "😋"),
+    ("zany_face", "🤪"),
+    ("zap", "⚡"),
+    ("zero", "0️⃣"),
+    ("zipper_mouth_face", "🤐"),
+    ("zzz", "💤"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_is_sorted() {
+        assert!(EMOJI.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn shortcodes() {
+        let custom = HashMap::new();
+        assert_eq!(lookup("rocket"), Some("🚀"));
+        assert_eq!(lookup("+1"), Some("👍"));
+        assert_eq!(lookup("no_such_emoji"), None);
+        assert_eq!(
+            find("Ship it :rocket:!", &custom),
+            Some((8, 16, Replacement::Character("🚀")))
+        );
+        // Unknown names and times are left alone
+        assert_eq!(find("at 10:30 :nope: :Rocket:", &custom), None);
+        assert_eq!(
+            find("10:30:tada:", &custom),
+            Some((5, 11, Replacement::Character("🎉")))
+        );
+    }
+
+    #[test]
+    fn custom_shortcodes() {
+        let mut custom = HashMap::new();
+        custom.insert("party_parrot".to_string(), "/emoji/parrot.gif".to_string());
+        custom.insert("rocket".to_string(), "/emoji/rocket.png".to_string());
+        assert_eq!(
+            find(":party_parrot:", &custom),
+            Some((0, 14, Replacement::Image("/emoji/parrot.gif".to_string())))
+        );
+        assert_eq!(
+            find(":rocket:", &custom),
+            Some((0, 8, Replacement::Image("/emoji/rocket.png".to_string())))
+        );
+    }
+}
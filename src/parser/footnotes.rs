@@ -0,0 +1,188 @@
+/*
+ * Footnotes.
+ *
+ * References such as `[^1]` are numbered in the order they are first used, whatever their labels
+ * and wherever their definitions are. Since a reference may come before its definition, the
+ * numbers are only known once the whole document has been parsed, so references and the
+ * section at the end share this table and look themselves up in it when they are rendered.
+ */
+use std::collections::HashMap;
+
+use super::lexer::Span;
+use super::parser::Node;
+use crate::types::elements;
+
+// The blocks making up a footnote, from a `[^label]: ...` definition
+pub struct Definition {
+    pub label: String,
+    pub children: Node,
+    pub span: Span,
+}
+
+#[derive(Default)]
+pub struct Footnotes {
+    // The labels referenced, in the order they were first used, with where that was
+    references: Vec<(String, Span)>,
+    // How many times each label has been referenced
+    counts: HashMap<String, usize>,
+    definitions: Vec<Definition>,
+}
+
+// Labels match whatever their case
+pub fn normalize(label: &str) -> String {
+    label.to_lowercase()
+}
+
+impl Footnotes {
+    pub fn new() -> Self {
+        Footnotes::default()
+    }
+
+    // Record a reference to a footnote, returning how many times it has been referenced so far
+    pub fn reference(&mut self, label: &str, span: Span) -> usize {
+        let label = normalize(label);
+        let count = self.counts.entry(label.clone()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            self.references.push((label, span));
+        }
+        *count
+    }
+
+    // The first definition of a label is the one that counts
+    pub fn define(&mut self, mut definition: Definition) {
+        definition.label = normalize(&definition.label);
+        self.definitions.push(definition);
+    }
+
+    fn definition(&self, label: &str) -> Option<&Definition> {
+        self.definitions
+            .iter()
+            .find(|definition| definition.label == label)
+    }
+
+    // The number of a footnote, if it is both referenced and defined
+    pub fn number(&self, label: &str) -> Option<usize> {
+        let label = normalize(label);
+        self.references
+            .iter()
+            .filter(|(label, _)| self.definition(label).is_some())
+            .position(|(referenced, _)| *referenced == label)
+            .map(|index| index + 1)
+    }
+
+    // The footnotes which are both referenced and defined, in order, ready for the end of the page
+    pub fn section(&self) -> Option<elements::Footnotes> {
+        let notes: Vec<elements::Footnote> = self
+            .references
+            .iter()
+            .filter_map(|(label, _)| self.definition(label).map(|definition| (label, definition)))
+            .enumerate()
+            .map(|(index, (label, definition))| elements::Footnote {
+                number: index + 1,
+                references: self.counts[label],
+                children: definition
+                    .children
+                    .children
+                    .iter()
+                    .map(|child| child.item.convert_to_renderable())
+                    .collect(),
+            })
+            .collect();
+        if notes.is_empty() {
+            return None;
+        }
+        Some(elements::Footnotes { notes })
+    }
+
+    // References to footnotes which are never defined, and definitions which are never used
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        for (label, span) in &self.references {
+            if self.definition(label).is_none() {
+                warnings.push(format!(
+                    "line {}: footnote [^{}] is never defined",
+                    span.line + 1,
+                    label
+                ));
+            }
+        }
+        for definition in &self.definitions {
+            if !self.counts.contains_key(&definition.label) {
+                warnings.push(format!(
+                    "line {}: footnote [^{}] is never used",
+                    definition.span.line + 1,
+                    definition.label
+                ));
+            }
+        }
+        warnings
+    }
+}
+
+/*
+ * The label of a footnote reference or definition at the start of text, as in `[^label]`, along
+ * with the length of the whole reference
+ */
+pub fn label(text: &str) -> Option<(&str, usize)> {
+    let rest = text.strip_prefix("[^")?;
+    let end = rest.find(']')?;
+    let label = &rest[..end];
+    if label.is_empty() || label.contains(|c: char| c.is_whitespace() || c == '[') {
+        return None;
+    }
+    Some((label, end + 3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels() {
+        assert_eq!(label("[^1] text"), Some(("1", 4)));
+        assert_eq!(label("[^note]: text"), Some(("note", 7)));
+        assert_eq!(label("[^]"), None);
+        assert_eq!(label("[^a b]"), None);
+        assert_eq!(label("[^a"), None);
+        assert_eq!(label("[a]"), None);
+    }
+
+    fn definition(label: &str, line: u32) -> Definition {
+        Definition {
+            label: label.to_string(),
+            children: Node { children: vec![] },
+            span: Span {
+                line,
+                ..Span::default()
+            },
+        }
+    }
+
+    #[test]
+    fn numbering() {
+        let mut footnotes = Footnotes::new();
+        footnotes.define(definition("b", 9));
+        footnotes.define(definition("a", 10));
+        footnotes.define(definition("unused", 11));
+        let at = |line| Span {
+            line,
+            ..Span::default()
+        };
+        assert_eq!(footnotes.reference("A", at(0)), 1);
+        assert_eq!(footnotes.reference("missing", at(1)), 1);
+        assert_eq!(footnotes.reference("b", at(2)), 1);
+        assert_eq!(footnotes.reference("a", at(3)), 2);
+        assert_eq!(footnotes.number("a"), Some(1));
+        assert_eq!(footnotes.number("B"), Some(2));
+        assert_eq!(footnotes.number("missing"), None);
+        assert_eq!(footnotes.number("unused"), None);
+        assert_eq!(
+            footnotes.warnings(),
+            [
+                "line 2: footnote [^missing] is never defined",
+                "line 12: footnote [^unused] is never used"
+            ]
+        );
+    }
+}
@@ -0,0 +1,112 @@
+/*
+ * Front matter.
+ *
+ * A page can start with settings for the site generator, such as its title, as `key: value`
+ * lines between two `---` lines, the way Jekyll and Hugo have it. Only flat string values are
+ * read; nested values and the items of a YAML list are skipped. A block with any other line
+ * that isn't `key: value` is not front matter, such as a thematic break above a paragraph.
+ */
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FrontMatter {
+    // The settings, in the order they are written
+    pub fields: Vec<(String, String)>,
+}
+
+impl FrontMatter {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.get("title").filter(|title| !title.is_empty())
+    }
+}
+
+/*
+ * The front matter at the start of source, along with its length in bytes up to the start of
+ * the first line after it. The block is closed by a `---` or `...` line
+ */
+pub fn parse(source: &str) -> Option<(FrontMatter, usize)> {
+    let mut lines = source.split_inclusive('\n');
+    let first = lines.next()?;
+    if first.trim_end() != "---" {
+        return None;
+    }
+    let mut front_matter = FrontMatter::default();
+    let mut length = first.len();
+    // Whether the last key had no value on its line, so a list or nested values may follow
+    let mut open = false;
+    for line in lines {
+        length += line.len();
+        let line = line.trim_end();
+        if line == "---" || line == "..." {
+            return Some((front_matter, length));
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // Nested values and list items belong to the key before them
+        if line.starts_with([' ', '\t']) || line.starts_with("- ") || line == "-" {
+            if !open {
+                return None;
+            }
+            continue;
+        }
+        // Anything else must be a key: value line, or this is a thematic break and not front matter
+        let (key, value) = line.split_once(':')?;
+        let key = key.trim();
+        if key.is_empty() || !(value.is_empty() || value.starts_with([' ', '\t'])) {
+            return None;
+        }
+        let value = unquote(value.trim());
+        open = value.is_empty();
+        front_matter
+            .fields
+            .push((key.to_string(), value.to_string()));
+    }
+    None
+}
+
+// A value without the quotes around it
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|rest| rest.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn front_matter() {
+        let source = "---\ntitle: \"Getting started\"\ntags:\n  - intro\ndraft: no\n---\n# Hi\n";
+        let (front_matter, length) = parse(source).unwrap();
+        assert_eq!(&source[length..], "# Hi\n");
+        assert_eq!(front_matter.title(), Some("Getting started"));
+        assert_eq!(front_matter.get("draft"), Some("no"));
+        assert_eq!(front_matter.get("tags"), Some(""));
+        assert_eq!(front_matter.get("  - intro"), None);
+        assert_eq!(parse("---\ntitle: a\n...").unwrap().1, 16);
+    }
+
+    #[test]
+    fn not_front_matter() {
+        assert_eq!(parse("# ---\ntitle: a\n---\n"), None);
+        assert_eq!(parse("---\ntitle: never closed\n"), None);
+        assert_eq!(parse(" ---\ntitle: a\n---\n"), None);
+        assert_eq!(parse("---\nSome intro paragraph\n\n---\nMore text\n"), None);
+        assert_eq!(parse("---\n  - a\n---\n"), None);
+        assert_eq!(parse("---\ntitle:a\n---\n"), None);
+    }
+}
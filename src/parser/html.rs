@@ -0,0 +1,416 @@
+/*
+ * Raw HTML.
+ *
+ * Recognise the HTML that CommonMark lets through untouched: tags, comments and the like inside
+ * a line of text, and the seven kinds of HTML block. Everything here works on source text, since
+ * the Lexer knows nothing about HTML.
+ */
+
+// Tags which start an HTML block of kind 6, ended by a blank line
+const BLOCK_TAGS: [&str; 62] = [
+    "address",
+    "article",
+    "aside",
+    "base",
+    "basefont",
+    "blockquote",
+    "body",
+    "caption",
+    "center",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "dialog",
+    "dir",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "frame",
+    "frameset",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hr",
+    "html",
+    "iframe",
+    "legend",
+    "li",
+    "link",
+    "main",
+    "menu",
+    "menuitem",
+    "nav",
+    "noframes",
+    "ol",
+    "optgroup",
+    "option",
+    "p",
+    "param",
+    "search",
+    "section",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "track",
+    "ul",
+];
+
+// Tags whose content is not HTML, which start an HTML block of kind 1
+const RAW_TEXT_TAGS: [&str; 4] = ["pre", "script", "style", "textarea"];
+
+// An opening or closing tag
+#[derive(Debug, PartialEq)]
+pub struct Tag<'a> {
+    pub name: &'a str,
+    pub closing: bool,
+    // Attribute names and their values, without quotes
+    pub attributes: Vec<(&'a str, Option<&'a str>)>,
+    // Ends in />
+    pub self_closing: bool,
+    // The length of the tag in bytes
+    pub length: usize,
+}
+
+// The length of the ASCII whitespace at the start of text
+fn whitespace(text: &str) -> usize {
+    text.len()
+        - text
+            .trim_start_matches(|c: char| c.is_ascii_whitespace())
+            .len()
+}
+
+// A tag name: an ASCII letter, then letters, digits and hyphens
+fn tag_name(text: &str) -> Option<&str> {
+    if !text.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let length = text
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+        .unwrap_or(text.len());
+    Some(&text[..length])
+}
+
+// An attribute name: a letter, `_` or `:`, then letters, digits, `_`, `.`, `:` and `-`
+fn attribute_name(text: &str) -> Option<&str> {
+    if !text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == ':') {
+        return None;
+    }
+    let length = text
+        .find(|c: char| !c.is_ascii_alphanumeric() && !matches!(c, '_' | '.' | ':' | '-'))
+        .unwrap_or(text.len());
+    Some(&text[..length])
+}
+
+/*
+ * An attribute value, quoted with ' or " or not quoted at all. Returns the value without its
+ * quotes, and the length it takes up in text
+ */
+fn attribute_value(text: &str) -> Option<(&str, usize)> {
+    match text.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let end = text[1..].find(quote)?;
+            Some((&text[1..end + 1], end + 2))
+        }
+        _ => {
+            let length = text
+                .find(|c: char| c.is_ascii_whitespace() || "\"'=<>`".contains(c))
+                .unwrap_or(text.len());
+            match length {
+                0 => None,
+                _ => Some((&text[..length], length)),
+            }
+        }
+    }
+}
+
+/*
+ * The opening or closing tag at the start of text, e.g. `<a href="/">` or `</a>`. Tags may run
+ * over several lines
+ */
+pub fn tag(text: &str) -> Option<Tag<'_>> {
+    let rest = text.strip_prefix('<')?;
+    if let Some(rest) = rest.strip_prefix('/') {
+        let name = tag_name(rest)?;
+        let after = &rest[name.len()..];
+        let space = whitespace(after);
+        if !after[space..].starts_with('>') {
+            return None;
+        }
+        return Some(Tag {
+            name,
+            closing: true,
+            attributes: vec![],
+            self_closing: false,
+            length: 2 + name.len() + space + 1,
+        });
+    }
+    let name = tag_name(rest)?;
+    let mut position = 1 + name.len();
+    let mut attributes = vec![];
+    loop {
+        let space = whitespace(&text[position..]);
+        let after = &text[position + space..];
+        if after.starts_with('>') {
+            position += space + 1;
+            break;
+        }
+        if after.starts_with("/>") {
+            return Some(Tag {
+                name,
+                closing: false,
+                attributes,
+                self_closing: true,
+                length: position + space + 2,
+            });
+        }
+        // Attributes have to be separated from what comes before them
+        let attribute = attribute_name(after).filter(|_| space > 0)?;
+        position += space + attribute.len();
+        // An optional value, with optional whitespace around the =
+        let before_equals = whitespace(&text[position..]);
+        let mut value = None;
+        if text[position + before_equals..].starts_with('=') {
+            let start = position + before_equals + 1;
+            let after_equals = whitespace(&text[start..]);
+            let (found, length) = attribute_value(&text[start + after_equals..])?;
+            value = Some(found);
+            position = start + after_equals + length;
+        }
+        attributes.push((attribute, value));
+    }
+    Some(Tag {
+        name,
+        closing: false,
+        attributes,
+        self_closing: false,
+        length: position,
+    })
+}
+
+/*
+ * The length of anything other than a tag which may appear in a line of HTML: a comment,
+ * processing instruction, declaration or CDATA section
+ */
+pub fn special(text: &str) -> Option<usize> {
+    if let Some(rest) = text.strip_prefix("<!--") {
+        // <!--> and <!---> are complete (if empty) comments
+        for empty in [">", "->"] {
+            if rest.starts_with(empty) {
+                return Some(4 + empty.len());
+            }
+        }
+        return rest.find("-->").map(|end| 4 + end + 3);
+    }
+    if let Some(rest) = text.strip_prefix("<?") {
+        return rest.find("?>").map(|end| 2 + end + 2);
+    }
+    if let Some(rest) = text.strip_prefix("<![CDATA[") {
+        return rest.find("]]>").map(|end| 9 + end + 3);
+    }
+    if let Some(rest) = text.strip_prefix("<!") {
+        if rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return rest.find('>').map(|end| 2 + end + 1);
+        }
+    }
+    None
+}
+
+// The length of the raw HTML at the start of text, if there is any
+pub fn inline(text: &str) -> Option<usize> {
+    tag(text).map(|tag| tag.length).or_else(|| special(text))
+}
+
+/*
+ * The seven kinds of HTML block. They differ in how they start and how they end: the first five
+ * end at a line containing a particular string, the last two at a blank line
+ */
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BlockKind {
+    // <pre>, <script>, <style> or <textarea>
+    RawText,
+    Comment,
+    ProcessingInstruction,
+    Declaration,
+    CData,
+    // One of BLOCK_TAGS
+    Block,
+    // Any other complete tag, alone on its line
+    Other,
+}
+
+impl BlockKind {
+    /*
+     * The kind of HTML block the line starts, if any. Only the last kind can't interrupt a
+     * paragraph
+     */
+    pub fn start(line: &str, after_paragraph: bool) -> Option<BlockKind> {
+        let text = line.trim_start_matches(' ');
+        if line.len() - text.len() > 3 || !text.starts_with('<') {
+            return None;
+        }
+        let lower = text.to_ascii_lowercase();
+        let ends_name = |rest: &str| {
+            rest.is_empty()
+                || rest.starts_with(|c: char| c.is_ascii_whitespace() || c == '>')
+                || rest.starts_with("/>")
+        };
+        if RAW_TEXT_TAGS.iter().any(|name| {
+            lower[1..]
+                .strip_prefix(name)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with([' ', '\t', '>']))
+        }) {
+            return Some(BlockKind::RawText);
+        }
+        if text.starts_with("<!--") {
+            return Some(BlockKind::Comment);
+        }
+        if text.starts_with("<?") {
+            return Some(BlockKind::ProcessingInstruction);
+        }
+        if text.starts_with("<![CDATA[") {
+            return Some(BlockKind::CData);
+        }
+        if text
+            .strip_prefix("<!")
+            .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_alphabetic()))
+        {
+            return Some(BlockKind::Declaration);
+        }
+        let name_start = if lower.starts_with("</") { 2 } else { 1 };
+        if let Some(name) = tag_name(&lower[name_start..]) {
+            if BLOCK_TAGS.contains(&name) && ends_name(&lower[name_start + name.len()..]) {
+                return Some(BlockKind::Block);
+            }
+        }
+        if !after_paragraph {
+            if let Some(tag) = tag(text) {
+                let alone = text[tag.length..].trim().is_empty();
+                if alone && !RAW_TEXT_TAGS.contains(&tag.name.to_ascii_lowercase().as_str()) {
+                    return Some(BlockKind::Other);
+                }
+            }
+        }
+        None
+    }
+
+    // Does the line end a block of this kind? Blocks ended by a blank line never end on a line
+    pub fn ends(&self, line: &str) -> bool {
+        let lower = line.to_ascii_lowercase();
+        match self {
+            BlockKind::RawText => RAW_TEXT_TAGS
+                .iter()
+                .any(|name| lower.contains(&format!("</{}>", name))),
+            BlockKind::Comment => line.contains("-->"),
+            BlockKind::ProcessingInstruction => line.contains("?>"),
+            BlockKind::Declaration => line.contains('>'),
+            BlockKind::CData => line.contains("]]>"),
+            BlockKind::Block | BlockKind::Other => false,
+        }
+    }
+
+    // Is the block ended by a blank line, rather than by a line with a particular string in it?
+    pub fn ends_at_blank_line(&self) -> bool {
+        matches!(self, BlockKind::Block | BlockKind::Other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags() {
+        let parsed = tag("<a href=\"/x\" title='y' hidden data-n=1>text").unwrap();
+        assert_eq!(parsed.name, "a");
+        assert_eq!(
+            parsed.attributes,
+            [
+                ("href", Some("/x")),
+                ("title", Some("y")),
+                ("hidden", None),
+                ("data-n", Some("1"))
+            ]
+        );
+        assert_eq!(parsed.length, 39);
+        assert!(tag("<br/>").unwrap().self_closing);
+        assert_eq!(tag("</div >").unwrap().length, 7);
+        assert_eq!(tag("<a\n  b='c'>").unwrap().length, 11);
+        for invalid in [
+            "<33>",
+            "<__>",
+            "<a h*#ref=\"hi\">",
+            "<a href='bar'title=title>",
+            "</a href>",
+            "< a>",
+            "<a",
+        ] {
+            assert_eq!(tag(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn specials() {
+        assert_eq!(inline("<!-- a comment -->x"), Some(18));
+        assert_eq!(inline("<!-->x"), Some(5));
+        assert_eq!(inline("<?php echo $a; ?>x"), Some(17));
+        assert_eq!(inline("<!ELEMENT br EMPTY>x"), Some(19));
+        assert_eq!(inline("<![CDATA[>&<]]>x"), Some(15));
+        assert_eq!(inline("<!-- unclosed"), None);
+        assert_eq!(inline("<http://example.com>"), None);
+    }
+
+    #[test]
+    fn block_starts() {
+        assert_eq!(
+            BlockKind::start("<script>", false),
+            Some(BlockKind::RawText)
+        );
+        assert_eq!(
+            BlockKind::start("<PRE class=\"x\">", false),
+            Some(BlockKind::RawText)
+        );
+        assert_eq!(BlockKind::start("<!-- x", false), Some(BlockKind::Comment));
+        assert_eq!(
+            BlockKind::start("<?x", false),
+            Some(BlockKind::ProcessingInstruction)
+        );
+        assert_eq!(
+            BlockKind::start("<!DOCTYPE html>", false),
+            Some(BlockKind::Declaration)
+        );
+        assert_eq!(BlockKind::start("<![CDATA[", false), Some(BlockKind::CData));
+        assert_eq!(
+            BlockKind::start("   <details>", false),
+            Some(BlockKind::Block)
+        );
+        assert_eq!(BlockKind::start("</div>", true), Some(BlockKind::Block));
+        assert_eq!(BlockKind::start("<div", false), Some(BlockKind::Block));
+        assert_eq!(
+            BlockKind::start("<custom-tag a=\"b\">  ", false),
+            Some(BlockKind::Other)
+        );
+        assert_eq!(BlockKind::start("<custom-tag>", true), None);
+        assert_eq!(BlockKind::start("<span>text", false), None);
+        assert_eq!(BlockKind::start("<divider>", false), Some(BlockKind::Other));
+        assert_eq!(BlockKind::start("    <div>", false), None);
+        assert!(BlockKind::RawText.ends("x</SCRIPT> y"));
+        assert!(!BlockKind::Comment.ends("- ->"));
+    }
+}
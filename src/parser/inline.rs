@@ -0,0 +1,2028 @@
+/*
+ * Inline parsing.
+ *
+ * Turn the tokens making up the text of a block into a tree of inline nodes. Emphasis follows the
+ * delimiter run rules of CommonMark: runs of `*` and `_` are pushed on a stack as they are read,
+ * and pairs of them are matched up once the whole text has been seen, which lets them nest.
+ * Links work the same way, with `[` pushed on a stack of brackets until a `]` closes it.
+ */
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::abbreviations::Abbreviations;
+use super::attributes;
+use super::autolink;
+use super::emoji::{self, Replacement};
+use super::footnotes::{self, Footnotes};
+use super::html;
+use super::lexer::{unescape, Span, Token, TokenType};
+use super::parser::{AST, MAX_NESTING};
+use super::smart;
+use super::wiki::{self, WikiLinks};
+use crate::types::elements;
+use crate::types::options::Options;
+
+pub struct Text {
+    pub text: String,
+    pub span: Span,
+}
+
+pub struct Code {
+    pub text: String,
+    pub attributes: elements::Attributes,
+    pub span: Span,
+}
+
+pub struct Emphasis {
+    pub children: Vec<Inline>,
+    pub span: Span,
+}
+
+pub struct Strong {
+    pub children: Vec<Inline>,
+    pub span: Span,
+}
+
+pub struct Strikethrough {
+    pub children: Vec<Inline>,
+    pub span: Span,
+}
+
+pub struct Highlight {
+    pub children: Vec<Inline>,
+    pub span: Span,
+}
+
+pub struct Superscript {
+    pub children: Vec<Inline>,
+    pub span: Span,
+}
+
+pub struct Subscript {
+    pub children: Vec<Inline>,
+    pub span: Span,
+}
+
+pub struct Link {
+    pub destination: String,
+    pub title: Option<String>,
+    pub children: Vec<Inline>,
+    pub attributes: elements::Attributes,
+    pub span: Span,
+}
+
+pub struct Image {
+    pub source: String,
+    pub title: Option<String>,
+    // The description, which becomes the alt text
+    pub children: Vec<Inline>,
+    pub attributes: elements::Attributes,
+    pub span: Span,
+}
+
+// `[text]` followed by an attribute block, as in `[text]{.class}`
+pub struct BracketedSpan {
+    pub children: Vec<Inline>,
+    pub attributes: elements::Attributes,
+    pub span: Span,
+}
+
+// LaTeX between dollar signs
+pub struct Math {
+    pub tex: String,
+    // Between double dollar signs
+    pub display: bool,
+    pub span: Span,
+}
+
+// Text which may turn out to hold abbreviations, once all their definitions have been seen
+pub struct AbbreviatedText {
+    pub text: Text,
+    pub abbreviations: Rc<RefCell<Abbreviations>>,
+}
+
+// A `[[Page Name]]` or `[[Page Name|label]]` link, which goes nowhere if there is no such page
+pub struct WikiLink {
+    pub label: String,
+    pub destination: Option<String>,
+    // The link exactly as it is in the source
+    pub text: String,
+    pub span: Span,
+}
+
+// A `:shortcode:` which is a known emoji
+pub struct Emoji {
+    pub name: String,
+    pub replacement: Replacement,
+    pub span: Span,
+}
+
+// A tag, comment or the like, exactly as it is in the source
+pub struct Html {
+    pub html: String,
+    pub span: Span,
+}
+
+// A `[^label]` reference, which is only a footnote if the label turns out to be defined
+pub struct FootnoteReference {
+    pub label: String,
+    // Which reference to the footnote this is, counting from 1
+    pub occurrence: usize,
+    // The reference exactly as it is in the source
+    pub text: String,
+    pub footnotes: Rc<RefCell<Footnotes>>,
+    pub span: Span,
+}
+
+pub enum Inline {
+    Text(Text),
+    AbbreviatedText(AbbreviatedText),
+    Code(Code),
+    Emphasis(Emphasis),
+    Strong(Strong),
+    Strikethrough(Strikethrough),
+    Highlight(Highlight),
+    Superscript(Superscript),
+    Subscript(Subscript),
+    Link(Link),
+    Image(Image),
+    BracketedSpan(BracketedSpan),
+    Html(Html),
+    FootnoteReference(FootnoteReference),
+    Math(Math),
+    Emoji(Emoji),
+    WikiLink(WikiLink),
+}
+
+impl Inline {
+    // The text of a text node, whether or not it may hold abbreviations
+    pub fn text_mut(&mut self) -> Option<&mut Text> {
+        match self {
+            Inline::Text(text) => Some(text),
+            Inline::AbbreviatedText(abbreviated) => Some(&mut abbreviated.text),
+            _ => None,
+        }
+    }
+
+    // The nodes inside the node, if it is one which holds others
+    fn children(&self) -> Option<&Vec<Inline>> {
+        match self {
+            Inline::Emphasis(Emphasis { children, .. })
+            | Inline::Strong(Strong { children, .. })
+            | Inline::Strikethrough(Strikethrough { children, .. })
+            | Inline::Highlight(Highlight { children, .. })
+            | Inline::Superscript(Superscript { children, .. })
+            | Inline::Subscript(Subscript { children, .. })
+            | Inline::Link(Link { children, .. })
+            | Inline::Image(Image { children, .. })
+            | Inline::BracketedSpan(BracketedSpan { children, .. }) => Some(children),
+            _ => None,
+        }
+    }
+
+    fn children_mut(&mut self) -> Option<&mut Vec<Inline>> {
+        match self {
+            Inline::Emphasis(Emphasis { children, .. })
+            | Inline::Strong(Strong { children, .. })
+            | Inline::Strikethrough(Strikethrough { children, .. })
+            | Inline::Highlight(Highlight { children, .. })
+            | Inline::Superscript(Superscript { children, .. })
+            | Inline::Subscript(Subscript { children, .. })
+            | Inline::Link(Link { children, .. })
+            | Inline::Image(Image { children, .. })
+            | Inline::BracketedSpan(BracketedSpan { children, .. }) => Some(children),
+            _ => None,
+        }
+    }
+
+    // The text of the node and everything inside it, without any formatting
+    pub fn plain_text(&self) -> String {
+        match self {
+            Inline::Text(text) => text.text.clone(),
+            Inline::AbbreviatedText(abbreviated) => abbreviated.text.text.clone(),
+            Inline::Code(code) => code.text.clone(),
+            Inline::Math(math) => math.tex.clone(),
+            Inline::Emoji(emoji) => match &emoji.replacement {
+                Replacement::Character(character) => character.to_string(),
+                Replacement::Image(_) => format!(":{}:", emoji.name),
+            },
+            Inline::Html(_) | Inline::FootnoteReference(_) => String::new(),
+            Inline::WikiLink(link) => link.label.clone(),
+            Inline::Emphasis(Emphasis { children, .. })
+            | Inline::Strong(Strong { children, .. })
+            | Inline::Strikethrough(Strikethrough { children, .. })
+            | Inline::Highlight(Highlight { children, .. })
+            | Inline::Superscript(Superscript { children, .. })
+            | Inline::Subscript(Subscript { children, .. })
+            | Inline::Link(Link { children, .. })
+            | Inline::Image(Image { children, .. })
+            | Inline::BracketedSpan(BracketedSpan { children, .. }) => {
+                children.iter().map(|child| child.plain_text()).collect()
+            }
+        }
+    }
+}
+
+// Convert a list of inline nodes to renderables
+pub fn convert_inlines(inlines: &[Inline]) -> Vec<Box<dyn elements::Renderable>> {
+    inlines
+        .iter()
+        .map(|inline| inline.convert_to_renderable())
+        .collect()
+}
+
+impl AST for Inline {
+    fn convert_to_renderable(&self) -> Box<dyn elements::Renderable> {
+        match self {
+            Inline::Text(text) => Box::new(elements::Text::new(&text.text)),
+            Inline::AbbreviatedText(abbreviated) => Box::new(elements::AbbreviatedText {
+                pieces: abbreviated
+                    .abbreviations
+                    .borrow()
+                    .split(&abbreviated.text.text),
+            }),
+            Inline::Code(code) => Box::new(elements::Code {
+                text: code.text.clone(),
+                attributes: code.attributes.clone(),
+            }),
+            Inline::Emphasis(emphasis) => {
+                Box::new(elements::Emphasis::new(convert_inlines(&emphasis.children)))
+            }
+            Inline::Strong(strong) => {
+                Box::new(elements::Strong::new(convert_inlines(&strong.children)))
+            }
+            Inline::Strikethrough(strikethrough) => Box::new(elements::Strikethrough::new(
+                convert_inlines(&strikethrough.children),
+            )),
+            Inline::Highlight(highlight) => Box::new(elements::Highlight::new(convert_inlines(
+                &highlight.children,
+            ))),
+            Inline::Superscript(superscript) => Box::new(elements::Superscript::new(
+                convert_inlines(&superscript.children),
+            )),
+            Inline::Subscript(subscript) => Box::new(elements::Subscript::new(convert_inlines(
+                &subscript.children,
+            ))),
+            Inline::Link(link) => Box::new(elements::Link {
+                destination: link.destination.clone(),
+                title: link.title.clone(),
+                children: convert_inlines(&link.children),
+                attributes: link.attributes.clone(),
+            }),
+            Inline::Image(image) => Box::new(elements::Image {
+                source: image.source.clone(),
+                title: image.title.clone(),
+                alt: image
+                    .children
+                    .iter()
+                    .map(|child| child.plain_text())
+                    .collect(),
+                attributes: image.attributes.clone(),
+            }),
+            Inline::BracketedSpan(bracketed) => Box::new(elements::BracketedSpan {
+                children: convert_inlines(&bracketed.children),
+                attributes: bracketed.attributes.clone(),
+            }),
+            Inline::Html(html) => Box::new(elements::Html {
+                html: html.html.clone(),
+                span: None,
+            }),
+            Inline::Math(math) => Box::new(elements::Math {
+                tex: math.tex.clone(),
+                display: math.display,
+                attributes: elements::Attributes::default(),
+                span: None,
+            }),
+            Inline::Emoji(emoji) => match &emoji.replacement {
+                Replacement::Character(character) => Box::new(elements::Text::new(character)),
+                Replacement::Image(source) => Box::new(elements::Emoji {
+                    name: emoji.name.clone(),
+                    source: source.clone(),
+                    attributes: elements::Attributes::default(),
+                }),
+            },
+            Inline::WikiLink(link) => match &link.destination {
+                Some(destination) => Box::new(elements::Link {
+                    destination: destination.clone(),
+                    title: None,
+                    children: vec![Box::new(elements::Text::new(&link.label))],
+                    attributes: elements::Attributes::default(),
+                }),
+                None => Box::new(elements::Text::new(&link.text)),
+            },
+            Inline::FootnoteReference(reference) => {
+                match reference.footnotes.borrow().number(&reference.label) {
+                    Some(number) => Box::new(elements::FootnoteReference {
+                        number,
+                        occurrence: reference.occurrence,
+                    }),
+                    None => Box::new(elements::Text::new(&reference.text)),
+                }
+            }
+        }
+    }
+
+    fn span(&self) -> Span {
+        match self {
+            Inline::Text(text) => text.span,
+            Inline::AbbreviatedText(abbreviated) => abbreviated.text.span,
+            Inline::Code(code) => code.span,
+            Inline::Emphasis(emphasis) => emphasis.span,
+            Inline::Strong(strong) => strong.span,
+            Inline::Strikethrough(strikethrough) => strikethrough.span,
+            Inline::Highlight(highlight) => highlight.span,
+            Inline::Superscript(superscript) => superscript.span,
+            Inline::Subscript(subscript) => subscript.span,
+            Inline::Link(link) => link.span,
+            Inline::Image(image) => image.span,
+            Inline::BracketedSpan(bracketed) => bracketed.span,
+            Inline::Html(html) => html.span,
+            Inline::FootnoteReference(reference) => reference.span,
+            Inline::Math(math) => math.span,
+            Inline::Emoji(emoji) => emoji.span,
+            Inline::WikiLink(link) => link.span,
+        }
+    }
+}
+
+/*
+ * A run of `*` or `_` which might open or close emphasis. It points at the Text node holding the
+ * characters of the run. Matching only counts off the characters used, in `opens` and `closes`,
+ * and the text is split up once every pair is known
+ */
+struct Delimiter {
+    id: usize,
+    character: char,
+    node: usize,
+    count: usize,
+    length: usize,
+    can_open: bool,
+    can_close: bool,
+    // The characters used by each pair this run opens, taken from its end, innermost first
+    opens: Vec<usize>,
+    // The characters used by each pair this run closes, taken from its start, innermost first
+    closes: Vec<usize>,
+}
+
+// Emphasis opened by a delimiter run, collecting its children until the run which closes it
+struct Frame {
+    character: char,
+    used: usize,
+    children: Vec<Inline>,
+    span: Span,
+}
+
+/*
+ * A `[` or `![` which might open a link or an image. Only the delimiters pushed after it belong
+ * to its text
+ */
+struct Bracket {
+    node: usize,
+    image: bool,
+    active: bool,
+    delimiter_bottom: Option<usize>,
+}
+
+struct InlineParser<'a> {
+    tokens: Vec<Token>,
+    source: &'a str,
+    options: &'a Options,
+    position: usize,
+    nodes: Vec<Inline>,
+    delimiters: Vec<Delimiter>,
+    brackets: Vec<Bracket>,
+    next_id: usize,
+    footnotes: &'a Rc<RefCell<Footnotes>>,
+    wiki_links: &'a Rc<RefCell<WikiLinks>>,
+    abbreviations: &'a Rc<RefCell<Abbreviations>>,
+}
+
+// Parse the tokens of a piece of text, taken from source
+pub fn parse(
+    tokens: &[Token],
+    source: &str,
+    options: &Options,
+    footnotes: &Rc<RefCell<Footnotes>>,
+    wiki_links: &Rc<RefCell<WikiLinks>>,
+    abbreviations: &Rc<RefCell<Abbreviations>>,
+) -> Vec<Inline> {
+    let mut parser = InlineParser {
+        tokens: tokens.to_vec(),
+        source,
+        options,
+        footnotes,
+        wiki_links,
+        abbreviations,
+        position: 0,
+        nodes: vec![],
+        delimiters: vec![],
+        brackets: vec![],
+        next_id: 0,
+    };
+    parser.parse()
+}
+
+// CommonMark counts both punctuation and symbols as punctuation
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation() || (!c.is_alphanumeric() && !c.is_whitespace() && !c.is_control())
+}
+
+/*
+ * Part of the span of `text`, which doesn't cross a line, from byte `from` up to byte `to`
+ */
+fn sub_span(span: Span, text: &str, from: usize, to: usize) -> Span {
+    Span {
+        start: span.start + from,
+        end: span.start + to,
+        line: span.line,
+        column: span.column + text[..from].chars().count() as u32,
+        end_line: span.line,
+        end_column: span.column + text[..to].chars().count() as u32,
+    }
+}
+
+/*
+ * Part of the span of a delimiter run, from character `from` up to character `to`. Every character
+ * in a run is a single byte
+ */
+fn run_span(span: Span, from: usize, to: usize) -> Span {
+    Span {
+        start: span.start + from,
+        end: span.start + to,
+        line: span.line,
+        column: span.column + from as u32,
+        end_line: span.line,
+        end_column: span.column + to as u32,
+    }
+}
+
+/*
+ * Drop a delimiter from the list linked through `previous` and `next`, returning the one after it
+ */
+fn unlink(previous: &mut [Option<usize>], next: &mut [usize], index: usize) -> usize {
+    if let Some(before) = previous[index] {
+        next[before] = next[index];
+    }
+    if next[index] < previous.len() {
+        previous[next[index]] = previous[index];
+    }
+    next[index]
+}
+
+impl InlineParser<'_> {
+    fn parse(&mut self) -> Vec<Inline> {
+        while let Some(token) = self.tokens.get(self.position) {
+            match token.token_type {
+                TokenType::ASTERISK
+                | TokenType::UNDERSCORE
+                | TokenType::TILDE
+                | TokenType::EQUALS
+                | TokenType::CARET => self.delimiter_run(),
+                TokenType::BACKTICK => self.code_span(),
+                TokenType::DOLLAR => self.math(),
+                TokenType::LBRACKET => self.open_bracket(),
+                TokenType::RBRACKET => self.close_bracket(),
+                TokenType::TEXT if token.value.contains('<') => self.angle_bracket(),
+                TokenType::TEXT => {
+                    let text = self.punctuate(&token.value);
+                    self.push_text(text, token.span);
+                    self.position += 1;
+                }
+                _ => {
+                    self.push_text(token.value.clone(), token.span);
+                    self.position += 1;
+                }
+            }
+        }
+        self.brackets.clear();
+        self.process_emphasis(None);
+        let nodes = limit_nesting(std::mem::take(&mut self.nodes), self.source, 0);
+        let mut nodes = merge_texts(nodes);
+        if self.options.extensions.autolinks {
+            nodes = replace_texts(nodes, false, &linkify);
+        }
+        if self.options.extensions.emoji {
+            let custom = &self.options.custom_emoji;
+            nodes = replace_texts(nodes, true, &|text| emojify(text, custom));
+        }
+        if self.options.extensions.abbreviations {
+            nodes = replace_texts(nodes, true, &|text| {
+                vec![Inline::AbbreviatedText(AbbreviatedText {
+                    text,
+                    abbreviations: Rc::clone(self.abbreviations),
+                })]
+            });
+        }
+        nodes
+    }
+
+    /*
+     * Move the cursor past `length` bytes of token text, splitting the token it ends in. Returns
+     * the span of what was skipped
+     */
+    fn consume(&mut self, mut length: usize) -> Span {
+        let start = self.tokens[self.position].span;
+        let mut end = start;
+        while length > 0 {
+            let token = &mut self.tokens[self.position];
+            if token.value.len() <= length {
+                length -= token.value.len();
+                end = token.span;
+                self.position += 1;
+            } else {
+                end = sub_span(token.span, &token.value, 0, length);
+                token.span = sub_span(token.span, &token.value, length, token.value.len());
+                token.value.drain(..length);
+                length = 0;
+            }
+        }
+        start.to(end)
+    }
+
+    /*
+     * Move the cursor up to the byte `offset` in the source, splitting the token it ends in.
+     * Returns the span of what was skipped
+     */
+    fn consume_to(&mut self, offset: usize) -> Span {
+        let start = self.tokens[self.position].span;
+        let mut end = start;
+        while let Some(token) = self.tokens.get_mut(self.position) {
+            if token.span.end <= offset {
+                end = token.span;
+                self.position += 1;
+            } else {
+                if token.span.start < offset {
+                    // What's left of the token is plain text, whatever it was before
+                    let raw = &self.source[token.span.start..token.span.end];
+                    let length = offset - token.span.start;
+                    end = sub_span(token.span, raw, 0, length);
+                    token.span = sub_span(token.span, raw, length, raw.len());
+                    token.value = raw[length..].to_string();
+                    token.token_type = TokenType::TEXT;
+                }
+                break;
+            }
+        }
+        start.to(end)
+    }
+
+    /*
+     * A text token with a `<` in it, which may start an autolink, or a tag or comment running on
+     * through the following tokens. Otherwise the `<` is just text
+     */
+    fn angle_bracket(&mut self) {
+        let token = &self.tokens[self.position];
+        let open = token.value.find('<').unwrap();
+        if open > 0 {
+            let text = self.punctuate(&token.value[..open]);
+            let span = self.consume(open);
+            self.push_text(text, span);
+        }
+        let start = self.tokens[self.position].span.start;
+        let end = self.tokens.last().unwrap().span.end;
+        let source = &self.source[start..end];
+        if let Some((destination, text, length)) = autolink::angle_brackets(source) {
+            let span = self.consume_to(start + length);
+            let text = Text {
+                text,
+                span: sub_span(span, &source[..length], 1, length - 1),
+            };
+            self.nodes.push(Inline::Link(Link {
+                destination,
+                title: None,
+                children: vec![Inline::Text(text)],
+                attributes: elements::Attributes::default(),
+                span,
+            }));
+            return;
+        }
+        match html::inline(source).filter(|_| self.options.extensions.raw_html) {
+            Some(length) => {
+                let span = self.consume_to(start + length);
+                let html = self.source[start..start + length].to_string();
+                self.nodes.push(Inline::Html(Html { html, span }));
+            }
+            None => {
+                let span = self.consume(1);
+                self.push_text("<".to_string(), span);
+            }
+        }
+    }
+
+    /*
+     * A `$` or `$$`, which opens math if it has a matching closer. Like a code span, nothing
+     * inside it is parsed as Markdown
+     */
+    fn math(&mut self) {
+        let start = self.tokens[self.position].span.start;
+        let end = self.tokens.last().unwrap().span.end;
+        match math_span(&self.source[start..end]) {
+            Some((tex, display, length)) => {
+                let span = self.consume_to(start + length);
+                self.nodes.push(Inline::Math(Math { tex, display, span }));
+            }
+            None => {
+                // An unmatched $$ is text as a whole, rather than a $ which might open math
+                let double = self.tokens.get(self.position + 1).is_some_and(|token| {
+                    token.token_type == TokenType::DOLLAR && token.span.start == start + 1
+                });
+                let span = self.consume(if double { 2 } else { 1 });
+                let text = if double { "$$" } else { "$" };
+                self.push_text(text.to_string(), span);
+            }
+        }
+    }
+
+    // A `[`, or a `![` when the text before it ends in an exclamation mark
+    fn open_bracket(&mut self) {
+        if self.options.extensions.footnotes && self.footnote_reference() {
+            return;
+        }
+        if self.options.extensions.wiki_links && self.wiki_link() {
+            return;
+        }
+        let token = self.tokens[self.position].clone();
+        self.position += 1;
+        let mut image = false;
+        let mut span = token.span;
+        if let Some(Inline::Text(text)) = self.nodes.last_mut() {
+            let previous = &self.tokens[self.position - 2];
+            if text.text.ends_with('!') && previous.token_type == TokenType::TEXT {
+                let length = text.text.len();
+                span = sub_span(text.span, &text.text, length - 1, length).to(span);
+                text.span = sub_span(text.span, &text.text, 0, length - 1);
+                text.text.pop();
+                image = true;
+            }
+        }
+        let value = if image { "![" } else { "[" };
+        self.push_text(value.to_string(), span);
+        self.brackets.push(Bracket {
+            node: self.nodes.len() - 1,
+            image,
+            active: true,
+            delimiter_bottom: self.delimiters.last().map(|delimiter| delimiter.id),
+        });
+    }
+
+    // A `[[Page Name]]` wiki link at the cursor
+    fn wiki_link(&mut self) -> bool {
+        let start = self.tokens[self.position].span.start;
+        let end = self.tokens.last().unwrap().span.end;
+        let Some((name, label, length)) = wiki::link(&self.source[start..end]) else {
+            return false;
+        };
+        let name = name.to_string();
+        let label = label.unwrap_or(&name).to_string();
+        let text = self.source[start..start + length].to_string();
+        let span = self.consume_to(start + length);
+        let destination = self.wiki_links.borrow_mut().resolve(&name, span);
+        self.nodes.push(Inline::WikiLink(WikiLink {
+            label,
+            destination,
+            text,
+            span,
+        }));
+        true
+    }
+
+    // A `[^label]` footnote reference at the cursor
+    fn footnote_reference(&mut self) -> bool {
+        let start = self.tokens[self.position].span.start;
+        let end = self.tokens.last().unwrap().span.end;
+        let Some((label, length)) = footnotes::label(&self.source[start..end]) else {
+            return false;
+        };
+        let label = label.to_string();
+        let text = self.source[start..start + length].to_string();
+        let span = self.consume_to(start + length);
+        let occurrence = self.footnotes.borrow_mut().reference(&label, span);
+        self.nodes
+            .push(Inline::FootnoteReference(FootnoteReference {
+                label,
+                occurrence,
+                text,
+                footnotes: Rc::clone(self.footnotes),
+                span,
+            }));
+        true
+    }
+
+    /*
+     * A `]` makes a link or image out of everything since the last bracket, if it is followed by
+     * a destination in parentheses. Otherwise it is just text
+     */
+    fn close_bracket(&mut self) {
+        let token = self.tokens[self.position].clone();
+        self.position += 1;
+        let bracket = match self.brackets.pop() {
+            Some(bracket) if bracket.active => bracket,
+            _ => {
+                self.push_text(token.value, token.span);
+                return;
+            }
+        };
+        let link = self.tokens.get(self.position).and_then(|token| {
+            let start = token.span.start;
+            let end = self.tokens.last().unwrap().span.end;
+            let (destination, title, length) = link_destination(&self.source[start..end])?;
+            Some((destination, title, start + length))
+        });
+        let (destination, title, offset) = match link {
+            Some(link) => link,
+            None => {
+                match self.attributes().filter(|_| !bracket.image) {
+                    Some((attributes, end)) => self.bracketed_span(bracket, attributes, end),
+                    None => self.push_text(token.value, token.span),
+                }
+                return;
+            }
+        };
+        let mut end = self.consume_to(offset);
+        let attributes = match self.attributes() {
+            Some((attributes, span)) => {
+                end = span;
+                attributes
+            }
+            None => elements::Attributes::default(),
+        };
+
+        self.process_emphasis(bracket.delimiter_bottom);
+        let children: Vec<Inline> = self.nodes.drain(bracket.node + 1..).collect();
+        let opening = self.nodes.pop().unwrap();
+        let span = opening.span().to(end);
+        if bracket.image {
+            self.nodes.push(Inline::Image(Image {
+                source: destination,
+                title,
+                children,
+                attributes,
+                span,
+            }));
+        } else {
+            self.nodes.push(Inline::Link(Link {
+                destination,
+                title,
+                children,
+                attributes,
+                span,
+            }));
+            // Links can't contain other links. Those below an inactive bracket already are
+            for bracket in self
+                .brackets
+                .iter_mut()
+                .rev()
+                .filter(|bracket| !bracket.image)
+            {
+                if !bracket.active {
+                    break;
+                }
+                bracket.active = false;
+            }
+        }
+    }
+
+    // The text since the `[` of bracket, as a span with the attributes which followed it
+    fn bracketed_span(&mut self, bracket: Bracket, attributes: elements::Attributes, end: Span) {
+        self.process_emphasis(bracket.delimiter_bottom);
+        let children: Vec<Inline> = self.nodes.drain(bracket.node + 1..).collect();
+        let opening = self.nodes.pop().unwrap();
+        let span = opening.span().to(end);
+        self.nodes.push(Inline::BracketedSpan(BracketedSpan {
+            children,
+            attributes,
+            span,
+        }));
+    }
+
+    /*
+     * An attribute block at the cursor, straight after an inline such as a link. Returns the
+     * attributes and their span
+     */
+    fn attributes(&mut self) -> Option<(elements::Attributes, Span)> {
+        if !self.options.extensions.attributes {
+            return None;
+        }
+        let start = self.tokens.get(self.position)?.span.start;
+        let end = self.tokens.last().unwrap().span.end;
+        let (attributes, length) = attributes::parse(&self.source[start..end])?;
+        Some((attributes, self.consume_to(start + length)))
+    }
+
+    /*
+     * Text with smart punctuation, when it is switched on. Only text straight from the source
+     * goes through here, so escaped quotes stay straight
+     */
+    fn punctuate(&self, text: &str) -> String {
+        if !self.options.extensions.smart_punctuation {
+            return text.to_string();
+        }
+        let previous = self.nodes.last().and_then(last_char);
+        let start = self.tokens[self.position].span.start;
+        let following = self
+            .source
+            .get(start + text.len()..)
+            .and_then(|rest| rest.chars().next());
+        smart::punctuate(text, previous, following)
+    }
+
+    fn push_text(&mut self, text: String, span: Span) {
+        self.nodes.push(Inline::Text(Text { text, span }));
+    }
+
+    // The text of a token exactly as it appears in the source, before escapes and entities
+    fn raw(&self, token: &Token) -> &str {
+        &self.source[token.span.start..token.span.end]
+    }
+
+    /*
+     * The number of backticks in the run starting at token `index`. An escaped backtick still
+     * counts, since backslashes are literal inside code spans and it may close one
+     */
+    fn backtick_run(&self, index: usize) -> usize {
+        let is_backtick = |token: &Token| token.token_type == TokenType::BACKTICK;
+        let first = match self.tokens.get(index) {
+            Some(token) if is_backtick(token) => 1,
+            Some(token) if token.token_type == TokenType::ESCAPE && token.value == "`" => 1,
+            _ => return 0,
+        };
+        first
+            + self.tokens[index + 1..]
+                .iter()
+                .take_while(|token| is_backtick(token))
+                .count()
+    }
+
+    /*
+     * A code span opens with a run of backticks and closes at the next run of exactly as many.
+     * Its content is left as it is in the source, except that line endings become spaces and a
+     * single space is stripped from each end when there is one on both. Without a closing run
+     * the opening one is just text
+     */
+    fn code_span(&mut self) {
+        let length = self.backtick_run(self.position);
+        let mut index = self.position + length;
+        let closing = loop {
+            match self.tokens.get(index) {
+                None => break None,
+                Some(_) => match self.backtick_run(index) {
+                    0 => index += 1,
+                    run if run == length => break Some(index),
+                    run => index += run,
+                },
+            }
+        };
+        let opening = self.tokens[self.position].span;
+        match closing {
+            Some(closing) => {
+                let mut text: String = self.tokens[self.position + length..closing]
+                    .iter()
+                    .map(|token| self.raw(token))
+                    .collect();
+                // An escaped backtick that closes the span leaves its backslash behind
+                if self.tokens[closing].token_type == TokenType::ESCAPE {
+                    text.push('\\');
+                }
+                let mut text = text.replace("\r\n", " ").replace('\n', " ");
+                if text.len() > 1
+                    && text.starts_with(' ')
+                    && text.ends_with(' ')
+                    && !text.chars().all(|c| c == ' ')
+                {
+                    text = text[1..text.len() - 1].to_string();
+                }
+                let mut span = opening.to(self.tokens[closing + length - 1].span);
+                self.position = closing + length;
+                let attributes = match self.attributes() {
+                    Some((attributes, end)) => {
+                        span = span.to(end);
+                        attributes
+                    }
+                    None => elements::Attributes::default(),
+                };
+                self.nodes.push(Inline::Code(Code {
+                    text,
+                    attributes,
+                    span,
+                }));
+            }
+            None => {
+                let span = opening.to(self.tokens[self.position + length - 1].span);
+                self.push_text("`".repeat(length), span);
+                self.position += length;
+            }
+        }
+    }
+
+    /*
+     * Read a run of `*` or `_` and work out whether it can open or close emphasis, from the
+     * characters on either side of it. The start and end of the text count as whitespace
+     */
+    fn delimiter_run(&mut self) {
+        let start = self.position;
+        let token_type = self.tokens[start].token_type.clone();
+        while matches!(self.tokens.get(self.position), Some(token) if token.token_type == token_type)
+        {
+            self.position += 1;
+        }
+        let count = self.position - start;
+        let character = self.tokens[start].value.chars().next().unwrap();
+        let span = self.tokens[start]
+            .span
+            .to(self.tokens[self.position - 1].span);
+
+        let before = match start {
+            0 => ' ',
+            _ => self.tokens[start - 1].value.chars().last().unwrap_or(' '),
+        };
+        let after = match self.tokens.get(self.position) {
+            Some(token) => token.value.chars().next().unwrap_or(' '),
+            None => ' ',
+        };
+        let left_flanking = !after.is_whitespace()
+            && (!is_punctuation(after) || before.is_whitespace() || is_punctuation(before));
+        let right_flanking = !before.is_whitespace()
+            && (!is_punctuation(before) || after.is_whitespace() || is_punctuation(after));
+        let (can_open, can_close) = match character {
+            // Underscores inside a word don't count
+            '_' => (
+                left_flanking && (!right_flanking || is_punctuation(before)),
+                right_flanking && (!left_flanking || is_punctuation(after)),
+            ),
+            _ => (left_flanking, right_flanking),
+        };
+
+        self.push_text(character.to_string().repeat(count), span);
+        // The extensions only use runs of a particular length, any other is just text
+        let extensions = &self.options.extensions;
+        let usable = match character {
+            '~' => match count {
+                1 => extensions.subscript || extensions.strikethrough,
+                2 => extensions.strikethrough,
+                _ => false,
+            },
+            '=' => count == 2,
+            '^' => count == 1,
+            _ => true,
+        };
+        if !usable {
+            return;
+        }
+        self.delimiters.push(Delimiter {
+            id: self.next_id,
+            character,
+            node: self.nodes.len() - 1,
+            count,
+            length: count,
+            can_open,
+            can_close,
+            opens: vec![],
+            closes: vec![],
+        });
+        self.next_id += 1;
+    }
+
+    /*
+     * Match up the openers and closers above the stack bottom, then wrap everything between each
+     * pair in an Emphasis or Strong node. A closer looks back for the nearest opener of the same
+     * character, and `openers_bottom` remembers where that search already failed so it isn't
+     * repeated. The delimiters are linked together through `previous` and `next`, so that those
+     * used up can be dropped without shifting the rest
+     */
+    fn process_emphasis(&mut self, stack_bottom: Option<usize>) {
+        let first = self
+            .delimiters
+            .partition_point(|delimiter| stack_bottom.is_some_and(|bottom| delimiter.id <= bottom));
+        let delimiters = &mut self.delimiters[first..];
+        let length = delimiters.len();
+        let mut previous: Vec<Option<usize>> =
+            (0..length).map(|index| index.checked_sub(1)).collect();
+        let mut next: Vec<usize> = (1..=length).collect();
+        let mut openers_bottom: HashMap<(char, bool, usize), usize> = HashMap::new();
+        let mut closer = 0;
+
+        while closer < length {
+            if !delimiters[closer].can_close {
+                closer = next[closer];
+                continue;
+            }
+            let closing = &delimiters[closer];
+            let key = (closing.character, closing.can_open, closing.length % 3);
+            let floor = openers_bottom.get(&key).copied().unwrap_or(0);
+
+            let mut opener = None;
+            let mut index = previous[closer];
+            while let Some(candidate) = index {
+                let opening = &delimiters[candidate];
+                if opening.id < floor {
+                    break;
+                }
+                // A run that can both open and close can't pair up with one whose length adds up
+                // to a multiple of three, unless both are
+                let odd_match = (opening.can_close || closing.can_open)
+                    && (opening.length + closing.length).is_multiple_of(3)
+                    && !(opening.length.is_multiple_of(3) && closing.length.is_multiple_of(3));
+                // The extensions only pair up runs of the same length
+                let matched = match closing.character {
+                    '*' | '_' => !odd_match,
+                    _ => opening.length == closing.length,
+                };
+                if opening.character == closing.character && opening.can_open && matched {
+                    opener = Some(candidate);
+                    break;
+                }
+                index = previous[candidate];
+            }
+
+            let Some(opener) = opener else {
+                openers_bottom.insert(key, closing.id);
+                closer = match closing.can_open {
+                    true => next[closer],
+                    false => unlink(&mut previous, &mut next, closer),
+                };
+                continue;
+            };
+
+            let strong = delimiters[opener].count >= 2 && delimiters[closer].count >= 2;
+            let used = match delimiters[closer].character {
+                '*' | '_' if strong => 2,
+                '*' | '_' => 1,
+                _ => delimiters[closer].count,
+            };
+            delimiters[opener].count -= used;
+            delimiters[opener].opens.push(used);
+            delimiters[closer].count -= used;
+            delimiters[closer].closes.push(used);
+
+            // Delimiters between the pair stay as text
+            let mut between = next[opener];
+            while between != closer {
+                between = unlink(&mut previous, &mut next, between);
+            }
+            if delimiters[opener].count == 0 {
+                unlink(&mut previous, &mut next, opener);
+            }
+            if delimiters[closer].count == 0 {
+                closer = unlink(&mut previous, &mut next, closer);
+            }
+        }
+
+        self.wrap_emphasis(first);
+    }
+
+    /*
+     * Rebuild the nodes from the first delimiter at or after `first` onwards, splitting each run
+     * into the characters which close emphasis, those left as text and those which open it
+     */
+    fn wrap_emphasis(&mut self, first: usize) {
+        let delimiters = self.delimiters.split_off(first);
+        let Some(start) = delimiters.first().map(|delimiter| delimiter.node) else {
+            return;
+        };
+        let mut delimiters = delimiters.into_iter().peekable();
+        let mut frames: Vec<Frame> = vec![];
+        let mut nodes = vec![];
+        for (index, node) in self.nodes.split_off(start).into_iter().enumerate() {
+            let Some(delimiter) = delimiters.next_if(|delimiter| delimiter.node == start + index)
+            else {
+                frames
+                    .last_mut()
+                    .map_or(&mut nodes, |frame| &mut frame.children)
+                    .push(node);
+                continue;
+            };
+            let Inline::Text(text) = node else {
+                unreachable!("delimiters always point at text");
+            };
+            let mut from = 0;
+            for used in delimiter.closes {
+                let frame = frames.pop().expect("a closer always has an opener");
+                let span = frame.span.to(run_span(text.span, from, from + used));
+                let node = self.emphasis(frame.character, frame.used, frame.children, span);
+                frames
+                    .last_mut()
+                    .map_or(&mut nodes, |frame| &mut frame.children)
+                    .push(node);
+                from += used;
+            }
+            let to = delimiter.length - delimiter.opens.iter().sum::<usize>();
+            if from < to {
+                frames
+                    .last_mut()
+                    .map_or(&mut nodes, |frame| &mut frame.children)
+                    .push(Inline::Text(Text {
+                        text: text.text[from..to].to_string(),
+                        span: run_span(text.span, from, to),
+                    }));
+            }
+            // The outermost emphasis opens first
+            let mut from = to;
+            for &used in delimiter.opens.iter().rev() {
+                frames.push(Frame {
+                    character: delimiter.character,
+                    used,
+                    children: vec![],
+                    span: run_span(text.span, from, from + used),
+                });
+                from += used;
+            }
+        }
+        self.nodes.append(&mut nodes);
+    }
+
+    // The node made by a pair of delimiter runs, from the character and how many of them it used
+    fn emphasis(&self, character: char, used: usize, children: Vec<Inline>, span: Span) -> Inline {
+        match (character, used) {
+            ('*' | '_', 2) => Inline::Strong(Strong { children, span }),
+            ('*' | '_', _) => Inline::Emphasis(Emphasis { children, span }),
+            ('~', 1) if self.options.extensions.subscript => {
+                Inline::Subscript(Subscript { children, span })
+            }
+            ('~', _) => Inline::Strikethrough(Strikethrough { children, span }),
+            ('=', _) => Inline::Highlight(Highlight { children, span }),
+            _ => Inline::Superscript(Superscript { children, span }),
+        }
+    }
+}
+
+// Bare link destinations can nest parentheses this deep, like in the reference implementation
+const MAX_PARENTHESES: usize = 32;
+
+/*
+ * Parse the destination and optional title of an inline link, e.g. `(/url "title")`, straight from
+ * the source. Returns them with the number of bytes they took up
+ */
+fn link_destination(text: &str) -> Option<(String, Option<String>, usize)> {
+    let mut rest = text.strip_prefix('(')?;
+    rest = rest.trim_start_matches([' ', '\t', '\n']);
+
+    let destination;
+    if let Some(inner) = rest.strip_prefix('<') {
+        // <...> destinations can contain spaces, but not line breaks
+        let (end, c) = unescaped_chars(inner).find(|&(_, c)| matches!(c, '>' | '\n' | '<'))?;
+        if c != '>' {
+            return None;
+        }
+        destination = unescape(&inner[..end]);
+        rest = &inner[end + 1..];
+    } else {
+        // Otherwise they run up to whitespace, and any parentheses in them must be balanced
+        let mut depth = 0;
+        let mut end = rest.len();
+        for (index, c) in unescaped_chars(rest) {
+            match c {
+                '(' if depth == MAX_PARENTHESES => return None,
+                '(' => depth += 1,
+                ')' if depth == 0 => {
+                    end = index;
+                    break;
+                }
+                ')' => depth -= 1,
+                c if c.is_whitespace() || c.is_control() => {
+                    end = index;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        destination = unescape(&rest[..end]);
+        rest = &rest[end..];
+    }
+
+    let before_title = rest.len();
+    rest = rest.trim_start_matches([' ', '\t', '\n']);
+    let mut title = None;
+    if let Some((open, close)) = match rest.chars().next() {
+        Some('"') => Some(('"', '"')),
+        Some('\'') => Some(('\'', '\'')),
+        Some('(') => Some(('(', ')')),
+        _ => None,
+    } {
+        // The title has to be separated from the destination
+        if before_title == rest.len() && !destination.is_empty() {
+            return None;
+        }
+        // A title in parentheses can't have another opening one in it
+        let inner = &rest[1..];
+        let (end, c) = unescaped_chars(inner).find(|&(_, c)| c == close || c == open)?;
+        if c != close {
+            return None;
+        }
+        title = Some(unescape(&inner[..end]));
+        rest = inner[end + 1..].trim_start_matches([' ', '\t', '\n']);
+    }
+
+    rest.strip_prefix(')')?;
+    let length = text.len() - rest.len() + 1;
+    Some((destination.replace(' ', "%20"), title, length))
+}
+
+// The characters of some source text with their byte offsets, leaving out any escaped ones
+fn unescaped_chars(text: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let mut chars = text.char_indices().peekable();
+    std::iter::from_fn(move || loop {
+        let (index, c) = chars.next()?;
+        if c != '\\'
+            || chars
+                .next_if(|(_, next)| next.is_ascii_punctuation())
+                .is_none()
+        {
+            return Some((index, c));
+        }
+    })
+}
+
+/*
+ * Math at the start of text: LaTeX between `$$`, or between single `$` which aren't just
+ * prices. The opening `$` must be followed by a non-space, and the closing one must come after
+ * a non-space and not be followed by a digit, so "$5 and $10" stays as it is. A `$` inside the
+ * math has to be escaped. Returns the LaTeX, whether it is display math, and the length of the
+ * whole thing
+ */
+fn math_span(text: &str) -> Option<(String, bool, usize)> {
+    if let Some(rest) = text.strip_prefix("$$") {
+        let end = rest.find("$$")?;
+        let tex = rest[..end].trim();
+        return match tex.is_empty() {
+            true => None,
+            false => Some((tex.to_string(), true, end + 4)),
+        };
+    }
+    let rest = text.strip_prefix('$')?;
+    if rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let mut previous = None;
+    let mut chars = rest.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            // A backslash escapes the character after it, which may be a $
+            '\\' => {
+                chars.next();
+                previous = Some('\\');
+                continue;
+            }
+            '$' if previous.is_some_and(|p: char| !p.is_whitespace())
+                && !rest[index + 1..].starts_with(|c: char| c.is_ascii_digit()) =>
+            {
+                return Some((rest[..index].to_string(), false, index + 2));
+            }
+            // Any other $ means the first one was a price, not math
+            '$' => return None,
+            _ => {}
+        }
+        previous = Some(c);
+    }
+    None
+}
+
+/*
+ * The last character of the plain text of node. The tree isn't limited in depth yet, so it is
+ * gone through without recursing
+ */
+fn last_char(node: &Inline) -> Option<char> {
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        match node.children() {
+            Some(children) => stack.extend(children),
+            None => {
+                if let Some(c) = node.plain_text().chars().next_back() {
+                    return Some(c);
+                }
+            }
+        }
+    }
+    None
+}
+
+/*
+ * Inlines can only nest MAX_NESTING deep, like blocks. The delimiters of those any deeper are
+ * left as text, with the text of the source, rather than running the renderer out of stack
+ */
+fn limit_nesting(nodes: Vec<Inline>, source: &str, depth: usize) -> Vec<Inline> {
+    let mut limited = vec![];
+    for mut node in nodes {
+        let Some(children) = node.children_mut() else {
+            limited.push(node);
+            continue;
+        };
+        if depth < MAX_NESTING {
+            *children = limit_nesting(std::mem::take(children), source, depth + 1);
+            limited.push(node);
+        } else {
+            flatten(node, source, &mut limited);
+        }
+    }
+    limited
+}
+
+// Push the leaves of node to out, with the delimiters around every set of children as text
+fn flatten(node: Inline, source: &str, out: &mut Vec<Inline>) {
+    let mut stack = vec![node];
+    while let Some(mut node) = stack.pop() {
+        let span = node.span();
+        let Some(children) = node.children_mut().map(std::mem::take) else {
+            out.push(node);
+            continue;
+        };
+        let text = |span: Span| {
+            Inline::Text(Text {
+                text: source[span.start..span.end].to_string(),
+                span,
+            })
+        };
+        let (Some(first), Some(last)) = (children.first(), children.last()) else {
+            out.push(text(span));
+            continue;
+        };
+        let inner = first.span().to(last.span());
+        let closing = text(Span {
+            start: inner.end,
+            line: inner.end_line,
+            column: inner.end_column,
+            ..span
+        });
+        let opening = text(Span {
+            end: inner.start,
+            end_line: inner.line,
+            end_column: inner.column,
+            ..span
+        });
+        stack.push(closing);
+        stack.extend(children.into_iter().rev());
+        stack.push(opening);
+    }
+}
+
+fn merge_texts(nodes: Vec<Inline>) -> Vec<Inline> {
+    let mut merged: Vec<Inline> = vec![];
+    for node in nodes {
+        let node = match node {
+            Inline::Emphasis(emphasis) => Inline::Emphasis(Emphasis {
+                children: merge_texts(emphasis.children),
+                span: emphasis.span,
+            }),
+            Inline::Strong(strong) => Inline::Strong(Strong {
+                children: merge_texts(strong.children),
+                span: strong.span,
+            }),
+            Inline::Strikethrough(strikethrough) => Inline::Strikethrough(Strikethrough {
+                children: merge_texts(strikethrough.children),
+                span: strikethrough.span,
+            }),
+            Inline::Highlight(highlight) => Inline::Highlight(Highlight {
+                children: merge_texts(highlight.children),
+                span: highlight.span,
+            }),
+            Inline::Superscript(superscript) => Inline::Superscript(Superscript {
+                children: merge_texts(superscript.children),
+                span: superscript.span,
+            }),
+            Inline::Subscript(subscript) => Inline::Subscript(Subscript {
+                children: merge_texts(subscript.children),
+                span: subscript.span,
+            }),
+            Inline::Link(link) => Inline::Link(Link {
+                children: merge_texts(link.children),
+                ..link
+            }),
+            Inline::Image(image) => Inline::Image(Image {
+                children: merge_texts(image.children),
+                ..image
+            }),
+            Inline::BracketedSpan(bracketed) => Inline::BracketedSpan(BracketedSpan {
+                children: merge_texts(bracketed.children),
+                ..bracketed
+            }),
+            Inline::Text(text) if text.text.is_empty() => continue,
+            node => node,
+        };
+        match (merged.last_mut(), node) {
+            (Some(Inline::Text(last)), Inline::Text(text)) => {
+                last.text.push_str(&text.text);
+                last.span = last.span.to(text.span);
+            }
+            (_, node) => merged.push(node),
+        }
+    }
+    merged
+}
+
+/*
+ * Run `replace` over the Text nodes, including those inside other inlines. The text of links and
+ * images is only gone into when `into_links` is set
+ */
+fn replace_texts(
+    nodes: Vec<Inline>,
+    into_links: bool,
+    replace: &impl Fn(Text) -> Vec<Inline>,
+) -> Vec<Inline> {
+    let recurse = |children| replace_texts(children, into_links, replace);
+    let mut replaced = vec![];
+    for node in nodes {
+        match node {
+            Inline::Text(text) => replaced.extend(replace(text)),
+            Inline::Emphasis(emphasis) => replaced.push(Inline::Emphasis(Emphasis {
+                children: recurse(emphasis.children),
+                span: emphasis.span,
+            })),
+            Inline::Strong(strong) => replaced.push(Inline::Strong(Strong {
+                children: recurse(strong.children),
+                span: strong.span,
+            })),
+            Inline::Strikethrough(strikethrough) => {
+                replaced.push(Inline::Strikethrough(Strikethrough {
+                    children: recurse(strikethrough.children),
+                    span: strikethrough.span,
+                }))
+            }
+            Inline::Highlight(highlight) => replaced.push(Inline::Highlight(Highlight {
+                children: recurse(highlight.children),
+                span: highlight.span,
+            })),
+            Inline::Superscript(superscript) => replaced.push(Inline::Superscript(Superscript {
+                children: recurse(superscript.children),
+                span: superscript.span,
+            })),
+            Inline::Subscript(subscript) => replaced.push(Inline::Subscript(Subscript {
+                children: recurse(subscript.children),
+                span: subscript.span,
+            })),
+            Inline::BracketedSpan(bracketed) => {
+                replaced.push(Inline::BracketedSpan(BracketedSpan {
+                    children: recurse(bracketed.children),
+                    ..bracketed
+                }))
+            }
+            Inline::Link(link) if into_links => replaced.push(Inline::Link(Link {
+                children: recurse(link.children),
+                ..link
+            })),
+            Inline::Image(image) if into_links => replaced.push(Inline::Image(Image {
+                children: recurse(image.children),
+                ..image
+            })),
+            node => replaced.push(node),
+        }
+    }
+    replaced
+}
+
+// Turn the URLs and email addresses in text into links
+fn linkify(mut text: Text) -> Vec<Inline> {
+    let mut linked = vec![];
+    while let Some((start, end, destination)) = autolink::find(&text.text) {
+        let span = sub_span(text.span, &text.text, start, end);
+        if start > 0 {
+            linked.push(Inline::Text(Text {
+                text: text.text[..start].to_string(),
+                span: sub_span(text.span, &text.text, 0, start),
+            }));
+        }
+        linked.push(Inline::Link(Link {
+            destination,
+            title: None,
+            children: vec![Inline::Text(Text {
+                text: text.text[start..end].to_string(),
+                span,
+            })],
+            attributes: elements::Attributes::default(),
+            span,
+        }));
+        text.span = sub_span(text.span, &text.text, end, text.text.len());
+        text.text = text.text[end..].to_string();
+    }
+    if !text.text.is_empty() {
+        linked.push(Inline::Text(text));
+    }
+    linked
+}
+
+// Turn the known emoji shortcodes in text into emoji
+fn emojify(mut text: Text, custom: &HashMap<String, String>) -> Vec<Inline> {
+    let mut replaced = vec![];
+    while let Some((start, end, replacement)) = emoji::find(&text.text, custom) {
+        if start > 0 {
+            replaced.push(Inline::Text(Text {
+                text: text.text[..start].to_string(),
+                span: sub_span(text.span, &text.text, 0, start),
+            }));
+        }
+        replaced.push(Inline::Emoji(Emoji {
+            name: text.text[start + 1..end - 1].to_string(),
+            replacement,
+            span: sub_span(text.span, &text.text, start, end),
+        }));
+        text.span = sub_span(text.span, &text.text, end, text.text.len());
+        text.text = text.text[end..].to_string();
+    }
+    if !text.text.is_empty() {
+        replaced.push(Inline::Text(text));
+    }
+    replaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::lexer::Lexer;
+    use crate::types::options::Extensions;
+
+    // Write out the tree, with emphasis as em(..) and strong as strong(..)
+    fn tree(inlines: &[Inline]) -> String {
+        let mut out = String::new();
+        for inline in inlines {
+            match inline {
+                Inline::Text(text) => out.push_str(&text.text),
+                Inline::AbbreviatedText(abbreviated) => out.push_str(&abbreviated.text.text),
+                Inline::Code(code) => out.push_str(&format!(
+                    "code({}){}",
+                    code.text,
+                    attributes(&code.attributes)
+                )),
+                Inline::Emphasis(emphasis) => {
+                    out.push_str(&format!("em({})", tree(&emphasis.children)))
+                }
+                Inline::Strong(strong) => {
+                    out.push_str(&format!("strong({})", tree(&strong.children)))
+                }
+                Inline::Strikethrough(strikethrough) => {
+                    out.push_str(&format!("del({})", tree(&strikethrough.children)))
+                }
+                Inline::Highlight(highlight) => {
+                    out.push_str(&format!("mark({})", tree(&highlight.children)))
+                }
+                Inline::Superscript(superscript) => {
+                    out.push_str(&format!("sup({})", tree(&superscript.children)))
+                }
+                Inline::Subscript(subscript) => {
+                    out.push_str(&format!("sub({})", tree(&subscript.children)))
+                }
+                Inline::Link(link) => out.push_str(&format!(
+                    "link<{}>({}){}",
+                    link.destination,
+                    tree(&link.children),
+                    attributes(&link.attributes)
+                )),
+                Inline::Image(image) => out.push_str(&format!(
+                    "image<{}>({}){}",
+                    image.source,
+                    tree(&image.children),
+                    attributes(&image.attributes)
+                )),
+                Inline::BracketedSpan(bracketed) => out.push_str(&format!(
+                    "span({}){}",
+                    tree(&bracketed.children),
+                    attributes(&bracketed.attributes)
+                )),
+                Inline::Html(html) => out.push_str(&format!("html({})", html.html)),
+                Inline::Math(math) => match math.display {
+                    true => out.push_str(&format!("dmath({})", math.tex)),
+                    false => out.push_str(&format!("math({})", math.tex)),
+                },
+                Inline::Emoji(emoji) => match &emoji.replacement {
+                    Replacement::Character(character) => out.push_str(character),
+                    Replacement::Image(source) => {
+                        out.push_str(&format!("emoji<{}>({})", source, emoji.name))
+                    }
+                },
+                Inline::FootnoteReference(reference) => {
+                    out.push_str(&format!("fnref({})", reference.label))
+                }
+                Inline::WikiLink(link) => match &link.destination {
+                    Some(destination) => {
+                        out.push_str(&format!("wiki<{}>({})", destination, link.label))
+                    }
+                    None => out.push_str(&format!("wiki({})", link.text)),
+                },
+            }
+        }
+        out
+    }
+
+    // Attributes as they were written, though always in the order id, classes, others
+    fn attributes(attributes: &elements::Attributes) -> String {
+        if attributes.is_empty() {
+            return String::new();
+        }
+        let mut parts = vec![];
+        if let Some(id) = &attributes.id {
+            parts.push(format!("#{}", id));
+        }
+        for class in &attributes.classes {
+            parts.push(format!(".{}", class));
+        }
+        for (name, value) in &attributes.pairs {
+            parts.push(format!("{}={}", name, value));
+        }
+        format!("{{{}}}", parts.join(" "))
+    }
+
+    fn parse_text(source: &str) -> Vec<Inline> {
+        parse_with_options(source, &Options::default())
+    }
+
+    fn parse_with_options(source: &str, options: &Options) -> Vec<Inline> {
+        let mut lexer = Lexer::new(source, options);
+        lexer.scan();
+        let footnotes = Rc::new(RefCell::new(Footnotes::new()));
+        let wiki_links = Rc::new(RefCell::new(WikiLinks::new(&options.wiki_pages)));
+        let abbreviations = Rc::new(RefCell::new(Abbreviations::new()));
+        parse(
+            &lexer.tokens,
+            source,
+            options,
+            &footnotes,
+            &wiki_links,
+            &abbreviations,
+        )
+    }
+
+    fn check(source: &str, expected: &str) {
+        assert_eq!(tree(&parse_text(source)), expected, "parsing {:?}", source);
+    }
+
+    #[test]
+    fn emphasis() {
+        check("*foo bar*", "em(foo bar)");
+        check("_foo bar_", "em(foo bar)");
+        check("**foo bar**", "strong(foo bar)");
+        check("__foo bar__", "strong(foo bar)");
+        check("a * foo bar*", "a * foo bar*");
+        check("*foo*bar", "em(foo)bar");
+    }
+
+    #[test]
+    fn underscores_inside_words() {
+        check("snake_case_word", "snake_case_word");
+        check("foo_bar_", "foo_bar_");
+        check("_foo_bar_baz_", "em(foo_bar_baz)");
+        check("__foo, __bar__, baz__", "strong(foo, strong(bar), baz)");
+        check("пристаням_стремятся_", "пристаням_стремятся_");
+    }
+
+    #[test]
+    fn nesting() {
+        check("***both***", "em(strong(both))");
+        check("**bold *and italic***", "strong(bold em(and italic))");
+        check("*foo **bar** baz*", "em(foo strong(bar) baz)");
+        check("***foo** bar*", "em(strong(foo) bar)");
+        check("*foo**bar**baz*", "em(foostrong(bar)baz)");
+        check("*foo**bar*", "em(foo**bar)");
+        check("_foo __bar__ baz_", "em(foo strong(bar) baz)");
+    }
+
+    #[test]
+    fn unmatched_delimiters() {
+        check("**foo*", "*em(foo)");
+        check("*foo**", "em(foo)*");
+        check("**foo", "**foo");
+        check("*foo_", "*foo_");
+        check("foo*", "foo*");
+    }
+
+    #[test]
+    fn many_delimiters() {
+        // Matching takes linear time, so none of these should take long
+        for source in [
+            "*a".repeat(40_000),
+            "a*".repeat(40_000),
+            "*a **b _c".repeat(10_000),
+            "~~a ==b ^c".repeat(10_000),
+        ] {
+            let start = std::time::Instant::now();
+            parse_text(&source);
+            let elapsed = start.elapsed();
+            assert!(elapsed.as_secs() < 2, "{:?} for {}", elapsed, &source[..10]);
+        }
+        check("*a*a*a*a", "em(a)aem(a)a");
+        check("**a*b***", "strong(aem(b))");
+    }
+
+    #[test]
+    fn code_spans_hide_delimiters() {
+        check("*a `*` b*", "em(a code(*) b)");
+        check("`code", "`code");
+    }
+
+    #[test]
+    fn multiple_backticks() {
+        check("``foo`bar``", "code(foo`bar)");
+        check("` `` `", "code(``)");
+        check("`  ``  `", "code( `` )");
+        check("` a`", "code( a)");
+        check("`  `", "code(  )");
+        check("```a code block`", "```a code block`");
+        check("```foo``", "```foo``");
+        check("`foo``bar``", "`foocode(bar)");
+        check("`foo\\`bar`", "code(foo\\)bar`");
+        check("``foo\\``", "code(foo\\)");
+        check("*foo`*`", "*foocode(*)");
+    }
+
+    #[test]
+    fn inline_extensions() {
+        check(
+            "~~gone~~ ==marked== x^2^ H~2~O",
+            "del(gone) mark(marked) xsup(2) Hsub(2)O",
+        );
+        check("~~*a ~b~*~~", "del(em(a sub(b)))");
+        check("**==*a*==**", "strong(mark(em(a)))");
+        check("~~a~ ~b~ ~~~c~~~", "~~a~ sub(b) ~~~c~~~");
+        check("=a= ==b === ^^c^^", "=a= ==b === ^^c^^");
+        check("~~ a~~ 2 ^ 3 ^", "~~ a~~ 2 ^ 3 ^");
+        check("`~~a~~` ~~`b`~~", "code(~~a~~) del(code(b))");
+    }
+
+    #[test]
+    fn inline_extensions_disabled() {
+        let parse_with = |source: &str, extensions: Extensions| {
+            let options = Options {
+                extensions,
+                ..Options::default()
+            };
+            tree(&parse_with_options(source, &options))
+        };
+        let source = "~~a~~ ~b~ ==c== ^d^";
+        assert_eq!(parse_with(source, Extensions::none()), source);
+        let strikethrough = Extensions {
+            strikethrough: true,
+            ..Extensions::none()
+        };
+        assert_eq!(parse_with(source, strikethrough), "del(a) del(b) ==c== ^d^");
+        let others = Extensions {
+            highlight: true,
+            superscript: true,
+            subscript: true,
+            ..Extensions::none()
+        };
+        assert_eq!(parse_with(source, others), "~~a~~ sub(b) mark(c) sup(d)");
+    }
+
+    #[test]
+    fn footnote_references() {
+        check("a[^1] *b[^note]*", "afnref(1) em(bfnref(note))");
+        check("[^a b] [link](x)[^2]", "[^a b] link<x>(link)fnref(2)");
+    }
+
+    #[test]
+    fn autolinks() {
+        check(
+            "<https://example.com/a_b_> <me@example.com>",
+            "link<https://example.com/a_b_>(https://example.com/a_b_) \
+             link<mailto:me@example.com>(me@example.com)",
+        );
+        check(
+            "see *www.example.com/x*, or (https://a.b/c).",
+            "see em(link<http://www.example.com/x>(www.example.com/x)), \
+             or (link<https://a.b/c>(https://a.b/c)).",
+        );
+        // The underscores split the text up, which has to be put back together inside ~~ too
+        check(
+            "~~see https://a.com/x_y_z~~ ==https://a.com/x_y_z==",
+            "del(see link<https://a.com/x_y_z>(https://a.com/x_y_z)) \
+             mark(link<https://a.com/x_y_z>(https://a.com/x_y_z))",
+        );
+        check(
+            "[https://a.b](x) `https://a.b`",
+            "link<x>(https://a.b) code(https://a.b)",
+        );
+        check(
+            "<a+b:c> <a b@c.d>",
+            "link<a+b:c>(a+b:c) <a link<mailto:b@c.d>(b@c.d)>",
+        );
+
+        let inlines = parse_text("at www.a.b");
+        assert_eq!((inlines[1].span().start, inlines[1].span().end), (3, 10));
+        let options = Options {
+            extensions: Extensions::none(),
+            ..Options::default()
+        };
+        assert_eq!(
+            tree(&parse_with_options("<https://a.b> https://a.b", &options)),
+            "link<https://a.b>(https://a.b) https://a.b"
+        );
+    }
+
+    #[test]
+    fn math() {
+        check(
+            "$x^2$ and $$\\frac{a}{b}$$",
+            "math(x^2) and dmath(\\frac{a}{b})",
+        );
+        check("$a_1 *b* c_2$ `$x$`", "math(a_1 *b* c_2) code($x$)");
+        check("$\\$ 5$ \\$x$", "math(\\$ 5) $x$");
+        // Prices are left alone
+        check("$5 and $10", "$5 and $10");
+        check("costs $5, $6 or $7.", "costs $5, $6 or $7.");
+        check("$ x$ $x $ $x$5 $$ x", "$ x$ $x $ $x$5 $$ x");
+    }
+
+    #[test]
+    fn inline_attributes() {
+        check(
+            "![a](b.png){.wide #hero} [c](/d){target=_blank}",
+            "image<b.png>(a){#hero .wide} link</d>(c){target=_blank}",
+        );
+        check(
+            "`x`{.rust} [*key* word]{.kbd} [plain] [no]{}",
+            "code(x){.rust} span(em(key) word){.kbd} [plain] [no]{}",
+        );
+        // Only straight after the inline
+        check("`x` {.rust}", "code(x) {.rust}");
+        let options = Options {
+            extensions: Extensions {
+                attributes: false,
+                ..Extensions::default()
+            },
+            ..Options::default()
+        };
+        assert_eq!(
+            tree(&parse_with_options("`x`{.a} [b]{.c}", &options)),
+            "code(x){.a} [b]{.c}"
+        );
+    }
+
+    #[test]
+    fn wiki_links() {
+        let mut options = Options::default();
+        options
+            .wiki_pages
+            .insert("Getting Started".to_string(), "start.html".to_string());
+        let tree = |source| tree(&parse_with_options(source, &options));
+        assert_eq!(
+            tree("See [[getting started]] or [[Getting_Started|the guide]]."),
+            "See wiki<start.html>(getting started) or wiki<start.html>(the guide)."
+        );
+        assert_eq!(tree("[[Missing]] [[a [b]]"), "wiki([[Missing]]) [[a [b]]");
+        assert_eq!(tree("`[[Getting Started]]`"), "code([[Getting Started]])");
+    }
+
+    #[test]
+    fn emoji() {
+        check(
+            "Shipped :rocket: :not_an_emoji: *:tada:*",
+            "Shipped 🚀 :not_an_emoji: em(🎉)",
+        );
+        check("`:rocket:` [:+1:](/ok)", "code(:rocket:) link</ok>(👍)");
+        let mut options = Options::default();
+        options
+            .custom_emoji
+            .insert("shipit".to_string(), "/emoji/shipit.png".to_string());
+        assert_eq!(
+            tree(&parse_with_options(":shipit: :rocket:", &options)),
+            "emoji</emoji/shipit.png>(shipit) 🚀"
+        );
+        options.extensions.emoji = false;
+        assert_eq!(tree(&parse_with_options(":rocket:", &options)), ":rocket:");
+    }
+
+    #[test]
+    fn smart_punctuation() {
+        let options = Options {
+            extensions: Extensions {
+                smart_punctuation: true,
+                ..Extensions::default()
+            },
+            ..Options::default()
+        };
+        let smart = |source: &str| tree(&parse_with_options(source, &options));
+        assert_eq!(
+            smart("\"*Don't*\" -- she said... `\"code\" --` $a--b$"),
+            "“em(Don’t)” – she said… code(\"code\" --) math(a--b)"
+        );
+        assert_eq!(
+            smart("*\"a\"* [\"b\"](x \"t\") \\\"c\\\" <a href=\"d\">"),
+            "em(“a”) link<x>(“b”) \"c\" html(<a href=\"d\">)"
+        );
+        assert_eq!(smart("it's 'quoted' --- x"), "it’s ‘quoted’ — x");
+        assert_eq!(tree(&parse_text("\"a\" -- b...")), "\"a\" -- b...");
+    }
+
+    #[test]
+    fn raw_html() {
+        check("a <b>bold</b> c", "a html(<b>)boldhtml(</b>) c");
+        check(
+            "<a href=\"x_*y*_\">*em*</a>",
+            "html(<a href=\"x_*y*_\">)em(em)html(</a>)",
+        );
+        check("<a title=\"[x]\">", "html(<a title=\"[x]\">)");
+        check("x <!-- *not em* --> y", "x html(<!-- *not em* -->) y");
+        check("1 < 2 and <33> <a/", "1 < 2 and <33> <a/");
+        check("`<b>` <i>", "code(<b>) html(<i>)");
+        check("<a href=\"`\">`", "html(<a href=\"`\">)`");
+        check("<img alt=\"\\*\">", "html(<img alt=\"\\*\">)");
+
+        let inlines = parse_text("ab <i>");
+        assert_eq!((inlines[1].span().start, inlines[1].span().end), (3, 6));
+        let options = Options {
+            extensions: Extensions::none(),
+            ..Options::default()
+        };
+        assert_eq!(tree(&parse_with_options("a <b>", &options)), "a <b>");
+    }
+
+    #[test]
+    fn spans() {
+        let inlines = parse_text("a **b**");
+        let strong = &inlines[1];
+        assert_eq!((strong.span().start, strong.span().end), (2, 7));
+        match strong {
+            Inline::Strong(strong) => {
+                let text = strong.children[0].span();
+                assert_eq!((text.start, text.end, text.column), (4, 5, 4));
+            }
+            _ => panic!("expected strong emphasis"),
+        }
+    }
+
+    #[test]
+    fn links() {
+        check("[foo](/url)", "link</url>(foo)");
+        check("[foo](/url \"title\")", "link</url>(foo)");
+        check("[foo](<my url>)", "link<my%20url>(foo)");
+        check("[foo](a(b)c)", "link<a(b)c>(foo)");
+        check("[foo]()", "link<>(foo)");
+        check("[foo] (bar)", "[foo] (bar)");
+        check("[foo](bar", "[foo](bar");
+        check("![alt](/img.png)", "image</img.png>(alt)");
+        check("wow![alt](/img.png)", "wowimage</img.png>(alt)");
+        // Escaped characters don't end the destination or title
+        check("[foo](/a\\)b&amp;c)", "link</a)b&c>(foo)");
+        check("[foo](/a \"\\\"\")", "link</a>(foo)");
+        check("[foo](/a (b(c)))", "[foo](/a (b(c)))");
+    }
+
+    #[test]
+    fn many_links() {
+        // Each destination is only read as far as it could go, so none of these should take long
+        for source in [
+            "[a](".repeat(20_000),
+            "[a](b (".repeat(20_000),
+            "*[a](b)".repeat(20_000),
+        ] {
+            let start = std::time::Instant::now();
+            parse_text(&source);
+            let elapsed = start.elapsed();
+            assert!(elapsed.as_secs() < 2, "{:?} for {}", elapsed, &source[..10]);
+        }
+    }
+
+    #[test]
+    fn deep_nesting() {
+        // Past the limit the delimiters are text, rather than running the renderer out of stack
+        check(
+            &format!(
+                "{}b{}",
+                "*a ".repeat(MAX_NESTING + 1),
+                " c*".repeat(MAX_NESTING + 1)
+            ),
+            &format!(
+                "{}*a b c*{}",
+                "em(a ".repeat(MAX_NESTING),
+                " c)".repeat(MAX_NESTING)
+            ),
+        );
+        for (open, close, node) in [
+            ("*a ", " c*", "em("),
+            ("~~a ", " c~~", "del("),
+            ("![a", "](b)", "image<b>("),
+            ("[a", "]{.c}", "span("),
+        ] {
+            let source = format!("{}b{}", open.repeat(20_000), close.repeat(20_000));
+            let tree = tree(&parse_text(&source));
+            assert_eq!(tree.matches(node).count(), MAX_NESTING, "nesting {}", open);
+        }
+        // Smart quotes look back into the image before them, which isn't limited yet
+        let options = Options {
+            extensions: Extensions {
+                smart_punctuation: true,
+                ..Extensions::default()
+            },
+            ..Options::default()
+        };
+        let source = format!("{}b{}'", "![a".repeat(20_000), "](b)".repeat(20_000));
+        assert!(tree(&parse_with_options(&source, &options)).ends_with('’'));
+    }
+
+    #[test]
+    fn links_nest_with_other_inlines() {
+        check(
+            "**a [link `code` *em*](/url)**",
+            "strong(a link</url>(link code(code) em(em)))",
+        );
+        check("*[foo*](/url)", "*link</url>(foo*)");
+        check("[foo [bar](/a)](/b)", "[foo link</a>(bar)](/b)");
+        check("![foo [bar](/a)](/b)", "image</b>(foo link</a>(bar))");
+    }
+
+    #[test]
+    fn link_spans() {
+        let inlines = parse_text("see [a](b) now");
+        let link = inlines[1].span();
+        assert_eq!((link.start, link.end), (4, 10));
+        let after = inlines[2].span();
+        assert_eq!((after.start, after.end, after.column), (10, 14, 10));
+    }
+}
@@ -7,6 +7,10 @@
  */
 use std::{iter::Peekable, str::Chars};
 
+use super::entities;
+use crate::types::options::Options;
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Clone)]
 pub enum TokenType {
     TEXT,
@@ -14,13 +18,71 @@ pub enum TokenType {
     HASH,
     BACKTICK,
     ASTERISK,
+    UNDERSCORE,
+    LBRACKET,
+    RBRACKET,
+    // A backslash escaped punctuation character, the value is the character itself
+    ESCAPE,
+    // An HTML entity such as &copy; the value is the text it stands for
+    ENTITY,
+    // The first four columns of whitespace at the start of a line
+    INDENT,
+    // Strikethrough and subscript
+    TILDE,
+    // Highlight
+    EQUALS,
+    // Superscript
+    CARET,
+    // Math
+    DOLLAR,
+}
+
+/*
+ * A region of the source: the byte range it covers, and the line and column (counted in
+ * characters, both starting at 0) where it begins and ends. The end is exclusive, just like the
+ * byte range
+ */
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: u32,
+    pub column: u32,
+    pub end_line: u32,
+    pub end_column: u32,
+}
+
+impl Span {
+    // The span from the start of self to the end of other
+    pub fn to(&self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+            line: self.line,
+            column: self.column,
+            end_line: other.end_line,
+            end_column: other.end_column,
+        }
+    }
+
+    // An empty span at the end of self
+    pub fn end_point(&self) -> Span {
+        Span {
+            start: self.end,
+            end: self.end,
+            line: self.end_line,
+            column: self.end_column,
+            end_line: self.end_line,
+            end_column: self.end_column,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Token {
     pub token_type: TokenType,
     pub value: String,
-    location: (u8, u8),
+    pub span: Span,
 }
 
 /*
@@ -31,152 +93,275 @@ pub struct Token {
 pub struct Lexer {
     pub tokens: Vec<Token>,
     input_string: String,
-    position: (u8, u8), //Line, index
+    position: Span,
+    options: Options,
 }
 
 impl Lexer {
     // Read file contents, and construct the struct to get ready for lexing
-    pub fn new(source: &str) -> Self {
-        let string = source.to_string();
+    pub fn new(source: &str, options: &Options) -> Self {
         Lexer {
-            input_string: string.clone(),
+            input_string: source.to_string(),
             tokens: Vec::new(),
-            position: (0, 0),
+            position: Span::default(),
+            options: options.clone(),
         }
     }
 
+    // The text the tokens were generated from
+    pub fn source(&self) -> &str {
+        &self.input_string
+    }
+
     // Main lexing loop. Read the input string to create a stream of tokens
     pub fn scan(&mut self) {
         let string = self.input_string.clone();
         let mut chars = string.chars().peekable();
 
-        loop {
-            let character = chars.next();
-            match character {
-                Some(c) => {
-                    // What token are we currently reading?
-                    self.scan_token(c, &mut chars);
-                }
-                None => break,
+        while let Some(c) = chars.next() {
+            if self.position.column == 0 && self.indentation(c, &mut chars) {
+                continue;
+            }
+            // What token are we currently reading?
+            self.scan_token(c, &mut chars);
+        }
+    }
+
+    // Characters that end a TEXT token, because they may start a token of their own
+    fn is_special(&self, c: char) -> bool {
+        match c {
+            '\n' | '#' | '*' | '_' | '`' | '[' | ']' | '\\' | '&' => true,
+            '~' => self.options.extensions.strikethrough || self.options.extensions.subscript,
+            '=' => self.options.extensions.highlight,
+            '^' => self.options.extensions.superscript,
+            '$' => self.options.extensions.math,
+            _ => false,
+        }
+    }
+
+    // Move the current position past a character
+    fn advance(&mut self, c: char) {
+        self.position.end += c.len_utf8();
+        if c == '\n' {
+            self.position.end_line += 1;
+            self.position.end_column = 0;
+        } else {
+            self.position.end_column += 1;
+        }
+    }
+
+    // Add a token covering everything read since the previous token
+    fn push(&mut self, token_type: TokenType, value: String) {
+        let span = self.position;
+        self.tokens.push(Token {
+            token_type,
+            value,
+            span,
+        });
+        self.position = span.end_point();
+    }
+
+    /*
+     * Read the whitespace at the start of a line, starting with c. If it is four columns wide or
+     * more, with a tab reaching to the next multiple of four, the first four columns become an
+     * INDENT token and the rest is left for scan_token
+     */
+    fn indentation(&mut self, c: char, iter: &mut Peekable<Chars>) -> bool {
+        let mut lookahead = iter.clone();
+        let mut whitespace = String::new();
+        let mut columns = 0;
+        let mut next = Some(c);
+        while let Some(c @ (' ' | '\t')) = next {
+            columns += if c == '\t' { 4 - columns % 4 } else { 1 };
+            whitespace.push(c);
+            if columns >= 4 {
+                break;
             }
+            next = lookahead.next();
         }
+        if columns < 4 {
+            return false;
+        }
+        for (index, c) in whitespace.chars().enumerate() {
+            self.advance(c);
+            // The first character has already been taken from the iterator
+            if index > 0 {
+                iter.next();
+            }
+        }
+        self.push(TokenType::INDENT, whitespace);
+        true
     }
 
     // The big switch case. Match the string to a particular token
     // Certain tokens/chars lead to extra actions, such as the new line char, which will modifiy
     // self.position
     fn scan_token(&mut self, token: char, iter: &mut Peekable<Chars>) {
+        self.advance(token);
         match token {
             // Headers
-            '#' => {
-                let token = Token {
-                    token_type: TokenType::HASH,
-                    value: token.to_string(),
-                    location: self.position,
-                };
-                self.position.1 += 1;
-                self.tokens.push(token);
-            }
-
+            '#' => self.push(TokenType::HASH, token.to_string()),
             // Asterisk - bold or italic statement
-            '*' => {
-                let token = Token {
-                    token_type: TokenType::ASTERISK,
-                    value: token.to_string(),
-                    location: self.position,
-                };
-                self.position.1 += 1;
-                self.tokens.push(token);
-            }
+            '*' => self.push(TokenType::ASTERISK, token.to_string()),
+            // Underscore - also bold or italic
+            '_' => self.push(TokenType::UNDERSCORE, token.to_string()),
+            // Brackets - link text
+            '[' => self.push(TokenType::LBRACKET, token.to_string()),
+            ']' => self.push(TokenType::RBRACKET, token.to_string()),
             // Backtick - in line code
-            '`' => {
-                let token = Token {
-                    token_type: TokenType::BACKTICK,
-                    value: token.to_string(),
-                    location: self.position,
-                };
-                self.position.1 += 1;
-                self.tokens.push(token);
-            }
+            '`' => self.push(TokenType::BACKTICK, token.to_string()),
+            // Tilde, equals and caret - the inline extensions
+            '~' if self.is_special(token) => self.push(TokenType::TILDE, token.to_string()),
+            '=' if self.is_special(token) => self.push(TokenType::EQUALS, token.to_string()),
+            '^' if self.is_special(token) => self.push(TokenType::CARET, token.to_string()),
+            // Dollar - math
+            '$' if self.is_special(token) => self.push(TokenType::DOLLAR, token.to_string()),
             // New line
-            '\n' => {
-                let token = Token {
-                    token_type: TokenType::NEWLINE,
-                    value: token.to_string(),
-                    location: self.position,
-                };
-                self.position.0 += 1;
-                self.position.1 = 0;
-                self.tokens.push(token);
-            }
+            '\n' => self.push(TokenType::NEWLINE, token.to_string()),
+            // Backslash - any ASCII punctuation after it is just text
+            '\\' => match iter.peek() {
+                Some(&c) if c.is_ascii_punctuation() => {
+                    self.advance(c);
+                    iter.next();
+                    self.push(TokenType::ESCAPE, c.to_string());
+                }
+                _ => self.push(TokenType::TEXT, token.to_string()),
+            },
+            // Ampersand - might start an entity
+            '&' => match entity(iter) {
+                Some((reference, text)) => {
+                    for c in reference.chars() {
+                        self.advance(c);
+                        iter.next();
+                    }
+                    self.push(TokenType::ENTITY, text);
+                }
+                None => self.push(TokenType::TEXT, token.to_string()),
+            },
 
             // Just strings
             _ => {
                 let mut text = String::from(token);
-                let mut position = 1;
-                let specials = vec!['\n', '*', '_', '`'];
-                loop {
-                    let peeked = iter.peek();
-                    match peeked {
-                        Some(peek) => {
-                            if specials.contains(peek) {
-                                break;
-                            }
-                            text.push(peek.to_owned());
-                            position += 1;
-                            iter.next();
-                        }
-                        None => {
-                            break;
-                        }
-                    };
+                while let Some(peek) = iter.peek() {
+                    if self.is_special(*peek) {
+                        break;
+                    }
+                    let c = *peek;
+                    text.push(c);
+                    self.advance(c);
+                    iter.next();
                 }
-                let token = Token {
-                    token_type: TokenType::TEXT,
-                    value: text,
-                    location: self.position,
-                };
-                self.position.1 += position;
-                self.tokens.push(token);
+                self.push(TokenType::TEXT, text);
             }
         }
     }
 }
 
+/*
+ * Decode the backslash escapes and entities in text taken straight from the source, such as a link
+ * destination
+ */
+pub fn unescape(text: &str) -> String {
+    let mut out = String::new();
+    let mut iter = text.chars().peekable();
+    while let Some(c) = iter.next() {
+        match c {
+            '\\' => match iter.next_if(|next| next.is_ascii_punctuation()) {
+                Some(escaped) => out.push(escaped),
+                None => out.push(c),
+            },
+            '&' => match entity(&iter) {
+                Some((reference, text)) => {
+                    out.push_str(&text);
+                    for _ in reference.chars() {
+                        iter.next();
+                    }
+                }
+                None => out.push(c),
+            },
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/*
+ * Read the entity following an ampersand without moving the iterator, e.g. `copy;`, `#169;` or
+ * `#xA9;`. Returns the characters making up the reference and the text it stands for
+ */
+fn entity(iter: &Peekable<Chars>) -> Option<(String, String)> {
+    let mut reference = String::new();
+    // The longest entity names are a little over 30 characters
+    for c in iter.clone().take(40) {
+        reference.push(c);
+        if c == ';' {
+            break;
+        }
+        if !c.is_ascii_alphanumeric() && c != '#' {
+            return None;
+        }
+    }
+    let name = reference.strip_suffix(';')?;
+    let text = match name.strip_prefix('#') {
+        Some(number) => entities::decode_numeric(number)?.to_string(),
+        None => entities::lookup(name)?.to_string(),
+    };
+    Some((reference, text))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    // Span of a token which doesn't cross a line
+    fn span(start: usize, end: usize, line: u32, column: u32) -> Span {
+        Span {
+            start,
+            end,
+            line,
+            column,
+            end_line: line,
+            end_column: column + (end - start) as u32,
+        }
+    }
+
     #[test]
     fn init() {
         let source = "## This is a heading\nI am a bunch of paragraph text. I can get pretty long.";
-        let mut scanner = Lexer::new(source);
+        let mut scanner = Lexer::new(source, &Options::default());
         scanner.scan();
-        let tokens = vec![
+        let tokens = [
             Token {
                 token_type: TokenType::HASH,
                 value: "#".to_string(),
-                location: (0, 0),
+                span: span(0, 1, 0, 0),
             },
             Token {
                 token_type: TokenType::HASH,
                 value: "#".to_string(),
-                location: (0, 1),
+                span: span(1, 2, 0, 1),
             },
             Token {
                 token_type: TokenType::TEXT,
                 value: " This is a heading".to_string(),
-                location: (0, 2),
+                span: span(2, 20, 0, 2),
             },
             Token {
                 token_type: TokenType::NEWLINE,
                 value: "\n".to_string(),
-                location: (0, 20),
+                span: Span {
+                    start: 20,
+                    end: 21,
+                    line: 0,
+                    column: 20,
+                    end_line: 1,
+                    end_column: 0,
+                },
             },
             Token {
                 token_type: TokenType::TEXT,
                 value: "I am a bunch of paragraph text. I can get pretty long.".to_string(),
-                location: (1, 0),
+                span: span(21, 75, 1, 0),
             },
         ];
         assert_eq!(tokens.len(), scanner.tokens.len());
@@ -187,28 +372,28 @@ mod tests {
     #[test]
     fn italics() {
         let source = "I am *italics*";
-        let mut scanner = Lexer::new(source);
+        let mut scanner = Lexer::new(source, &Options::default());
         scanner.scan();
-        let tokens = vec![
+        let tokens = [
             Token {
                 token_type: TokenType::TEXT,
                 value: "I am ".to_string(),
-                location: (0, 0),
+                span: span(0, 5, 0, 0),
             },
             Token {
                 token_type: TokenType::ASTERISK,
                 value: "*".to_string(),
-                location: (0, 5),
+                span: span(5, 6, 0, 5),
             },
             Token {
                 token_type: TokenType::TEXT,
                 value: "italics".to_string(),
-                location: (0, 6),
+                span: span(6, 13, 0, 6),
             },
             Token {
                 token_type: TokenType::ASTERISK,
                 value: "*".to_string(),
-                location: (0, 13),
+                span: span(13, 14, 0, 13),
             },
         ];
         assert_eq!(tokens.len(), scanner.tokens.len());
@@ -220,38 +405,38 @@ mod tests {
     #[test]
     fn bold() {
         let source = "I am **bold**";
-        let mut scanner = Lexer::new(source);
+        let mut scanner = Lexer::new(source, &Options::default());
         scanner.scan();
-        let tokens = vec![
+        let tokens = [
             Token {
                 token_type: TokenType::TEXT,
                 value: "I am ".to_string(),
-                location: (0, 0),
+                span: span(0, 5, 0, 0),
             },
             Token {
                 token_type: TokenType::ASTERISK,
                 value: "*".to_string(),
-                location: (0, 5),
+                span: span(5, 6, 0, 5),
             },
             Token {
                 token_type: TokenType::ASTERISK,
                 value: "*".to_string(),
-                location: (0, 6),
+                span: span(6, 7, 0, 6),
             },
             Token {
                 token_type: TokenType::TEXT,
                 value: "bold".to_string(),
-                location: (0, 7),
+                span: span(7, 11, 0, 7),
             },
             Token {
                 token_type: TokenType::ASTERISK,
                 value: "*".to_string(),
-                location: (0, 11),
+                span: span(11, 12, 0, 11),
             },
             Token {
                 token_type: TokenType::ASTERISK,
                 value: "*".to_string(),
-                location: (0, 12),
+                span: span(12, 13, 0, 12),
             },
         ];
         assert_eq!(tokens.len(), scanner.tokens.len());
@@ -263,28 +448,28 @@ mod tests {
     #[test]
     fn code() {
         let source = "I am `code`";
-        let mut scanner = Lexer::new(source);
+        let mut scanner = Lexer::new(source, &Options::default());
         scanner.scan();
-        let tokens = vec![
+        let tokens = [
             Token {
                 token_type: TokenType::TEXT,
                 value: "I am ".to_string(),
-                location: (0, 0),
+                span: span(0, 5, 0, 0),
             },
             Token {
                 token_type: TokenType::BACKTICK,
                 value: "`".to_string(),
-                location: (0, 5),
+                span: span(5, 6, 0, 5),
             },
             Token {
                 token_type: TokenType::TEXT,
                 value: "code".to_string(),
-                location: (0, 6),
+                span: span(6, 10, 0, 6),
             },
             Token {
                 token_type: TokenType::BACKTICK,
                 value: "`".to_string(),
-                location: (0, 10),
+                span: span(10, 11, 0, 10),
             },
         ];
         assert_eq!(tokens.len(), scanner.tokens.len());
@@ -292,4 +477,111 @@ mod tests {
             assert_eq!(tokens.get(index).unwrap(), token);
         }
     }
+
+    #[test]
+    fn indentation() {
+        let source = "    a\n  \tb\n      c\n   d\n\t\n";
+        let mut scanner = Lexer::new(source, &Options::default());
+        scanner.scan();
+        let tokens: Vec<(TokenType, &str)> = scanner
+            .tokens
+            .iter()
+            .map(|t| (t.token_type.clone(), t.value.as_str()))
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                (TokenType::INDENT, "    "),
+                (TokenType::TEXT, "a"),
+                (TokenType::NEWLINE, "\n"),
+                (TokenType::INDENT, "  \t"),
+                (TokenType::TEXT, "b"),
+                (TokenType::NEWLINE, "\n"),
+                (TokenType::INDENT, "    "),
+                (TokenType::TEXT, "  c"),
+                (TokenType::NEWLINE, "\n"),
+                (TokenType::TEXT, "   d"),
+                (TokenType::NEWLINE, "\n"),
+                (TokenType::INDENT, "\t"),
+                (TokenType::NEWLINE, "\n"),
+            ]
+        );
+        assert_eq!(scanner.tokens[7].span, span(15, 18, 2, 4));
+    }
+
+    #[test]
+    fn escapes_and_entities() {
+        let source = "\\*a\\b &amp; &#x41; &nope;";
+        let mut scanner = Lexer::new(source, &Options::default());
+        scanner.scan();
+        let tokens: Vec<(TokenType, &str)> = scanner
+            .tokens
+            .iter()
+            .map(|t| (t.token_type.clone(), t.value.as_str()))
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                (TokenType::ESCAPE, "*"),
+                (TokenType::TEXT, "a"),
+                (TokenType::TEXT, "\\"),
+                (TokenType::TEXT, "b "),
+                (TokenType::ENTITY, "&"),
+                (TokenType::TEXT, " "),
+                (TokenType::ENTITY, "A"),
+                (TokenType::TEXT, " "),
+                (TokenType::TEXT, "&"),
+                (TokenType::TEXT, "nope;"),
+            ]
+        );
+        assert_eq!(scanner.tokens[4].span, span(6, 11, 0, 6));
+    }
+
+    #[test]
+    fn long_document() {
+        let source = "line\n".repeat(5000);
+        let mut scanner = Lexer::new(&source, &Options::default());
+        scanner.scan();
+        assert_eq!(scanner.tokens.len(), 10000);
+        let last = scanner.tokens.last().unwrap();
+        assert_eq!(last.token_type, TokenType::NEWLINE);
+        assert_eq!(
+            last.span,
+            Span {
+                start: 24999,
+                end: 25000,
+                line: 4999,
+                column: 4,
+                end_line: 5000,
+                end_column: 0,
+            }
+        );
+        let text = &scanner.tokens[9998];
+        assert_eq!(text.span, span(24995, 24999, 4999, 0));
+    }
+
+    #[test]
+    fn long_line() {
+        let source = format!("{}*é*", "a".repeat(1000));
+        let mut scanner = Lexer::new(&source, &Options::default());
+        scanner.scan();
+        let spans: Vec<Span> = scanner.tokens.iter().map(|t| t.span).collect();
+        assert_eq!(
+            spans,
+            [
+                span(0, 1000, 0, 0),
+                span(1000, 1001, 0, 1000),
+                // Columns count characters, the byte range doesn't
+                Span {
+                    start: 1001,
+                    end: 1003,
+                    line: 0,
+                    column: 1001,
+                    end_line: 0,
+                    end_column: 1002,
+                },
+                span(1003, 1004, 0, 1002),
+            ]
+        );
+    }
 }
@@ -1,2 +1,15 @@
+pub mod abbreviations;
+pub mod attributes;
+pub mod autolink;
+pub mod emoji;
+pub mod entities;
+pub mod footnotes;
+pub mod front_matter;
+pub mod html;
+pub mod inline;
 pub mod lexer;
+#[allow(clippy::module_inception)]
 pub mod parser;
+pub mod slug;
+pub mod smart;
+pub mod wiki;
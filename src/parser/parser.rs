@@ -5,100 +5,424 @@
  *
  *
  */
-use std::iter::Peekable;
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
 
-use super::lexer::{Lexer, Token, TokenType};
+use super::abbreviations::{self, Abbreviations};
+use super::attributes;
+use super::footnotes::{self, Definition, Footnotes};
+use super::front_matter::{self, FrontMatter};
+use super::html::BlockKind;
+use super::inline::{self, Inline, Text};
+use super::lexer::{Lexer, Span, Token, TokenType};
+use super::slug::Slugger;
+use super::wiki::WikiLinks;
 use crate::types::elements;
+use crate::types::options::Options;
+use crate::types::page::{PageData, TaskCounts};
 
-pub static CODE_STYLE: u8 = 0b100;
-pub static BOLD_STYLE: u8 = 0b010;
-pub static ITALIC_STYLE: u8 = 0b001;
-
-struct Text {
-    token: Token,
-    text: String,
-    style: u8,
-}
+// Blocks can only be nested this deep, so that no document can run the parser out of stack
+pub const MAX_NESTING: usize = 32;
 
 struct Paragraph {
-    texts: Vec<Text>,
+    inlines: Vec<Inline>,
+    span: Span,
 }
 
 struct CodeBlock {
     texts: Vec<Text>,
+    span: Span,
 }
 
-impl Into<elements::CodeBlock> for &CodeBlock {
-    fn into(self) -> elements::CodeBlock {
+impl From<&CodeBlock> for elements::CodeBlock {
+    fn from(block: &CodeBlock) -> Self {
         let mut para = elements::CodeBlock::new();
-        for text in &self.texts {
-            let s = text.text.clone();
-            let style = text.style;
-            let text = elements::Text {
-                text: s,
-                style: style,
-            };
-            para.texts.push(text);
+        for text in &block.texts {
+            para.texts.push(elements::Text::new(&text.text));
         }
-        return para;
+        para.span = Some(block.span);
+        para
     }
 }
 
-impl Into<elements::Paragraph> for &Paragraph {
-    fn into(self) -> elements::Paragraph {
+impl From<&Paragraph> for elements::Paragraph {
+    fn from(paragraph: &Paragraph) -> Self {
         let mut para = elements::Paragraph::new();
-        for text in &self.texts {
-            let s = text.text.clone();
-            let style = text.style;
-            let text = elements::Text {
-                text: s,
-                style: style,
-            };
-            para.texts.push(text);
-        }
-        return para;
+        para.children = inline::convert_inlines(&paragraph.inlines);
+        para.span = Some(paragraph.span);
+        para
     }
 }
 
 struct Heading {
     level: u8,
-    text: Paragraph,
+    inlines: Vec<Inline>,
+    // Set when the heading_ids extension is on, or given in the heading's attributes
+    id: Option<String>,
+    attributes: elements::Attributes,
+    span: Span,
+}
+impl From<&Heading> for elements::Heading {
+    fn from(heading: &Heading) -> Self {
+        let mut element = elements::Heading::new(heading.level);
+        element.children = inline::convert_inlines(&heading.inlines);
+        element.id = heading.id.clone();
+        element.attributes = heading.attributes.clone();
+        element.span = Some(heading.span);
+        element
+    }
+}
+
+// A block of raw HTML, kept exactly as it is in the source
+struct HtmlBlock {
+    html: String,
+    span: Span,
+}
+
+impl From<&HtmlBlock> for elements::Html {
+    fn from(block: &HtmlBlock) -> Self {
+        elements::Html {
+            html: block.html.clone(),
+            span: Some(block.span),
+        }
+    }
+}
+
+// A thematic break, the line between sections
+struct ThematicBreak {
+    span: Span,
+}
+
+impl From<&ThematicBreak> for elements::ThematicBreak {
+    fn from(rule: &ThematicBreak) -> Self {
+        elements::ThematicBreak {
+            attributes: elements::Attributes::default(),
+            span: Some(rule.span),
+        }
+    }
+}
+
+// A bullet or ordered list, with start set when an ordered list doesn't start at 1
+struct List {
+    ordered: bool,
+    start: Option<u64>,
+    items: Vec<ListItem>,
+    // Tight lists have no blank lines between their items, and their text goes without <p>
+    tight: bool,
+    span: Span,
+}
+
+struct ListItem {
+    children: Node,
+    task: Option<bool>,
+}
+
+impl From<&List> for elements::List {
+    fn from(list: &List) -> Self {
+        let items = list
+            .items
+            .iter()
+            .map(|item| elements::ListItem {
+                children: item
+                    .children
+                    .children
+                    .iter()
+                    .map(|child| match list.tight {
+                        true => child.item.convert_to_tight_renderable(),
+                        false => child.item.convert_to_renderable(),
+                    })
+                    .collect(),
+                task: item.task,
+            })
+            .collect();
+        elements::List {
+            ordered: list.ordered,
+            start: list.start,
+            items,
+            attributes: elements::Attributes::default(),
+            span: Some(list.span),
+        }
+    }
+}
+
+struct DefinitionList {
+    items: Vec<DefinitionItem>,
+    // Like a tight list, a tight definition list has no blank lines and no <p> in its definitions
+    tight: bool,
+    span: Span,
+}
+
+// A term, and the blocks of each of its definitions
+struct DefinitionItem {
+    term: Vec<Inline>,
+    definitions: Vec<Node>,
 }
-impl Into<elements::Heading> for &Heading {
-    fn into(self) -> elements::Heading {
-        let para: elements::Paragraph = (&self.text).into();
-        elements::Heading::new(para, self.level)
+
+impl From<&DefinitionList> for elements::DefinitionList {
+    fn from(list: &DefinitionList) -> Self {
+        let items = list
+            .items
+            .iter()
+            .map(|item| elements::DefinitionItem {
+                term: inline::convert_inlines(&item.term),
+                definitions: item
+                    .definitions
+                    .iter()
+                    .map(|definition| {
+                        definition
+                            .children
+                            .iter()
+                            .map(|child| match list.tight {
+                                true => child.item.convert_to_tight_renderable(),
+                                false => child.item.convert_to_renderable(),
+                            })
+                            .collect()
+                    })
+                    .collect(),
+            })
+            .collect();
+        elements::DefinitionList {
+            items,
+            attributes: elements::Attributes::default(),
+            span: Some(list.span),
+        }
     }
 }
 
-struct Noop {}
+// Display math on lines of its own, between $$
+struct MathBlock {
+    tex: String,
+    span: Span,
+}
+
+struct Blockquote {
+    children: Node,
+    span: Span,
+}
+
+// A callout, from a `> [!NOTE]` blockquote or a `:::note` container
+struct Admonition {
+    kind: String,
+    title: String,
+    children: Node,
+    span: Span,
+}
+
+// The footnotes at the end of the document
+struct FootnoteSection {
+    footnotes: Rc<RefCell<Footnotes>>,
+    span: Span,
+}
+
+/*
+ * A [[toc]] marker. The headings it lists are only known once the whole document has been
+ * parsed, so they are filled in afterwards
+ */
+struct TableOfContents {
+    entries: Rc<RefCell<Vec<elements::TocEntry>>>,
+    span: Span,
+}
+
+struct Noop {
+    span: Span,
+}
+
+// A block with the attributes from the `{.class}` line after it
+struct Attributed {
+    item: Box<dyn AST>,
+    attributes: elements::Attributes,
+    // The block and the attribute line
+    span: Span,
+}
+
+impl Attributed {
+    fn apply(&self, mut element: Box<dyn elements::Renderable>) -> Box<dyn elements::Renderable> {
+        if let Some(attributes) = element.attributes_mut() {
+            attributes.merge(self.attributes.clone());
+        }
+        element
+    }
+}
 
+#[allow(clippy::upper_case_acronyms)]
 pub trait AST {
     fn convert_to_renderable(&self) -> Box<dyn elements::Renderable>;
+    // Where in the source the node came from
+    fn span(&self) -> Span;
+    // The entry of the node in a table of contents, if it is a heading
+    fn toc_entry(&self) -> Option<elements::TocEntry> {
+        None
+    }
+    // The node as it renders in a tight list, where only paragraphs look any different
+    fn convert_to_tight_renderable(&self) -> Box<dyn elements::Renderable> {
+        self.convert_to_renderable()
+    }
 }
 
 impl AST for Paragraph {
     fn convert_to_renderable(&self) -> Box<dyn elements::Renderable> {
         let text: elements::Paragraph = self.into();
-        return Box::new(text);
+        Box::new(text)
+    }
+    fn convert_to_tight_renderable(&self) -> Box<dyn elements::Renderable> {
+        let mut text: elements::Paragraph = self.into();
+        text.tight = true;
+        Box::new(text)
+    }
+    fn span(&self) -> Span {
+        self.span
     }
 }
 impl AST for CodeBlock {
     fn convert_to_renderable(&self) -> Box<dyn elements::Renderable> {
         let text: elements::CodeBlock = self.into();
-        return Box::new(text);
+        Box::new(text)
+    }
+    fn span(&self) -> Span {
+        self.span
     }
 }
 impl AST for Heading {
     fn convert_to_renderable(&self) -> Box<dyn elements::Renderable> {
         let heading: elements::Heading = self.into();
-        return Box::new(heading);
+        Box::new(heading)
+    }
+    fn span(&self) -> Span {
+        self.span
+    }
+    fn toc_entry(&self) -> Option<elements::TocEntry> {
+        Some(elements::TocEntry {
+            level: self.level,
+            id: self.id.clone(),
+            text: self.inlines.iter().map(Inline::plain_text).collect(),
+            children: vec![],
+        })
+    }
+}
+impl AST for HtmlBlock {
+    fn convert_to_renderable(&self) -> Box<dyn elements::Renderable> {
+        let html: elements::Html = self.into();
+        Box::new(html)
+    }
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+impl AST for ThematicBreak {
+    fn convert_to_renderable(&self) -> Box<dyn elements::Renderable> {
+        let rule: elements::ThematicBreak = self.into();
+        Box::new(rule)
+    }
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+impl AST for List {
+    fn convert_to_renderable(&self) -> Box<dyn elements::Renderable> {
+        let list: elements::List = self.into();
+        Box::new(list)
+    }
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+impl AST for DefinitionList {
+    fn convert_to_renderable(&self) -> Box<dyn elements::Renderable> {
+        let list: elements::DefinitionList = self.into();
+        Box::new(list)
+    }
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+impl AST for MathBlock {
+    fn convert_to_renderable(&self) -> Box<dyn elements::Renderable> {
+        Box::new(elements::Math {
+            tex: self.tex.clone(),
+            display: true,
+            attributes: elements::Attributes::default(),
+            span: Some(self.span),
+        })
+    }
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+impl AST for Blockquote {
+    fn convert_to_renderable(&self) -> Box<dyn elements::Renderable> {
+        Box::new(elements::Blockquote {
+            children: self.children.convert_to_renderables(),
+            attributes: elements::Attributes::default(),
+            span: Some(self.span),
+        })
+    }
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+impl AST for Admonition {
+    fn convert_to_renderable(&self) -> Box<dyn elements::Renderable> {
+        Box::new(elements::Admonition {
+            kind: self.kind.clone(),
+            title: self.title.clone(),
+            children: self.children.convert_to_renderables(),
+            attributes: elements::Attributes::default(),
+            span: Some(self.span),
+        })
+    }
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+impl AST for FootnoteSection {
+    fn convert_to_renderable(&self) -> Box<dyn elements::Renderable> {
+        match self.footnotes.borrow().section() {
+            Some(section) => Box::new(section),
+            None => Box::new(elements::Paragraph::new()),
+        }
+    }
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+impl AST for TableOfContents {
+    fn convert_to_renderable(&self) -> Box<dyn elements::Renderable> {
+        Box::new(elements::Toc {
+            entries: self.entries.borrow().clone(),
+            attributes: elements::Attributes::default(),
+            span: Some(self.span),
+        })
+    }
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+impl AST for Attributed {
+    fn convert_to_renderable(&self) -> Box<dyn elements::Renderable> {
+        self.apply(self.item.convert_to_renderable())
+    }
+    fn convert_to_tight_renderable(&self) -> Box<dyn elements::Renderable> {
+        self.apply(self.item.convert_to_tight_renderable())
+    }
+    fn span(&self) -> Span {
+        self.span
+    }
+    fn toc_entry(&self) -> Option<elements::TocEntry> {
+        let mut entry = self.item.toc_entry()?;
+        if self.attributes.id.is_some() {
+            entry.id = self.attributes.id.clone();
+        }
+        Some(entry)
     }
 }
 impl AST for Noop {
     fn convert_to_renderable(&self) -> Box<dyn elements::Renderable> {
         let blank = elements::Paragraph::new();
-        return Box::new(blank);
+        Box::new(blank)
+    }
+    fn span(&self) -> Span {
+        self.span
     }
 }
 
@@ -110,97 +434,162 @@ pub struct Node {
     pub children: Vec<Exp>,
 }
 
+impl Node {
+    // The outline of the headings in the node, keeping only those with a level in `levels`
+    pub fn toc(&self, levels: &RangeInclusive<u8>) -> Vec<elements::TocEntry> {
+        let headings = self
+            .children
+            .iter()
+            .filter_map(|child| child.item.toc_entry())
+            .filter(|entry| levels.contains(&entry.level))
+            .collect();
+        elements::TocEntry::nest(headings)
+    }
+    fn convert_to_renderables(&self) -> Vec<Box<dyn elements::Renderable>> {
+        self.children
+            .iter()
+            .map(|child| child.item.convert_to_renderable())
+            .collect()
+    }
+
+    // Are there blank lines between any of the blocks?
+    fn has_blank_lines(&self) -> bool {
+        self.children
+            .windows(2)
+            .any(|pair| pair[1].item.span().line > pair[0].item.span().end_line + 1)
+    }
+}
+
 /*
  * The Parser struct
- * Contains the lexer from which it will generate the tree
+ * Contains the tokens from the lexer from which it will generate the tree, and the position of
+ * the next token to read
  */
 pub struct Parser {
-    lexer: Lexer,
-    tokens: Peekable<std::vec::IntoIter<Token>>,
+    tokens: Vec<Token>,
+    // The text the tokens came from
+    source: String,
+    position: usize,
+    options: Options,
+    slugger: Slugger,
+    // The id of every heading, in the order they appear
+    pub heading_ids: Vec<String>,
+    // Ids given by the author which an earlier heading already had, with the line of each
+    duplicate_ids: Vec<(String, String, u32)>,
+    // Is the last block a paragraph which the next line may continue?
+    paragraph_open: bool,
+    // How many blocks the one being parsed is inside
+    depth: usize,
+    // Shared by every [[toc]] marker
+    toc: Rc<RefCell<Vec<elements::TocEntry>>>,
+    // The task list items seen so far
+    pub tasks: TaskCounts,
+    // Shared by every footnote reference, and the footnotes at the end
+    footnotes: Rc<RefCell<Footnotes>>,
+    // Shared by every wiki link, noting the ones which go nowhere
+    wiki_links: Rc<RefCell<WikiLinks>>,
+    // Shared by all the text, which needs every definition before it can be rendered
+    abbreviations: Rc<RefCell<Abbreviations>>,
+    pub front_matter: FrontMatter,
     pub tree: Node,
 }
 
 impl Parser {
-    pub fn new(lexer: Lexer) -> Self {
+    pub fn new(lexer: Lexer, options: &Options) -> Self {
         let root = Node { children: vec![] };
-        let input_lexer = lexer;
-        let input_tokens = input_lexer.tokens.clone().into_iter().peekable();
+        let source = lexer.source().to_string();
         Parser {
-            lexer: input_lexer,
-            tokens: input_tokens,
+            tokens: lexer.tokens,
+            source,
+            position: 0,
+            options: options.clone(),
+            slugger: Slugger::new(),
+            heading_ids: vec![],
+            duplicate_ids: vec![],
+            paragraph_open: false,
+            depth: 0,
+            toc: Rc::new(RefCell::new(vec![])),
+            tasks: TaskCounts::default(),
+            footnotes: Rc::new(RefCell::new(Footnotes::new())),
+            wiki_links: Rc::new(RefCell::new(WikiLinks::new(&options.wiki_pages))),
+            abbreviations: Rc::new(RefCell::new(Abbreviations::new())),
+            front_matter: FrontMatter::default(),
             tree: root,
         }
     }
 
-    fn _asterisk_helper(&mut self) -> Text {
-        let is_bold = self.tokens.peek().unwrap().token_type == TokenType::ASTERISK;
-        let _tmp_token = self.tokens.peek().unwrap().value.clone();
-        // Move ahead of asterisk
-        if is_bold {
-            self.tokens.next();
+    // The token at the cursor
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    // The token n places after the cursor
+    fn peek_nth(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.position + n)
+    }
+
+    // Is the token at the cursor of this type?
+    fn at(&self, token_type: TokenType) -> bool {
+        matches!(self.peek(), Some(token) if token.token_type == token_type)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        if token.is_some() {
+            self.position += 1;
         }
-        let mut inner_text = self.text();
+        token
+    }
 
-        // We are at least italics
-        assert!(self.tokens.next().unwrap().token_type == TokenType::ASTERISK);
-        let is_bold_after = self.tokens.peek().unwrap().token_type == TokenType::ASTERISK;
-        if is_bold && is_bold_after {
-            inner_text.style |= BOLD_STYLE;
-            // Move over the asterisk
-            self.tokens.next();
+    // Move past the token at the cursor if it is of this type
+    fn next_if(&mut self, token_type: TokenType) -> Option<Token> {
+        if self.at(token_type) {
+            return self.next();
         }
-        // Meaning we have something like ** TEXT *
-        else if is_bold && !is_bold_after {
-            let _tmp: &str = &inner_text.text;
-            inner_text.text = format!("{_tmp_token}{_tmp}");
-            inner_text.style |= ITALIC_STYLE;
+        None
+    }
+
+    // An empty span where the token at the cursor starts
+    fn point(&self) -> Span {
+        match self.peek() {
+            Some(token) => Span {
+                end: token.span.start,
+                end_line: token.span.line,
+                end_column: token.span.column,
+                ..token.span
+            },
+            None => match self.tokens.last() {
+                Some(token) => token.span.end_point(),
+                None => Span::default(),
+            },
         }
-        // We are italics otherwise
-        else {
-            inner_text.style |= ITALIC_STYLE;
+    }
+
+    // The span of the last token read
+    fn previous_span(&self) -> Span {
+        match self.position {
+            0 => Span::default(),
+            position => self.tokens[position - 1].span,
         }
-        return inner_text;
     }
 
-    /* Parse a Text block
-     * | TEXT | text TEXT | ASTERISK text ASTERISK | BACKTICK text BACKTICK
-     */
-    fn text(&mut self) -> Text {
-        match &self.tokens.next() {
-            Some(token) => {
-                if token.token_type == TokenType::TEXT {
-                    return Text {
-                        text: token.value.clone(),
-                        style: 0b000,
-                        token: token.clone(),
-                    };
-                }
-                // Bold or italic text
-                else if token.token_type == TokenType::ASTERISK {
-                    return self._asterisk_helper();
-                } else if token.token_type == TokenType::BACKTICK {
-                    let mut inner_text = self.text();
-                    // It ends with a backtick
-                    let token = self.tokens.peek();
-                    if token.is_none() || token.as_ref().unwrap().token_type != TokenType::BACKTICK
-                    {
-                        // Restore backtick if not closed correctly (treat as normal)
-                        let _tmp = inner_text.text;
-                        inner_text.text = format!("`{_tmp}");
-                        return inner_text;
-                    }
-                    if token.unwrap().token_type == TokenType::BACKTICK {
-                        self.tokens.next();
-                        inner_text.style |= CODE_STYLE;
-                        return inner_text;
-                    }
-                }
-                panic!("Invalid expression for text!");
-            }
-            None => {
-                panic!("Invalid expression for text!")
+    // The inline content of the tokens from the cursor up to the end of the line
+    fn text(&mut self) -> Vec<Inline> {
+        let start = self.position;
+        while let Some(token) = self.peek() {
+            if token.token_type == TokenType::NEWLINE {
+                break;
             }
+            self.position += 1;
         }
+        inline::parse(
+            &self.tokens[start..self.position],
+            &self.source,
+            &self.options,
+            &self.footnotes,
+            &self.wiki_links,
+            &self.abbreviations,
+        )
     }
 
     /*
@@ -208,27 +597,27 @@ impl Parser {
      * calling it a paragraph alligns it with the HTML equivalent
      */
     fn paragraph(&mut self) -> Paragraph {
-        let mut para = Paragraph { texts: vec![] };
-        while self.tokens.peek().unwrap().token_type != TokenType::NEWLINE {
-            let text = self.text();
-            para.texts.push(text);
-        }
-
-        return para;
+        // Indentation doesn't matter inside a paragraph
+        self.next_if(TokenType::INDENT);
+        self.paragraph_open = true;
+        let start = self.point();
+        let inlines = self.text();
+        let span = match inlines.last() {
+            Some(last) => start.to(last.span()),
+            None => start,
+        };
+        Paragraph { inlines, span }
     }
 
-    /* Parse a Text block - Unformatted text
+    /* Parse a Text block - Unformatted text, exactly as it appears in the source
      * | TEXT | text TEXT
      */
     fn raw_text(&mut self) -> Text {
-        match &self.tokens.next() {
-            Some(token) => {
-                return Text {
-                    text: token.value.clone(),
-                    style: 0b000,
-                    token: token.clone(),
-                }
-            }
+        match self.next() {
+            Some(token) => Text {
+                text: self.source[token.span.start..token.span.end].to_string(),
+                span: token.span,
+            },
             None => {
                 panic!("Invalid expression for rawtext!")
             }
@@ -239,172 +628,2133 @@ impl Parser {
      * No other formatting applies to the block
      * 3BT raw_text 3BT
      */
-    fn code_block(&mut self) -> CodeBlock {
-        let mut para = CodeBlock { texts: vec![] };
-        loop {
-            let token = match self.tokens.peek() {
-                Some(token) => token,
-                None => {
-                    return para;
-                }
-            };
-            println!("CodeBlock: Got {:?}, {:?}", token.token_type, token.value);
-            if token.token_type == TokenType::BACKTICK {
-                // This is a backtick
-                let next1 = self.raw_text();
-                let next2 = match self.tokens.peek() {
-                    Some(token) => token,
-                    None => {
-                        return para;
-                    }
-                };
-                println!("CodeBlock-2: Got {:?}, {:?}", next2.token_type, next2.value);
-                if next2.token_type == TokenType::BACKTICK {
-                    // This is a backtick
-                    let next2 = self.raw_text();
-                    let next3 = match self.tokens.peek() {
-                        Some(token) => token,
-                        None => {
-                            return para;
-                        }
-                    };
-                    println!("CodeBlock-3: Got {:?}, {:?}", next3.token_type, next3.value);
-                    if next3.token_type == TokenType::BACKTICK {
-                        // We are now closing the codeblock - break
-                        // Let us also finally consume next3
-                        self.tokens.next();
-                        return para;
-                    } else {
-                        // Not a closing statment. Add next1 and next2 as texts
-                        para.texts.push(next1);
-                        para.texts.push(next2);
-                    }
-                } else {
-                    // Not a closing statement. Add next1 as text
-                    para.texts.push(next1);
-                }
+    fn code_block(&mut self, start: Span) -> CodeBlock {
+        let mut para = CodeBlock {
+            texts: vec![],
+            span: start,
+        };
+        while self.peek().is_some() {
+            let closing = (0..3).all(|n| {
+                matches!(self.peek_nth(n), Some(token) if token.token_type == TokenType::BACKTICK)
+            });
+            if closing {
+                // We are now closing the codeblock - consume the backticks
+                self.position += 3;
+                break;
             }
             let text = self.raw_text();
             para.texts.push(text);
         }
+        para.span = start.to(self.previous_span());
+        para
     }
 
-    /* Parse a Heading
-     * HASH heading | HASH paragraph
+    /* Parse an indented code block
+     * (INDENT raw_text NEWLINE)+
+     * Blank lines between the indented lines belong to the block, those after it don't. The
+     * first four columns of indentation are dropped from every line
      */
-    fn heading(&mut self) -> Heading {
-        let mut heading_size = 0;
-        while self.tokens.peek().unwrap().token_type == TokenType::HASH {
-            heading_size += 1;
-            self.tokens.next();
+    fn indented_code(&mut self) -> CodeBlock {
+        let start = self.point();
+        let mut texts = vec![];
+        let mut blank_lines = vec![];
+        let mut index = self.position;
+        loop {
+            let end = self.line_end(index);
+            let indented = self.tokens.get(index).map(|token| &token.token_type);
+            if self.line_is_blank(index) {
+                blank_lines.push(index);
+            } else if indented == Some(&TokenType::INDENT) {
+                for blank in blank_lines.drain(..) {
+                    texts.push(self.code_line(blank));
+                }
+                texts.push(self.code_line(index));
+                self.position = end;
+            } else {
+                break;
+            }
+            if end == self.tokens.len() {
+                break;
+            }
+            index = end + 1;
+        }
+        CodeBlock {
+            texts,
+            span: start.to(self.previous_span()),
         }
-        let heading_text = self.paragraph();
-        return Heading {
-            level: heading_size,
-            text: heading_text,
+    }
+
+    // A line of an indented code block, without its INDENT token
+    fn code_line(&self, index: usize) -> Text {
+        let end = self.line_end(index);
+        let from = match self.tokens[index].token_type {
+            TokenType::INDENT => index + 1,
+            _ => end,
+        };
+        let text = match from < end {
+            true => &self.source[self.tokens[from].span.start..self.tokens[end - 1].span.end],
+            false => "",
+        };
+        let span = match end > index {
+            true => self.tokens[index].span.to(self.tokens[end - 1].span),
+            false => self.tokens[index].span,
         };
+        Text {
+            text: format!("{}\n", text),
+            span,
+        }
     }
 
-    /* exp
-     * paragraph | heading | code-block
-     *
+    /* Parse an HTML block
+     * The block runs up to the line which ends it, or up to a blank line, depending on its kind
      */
-    fn exp(&mut self) -> Exp {
-        let token = self.tokens.peek();
-        if token.is_none() {
-            return Exp {
-                item: Box::new(Noop {}),
-            };
+    fn html_block(&mut self, kind: BlockKind) -> HtmlBlock {
+        let start = self.point();
+        let mut index = self.position;
+        loop {
+            if kind.ends_at_blank_line() && self.line_is_blank(index) {
+                break;
+            }
+            let end = self.line_end(index);
+            self.position = end;
+            if kind.ends(self.line_text(index)) || end == self.tokens.len() {
+                break;
+            }
+            index = end + 1;
+        }
+        let span = start.to(self.previous_span());
+        HtmlBlock {
+            html: self.source[span.start..span.end].to_string(),
+            span,
         }
-        let token = token.unwrap().clone();
+    }
 
-        println!("Got {:?}, {:?}", token.token_type, token.value);
+    /*
+     * The level of the ATX heading starting at the cursor, if there is one: up to three spaces,
+     * one to six hashes, then a space or the end of the line
+     */
+    fn atx_level(&self) -> Option<u8> {
+        let mut index = self.position;
+        if self.tokens.get(index).is_some_and(is_indent) {
+            index += 1;
+        }
+        let hashes = self.tokens[index..]
+            .iter()
+            .take_while(|token| token.token_type == TokenType::HASH)
+            .count();
+        let separated = match self.tokens.get(index + hashes) {
+            None => true,
+            Some(token) => match token.token_type {
+                TokenType::NEWLINE => true,
+                TokenType::TEXT => token.value.starts_with([' ', '\t']),
+                _ => false,
+            },
+        };
+        match hashes {
+            1..=6 if separated => Some(hashes as u8),
+            _ => None,
+        }
+    }
 
-        if token.token_type == TokenType::BACKTICK {
-            println!("Considering codeblock");
-            // Let's check for code block first, tmp clone
-            let mut _tmp = self.tokens.clone();
-            // Move it to current peeked entry
-            _tmp.next();
-            match _tmp.next() {
-                Some(token) => {
-                    if token.token_type == TokenType::BACKTICK {
-                        // 2 backticks, check for third
-                        match _tmp.next() {
-                            Some(token2) => {
-                                if token2.token_type == TokenType::BACKTICK {
-                                    // 3 backticks, it's a code block
-                                    // Let's also move the iterator ahead so we don't
-                                    // include backticks in the block
-                                    self.tokens.next();
-                                    self.tokens.next();
-                                    self.tokens.next();
-                                    let code = self.code_block();
-                                    return Exp {
-                                        item: Box::new(code),
-                                    };
-                                }
-                            }
-                            None => {
-                                /*Leave it to other if branches*/
-
-                                println!("Next2 is not backtick");
-                            }
-                        }
-                    }
-                }
-                None => {
-                    /*Leave it to other if branches*/
-                    println!("Next is not backtick");
+    /* Parse an ATX heading
+     * HASH{1,6} text HASH*
+     * A closing run of hashes is dropped if there is a space before it
+     */
+    fn heading(&mut self, level: u8) -> Heading {
+        let start = self.point();
+        if self.peek().is_some_and(is_indent) {
+            self.next();
+        }
+        self.position += level as usize;
+        let end = self.line_end(self.position);
+        let mut content_end = end;
+        while content_end > self.position && is_blank(&self.tokens[content_end - 1]) {
+            content_end -= 1;
+        }
+        let mut closing = content_end;
+        while closing > self.position && self.tokens[closing - 1].token_type == TokenType::HASH {
+            closing -= 1;
+        }
+        let spaced = |token: &Token| {
+            token.token_type == TokenType::TEXT && token.value.ends_with([' ', '\t'])
+        };
+        if closing < content_end && (closing == self.position || spaced(&self.tokens[closing - 1]))
+        {
+            content_end = closing;
+        }
+        let mut inlines = inline::parse(
+            &self.tokens[self.position..content_end],
+            &self.source,
+            &self.options,
+            &self.footnotes,
+            &self.wiki_links,
+            &self.abbreviations,
+        );
+        trim_inlines(&mut inlines);
+        self.position = end;
+        let (id, attributes) = self.heading_id(&mut inlines);
+        Heading {
+            level,
+            span: start.to(self.previous_span()),
+            inlines,
+            id,
+            attributes,
+        }
+    }
+
+    /*
+     * The level of a setext heading underline on the line starting at index: a row of `=` for
+     * level 1 or `-` for level 2, indented by up to three spaces
+     */
+    fn setext_level(&self, index: usize) -> Option<u8> {
+        let mut line = String::new();
+        for token in &self.tokens[index..self.line_end(index)] {
+            match token.token_type {
+                TokenType::TEXT | TokenType::EQUALS => line.push_str(&token.value),
+                _ => return None,
+            }
+        }
+        let underline = line.trim_start_matches(' ');
+        if line.len() - underline.len() > 3 {
+            return None;
+        }
+        let underline = underline.trim_end();
+        if !underline.is_empty() && underline.chars().all(|c| c == '=') {
+            Some(1)
+        } else if !underline.is_empty() && underline.chars().all(|c| c == '-') {
+            Some(2)
+        } else {
+            None
+        }
+    }
+
+    /* Parse a setext heading
+     * text NEWLINE underline
+     */
+    fn setext_heading(&mut self, level: u8) -> Heading {
+        let start = self.point();
+        let mut inlines = self.text();
+        trim_inlines(&mut inlines);
+        // Skip the underline
+        self.next();
+        self.position = self.line_end(self.position);
+        let (id, attributes) = self.heading_id(&mut inlines);
+        Heading {
+            level,
+            span: start.to(self.previous_span()),
+            inlines,
+            id,
+            attributes,
+        }
+    }
+
+    /*
+     * The id and attributes of a heading. The author can give them with a trailing
+     * {#id .class}, which is removed from the text; otherwise the id is made from the text. Ids
+     * belong to the heading_ids extension, and everything else to the attributes extension
+     */
+    fn heading_id(&mut self, inlines: &mut Vec<Inline>) -> (Option<String>, elements::Attributes) {
+        let extensions = &self.options.extensions;
+        let mut attributes = trailing_attributes(inlines, |attributes| {
+            let others = !attributes.classes.is_empty() || !attributes.pairs.is_empty();
+            (attributes.id.is_none() || extensions.heading_ids)
+                && (!others || extensions.attributes)
+        })
+        .unwrap_or_default();
+        let id = match attributes.id.take() {
+            Some(id) => {
+                let unique = self.slugger.reserve(&id);
+                if unique != id {
+                    let line = self.previous_span().line;
+                    self.duplicate_ids.push((id, unique.clone(), line));
                 }
+                unique
+            }
+            None if extensions.heading_ids => {
+                let text: String = inlines.iter().map(Inline::plain_text).collect();
+                self.slugger.slug(&text)
             }
+            None => return (None, attributes),
+        };
+        // Text without letters or numbers has an empty slug, which isn't a valid id
+        if id.is_empty() {
+            return (None, attributes);
         }
-        if token.token_type == TokenType::TEXT
-            || token.token_type == TokenType::ASTERISK
-            || token.token_type == TokenType::BACKTICK
-        {
-            let tree = self.paragraph();
-            return Exp {
-                item: Box::new(tree),
-            };
+        self.heading_ids.push(id.clone());
+        (Some(id), attributes)
+    }
+
+    // The index of the NEWLINE token ending the line which starts at index, or the token count
+    fn line_end(&self, index: usize) -> usize {
+        let mut end = index;
+        while end < self.tokens.len() && self.tokens[end].token_type != TokenType::NEWLINE {
+            end += 1;
         }
+        end
+    }
 
-        if token.token_type == TokenType::HASH {
-            let tree = self.heading();
-            return Exp {
-                item: Box::new(tree),
+    // The source text of the line starting at index
+    fn line_text(&self, index: usize) -> &str {
+        let end = self.line_end(index);
+        if end == index {
+            return "";
+        }
+        &self.source[self.tokens[index].span.start..self.tokens[end - 1].span.end]
+    }
+
+    /*
+     * Is the line starting at index a thematic break: three or more `-`, `*` or `_`, all the
+     * same, with any spaces between them and up to three before them
+     */
+    fn is_thematic_break(&self, index: usize) -> bool {
+        let line = self.line_text(index);
+        let marks = line.trim_start_matches(' ');
+        if line.len() - marks.len() > 3 {
+            return false;
+        }
+        let mut marks = marks.chars().filter(|c| !matches!(c, ' ' | '\t'));
+        match marks.next() {
+            Some(mark @ ('-' | '*' | '_')) => {
+                let rest: Vec<char> = marks.collect();
+                rest.len() >= 2 && rest.iter().all(|&c| c == mark)
+            }
+            _ => false,
+        }
+    }
+
+    // Is the line starting at index empty, or only whitespace?
+    fn line_is_blank(&self, index: usize) -> bool {
+        self.tokens[index..self.line_end(index)]
+            .iter()
+            .all(is_blank)
+    }
+
+    // The line starting at index, from its start to the end of the line
+    fn stripped_line(&self, index: usize, width: usize) -> StrippedLine {
+        let text = self.line_text(index);
+        let width = width.min(text.len());
+        let start = match self.tokens.get(index) {
+            Some(token) => token.span,
+            None => self.point(),
+        };
+        StrippedLine {
+            text: text[width..].to_string(),
+            byte: start.start + width,
+            line: start.line,
+            column: start.column + text[..width].chars().count() as u32,
+        }
+    }
+
+    /*
+     * The lines of a block such as a list item, starting at the cursor with its first `width`
+     * bytes, the marker, taken off. The block goes on for as long as its lines are indented by
+     * `columns`, with blank lines in between. The cursor is left at the end of its last line
+     */
+    fn indented_lines(&mut self, width: usize, columns: usize) -> Vec<StrippedLine> {
+        let mut lines = vec![self.stripped_line(self.position, width)];
+        let mut blank_lines = vec![];
+        let mut end = self.line_end(self.position);
+        let mut index = end;
+        while index < self.tokens.len() {
+            let next = index + 1;
+            index = self.line_end(next);
+            if self.line_is_blank(next) {
+                // An item can start with at most one blank line
+                if lines.len() == 1 && lines[0].text.trim().is_empty() {
+                    break;
+                }
+                blank_lines.push(next);
+                continue;
+            }
+            let Some(width) = strip_columns(self.line_text(next), columns) else {
+                break;
             };
+            for blank in blank_lines.drain(..) {
+                lines.push(self.stripped_line(blank, usize::MAX));
+            }
+            lines.push(self.stripped_line(next, width));
+            end = index;
         }
+        self.position = end;
+        lines
+    }
 
-        panic!("Invalid Exp type!")
+    /*
+     * Parse lines taken out of the source, such as the content of a list item, as blocks of
+     * their own. Their tokens keep the spans of the source they came from
+     */
+    fn parse_lines(&mut self, lines: &[StrippedLine]) -> Node {
+        let text: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+        let mut lexer = Lexer::new(&text.join("\n"), &self.options);
+        lexer.scan();
+        let mut offsets = vec![];
+        let mut offset = 0;
+        for line in &text {
+            offsets.push(offset);
+            offset += line.len() + 1;
+        }
+        let tokens = lexer
+            .tokens
+            .into_iter()
+            .map(|mut token| {
+                let line = &lines[token.span.line as usize];
+                let start = line.byte + token.span.start - offsets[token.span.line as usize];
+                let column = line.column + token.span.column;
+                token.span = match token.token_type {
+                    TokenType::NEWLINE => Span {
+                        start,
+                        end: start + 1,
+                        line: line.line,
+                        column,
+                        end_line: line.line + 1,
+                        end_column: 0,
+                    },
+                    _ => Span {
+                        start,
+                        end: start + token.span.end - token.span.start,
+                        line: line.line,
+                        column,
+                        end_line: line.line,
+                        end_column: column + token.span.end_column - token.span.column,
+                    },
+                };
+                token
+            })
+            .collect();
+        let tokens = std::mem::replace(&mut self.tokens, tokens);
+        let position = std::mem::replace(&mut self.position, 0);
+        let paragraph_open = std::mem::replace(&mut self.paragraph_open, false);
+        self.depth += 1;
+        let node = self.node();
+        self.depth -= 1;
+        self.tokens = tokens;
+        self.position = position;
+        self.paragraph_open = paragraph_open;
+        node
     }
 
-    /* Node
-     * exp | exp NEWLINE node
+    /* Parse a footnote definition, if there is one at the cursor
+     * [^label]: blocks
+     * The blocks after the first line are indented by four columns. The footnote is kept for the
+     * end of the document, so the definition itself renders as nothing
      */
-    fn node(&mut self) -> Node {
-        let mut node = Node { children: vec![] };
-        let exp = self.exp();
-        node.children.push(exp);
+    fn footnote_definition(&mut self) -> bool {
+        if !self.options.extensions.footnotes || !self.can_nest() {
+            return false;
+        }
+        let line = self.line_text(self.position);
+        let rest = line.trim_start_matches(' ');
+        if line.len() - rest.len() > 3 {
+            return false;
+        }
+        let Some((label, length)) = footnotes::label(rest) else {
+            return false;
+        };
+        let Some(after) = rest[length..].strip_prefix(':') else {
+            return false;
+        };
+        let width = line.len() - after.trim_start_matches([' ', '\t']).len();
+        let label = label.to_string();
+        let start = self.point();
+        let lines = self.indented_lines(width, 4);
+        let children = self.parse_lines(&lines);
+        self.footnotes.borrow_mut().define(Definition {
+            label,
+            children,
+            span: start.to(self.previous_span()),
+        });
+        true
+    }
 
+    /* Parse a block of display math, if there is one at the cursor
+     * `$$` TEXT* `$$`
+     * The math may start on the line of the opening `$$`, and end on the line of the closing
+     * one. If it is never closed, it runs to the end of the document
+     */
+    fn math_block(&mut self) -> Option<MathBlock> {
+        if !self.options.extensions.math {
+            return None;
+        }
+        let first = self.line_text(self.position).trim().strip_prefix("$$")?;
+        // $$x$$ and more text on the same line is inline math in a paragraph
+        if !first.is_empty() && !first.ends_with("$$") {
+            return None;
+        }
+        let start = self.point();
+        let mut lines = vec![];
+        let mut line = first.to_string();
         loop {
-            match self.tokens.peek() {
-                Some(token) => {
-                    if token.token_type == TokenType::NEWLINE {
-                        self.tokens.next();
-                        continue;
-                    } else {
-                        node.children.push(self.exp());
-                    }
-                }
-                None => break,
+            if let Some(body) = line.trim_end().strip_suffix("$$") {
+                lines.push(body.to_string());
+                break;
             }
+            lines.push(line);
+            self.position = self.line_end(self.position);
+            if self.position >= self.tokens.len() {
+                break;
+            }
+            self.position += 1;
+            line = self.line_text(self.position).to_string();
         }
-        return node;
+        self.position = self.line_end(self.position);
+        Some(MathBlock {
+            tex: lines.join("\n").trim().to_string(),
+            span: start.to(self.previous_span()),
+        })
     }
 
-    // Entrypoint - we always begin with a node
-    pub fn parse(&mut self) {
-        self.tree = self.node();
+    // The lines of the blockquote at the cursor, without their `>` markers
+    fn quote_lines(&mut self) -> Vec<StrippedLine> {
+        let mut lines = vec![];
+        let mut index = self.position;
+        while let Some(width) = quote_marker(self.line_text(index)) {
+            lines.push(self.stripped_line(index, width));
+            self.position = self.line_end(index);
+            if self.position >= self.tokens.len() {
+                break;
+            }
+            index = self.position + 1;
+        }
+        lines
+    }
+
+    /* Parse a blockquote
+     * (`>` blocks NEWLINE)+
+     * When its first line is `[!NOTE]` or another kind of admonition, it makes an admonition
+     * instead
+     */
+    fn blockquote(&mut self) -> Box<dyn AST> {
+        let start = self.point();
+        let lines = self.quote_lines();
+        let span = start.to(self.previous_span());
+        if let Some((kind, title)) = self.callout(&lines[0].text) {
+            let children = self.parse_lines(&lines[1..]);
+            return Box::new(Admonition {
+                kind,
+                title,
+                children,
+                span,
+            });
+        }
+        let children = self.parse_lines(&lines);
+        Box::new(Blockquote { children, span })
+    }
+
+    // Can a block which holds other blocks start here? Past the limit, its marker is just text
+    fn can_nest(&self) -> bool {
+        self.depth < MAX_NESTING
+    }
+
+    // The kind and title of a `[!KIND] title` callout, if the kind is one of the admonition types
+    fn callout(&self, line: &str) -> Option<(String, String)> {
+        if !self.options.extensions.admonitions {
+            return None;
+        }
+        let rest = line.trim().strip_prefix("[!")?;
+        let (kind, title) = rest.split_once(']')?;
+        self.admonition(kind, title)
+    }
+
+    // The kind and title of an admonition, with a title made from the kind if there isn't one
+    fn admonition(&self, kind: &str, title: &str) -> Option<(String, String)> {
+        let kind = kind.to_lowercase();
+        if !self.options.admonition_types.contains(&kind) {
+            return None;
+        }
+        let title = match title.trim() {
+            "" => {
+                let mut chars = kind.chars();
+                chars.next().map_or(String::new(), |first| {
+                    first.to_uppercase().chain(chars).collect()
+                })
+            }
+            title => title.to_string(),
+        };
+        Some((kind, title))
+    }
+
+    /* Parse a fenced container, if there is one at the cursor
+     * `:::kind title` NEWLINE blocks `:::`
+     * Containers can be nested, and one that is never closed runs to the end of the document
+     */
+    fn container(&mut self) -> Option<Admonition> {
+        if !self.options.extensions.admonitions || !self.can_nest() {
+            return None;
+        }
+        let (colons, kind, title) = container_fence(self.line_text(self.position))?;
+        let (kind, title) = self.admonition(kind, title)?;
+        let start = self.point();
+        let mut lines = vec![];
+        let mut depth = 0;
+        self.position = self.line_end(self.position);
+        while self.position < self.tokens.len() {
+            let index = self.position + 1;
+            self.position = self.line_end(index);
+            match container_fence(self.line_text(index)) {
+                Some((_, kind, _)) if !kind.is_empty() => depth += 1,
+                Some((count, _, _)) if depth == 0 && count >= colons => break,
+                Some(_) if depth > 0 => depth -= 1,
+                _ => {}
+            }
+            lines.push(self.stripped_line(index, 0));
+        }
+        let span = start.to(self.previous_span());
+        let children = self.parse_lines(&lines);
+        Some(Admonition {
+            kind,
+            title,
+            children,
+            span,
+        })
+    }
+
+    // Does the line at index hold the term of a definition list: text, with a definition after it?
+    fn definition_term(&self, index: usize) -> bool {
+        self.options.extensions.definition_lists
+            && !self.line_is_blank(index)
+            && definition_start(self.line_text(index)).is_none()
+            && self.next_definition(self.line_end(index)).is_some()
+    }
+
+    /*
+     * The index of the line holding a definition after the line ending at `end`, if there is
+     * one. A blank line may come between them
+     */
+    fn next_definition(&self, end: usize) -> Option<usize> {
+        let mut index = end;
+        for _ in 0..2 {
+            if index >= self.tokens.len() {
+                return None;
+            }
+            let next = index + 1;
+            if !self.line_is_blank(next) {
+                return definition_start(self.line_text(next)).map(|_| next);
+            }
+            index = self.line_end(next);
+        }
+        None
+    }
+
+    /* Parse a definition list
+     * (term (NEWLINE `:` blocks)+)+
+     * The blocks of a definition carry on for as long as they are indented to its content
+     */
+    fn definition_list(&mut self) -> DefinitionList {
+        let start = self.point();
+        let mut items = vec![];
+        let mut tight = true;
+        loop {
+            let mut term = self.text();
+            trim_inlines(&mut term);
+            let mut definitions = vec![];
+            while let Some(index) = self.next_definition(self.position) {
+                tight &= index == self.position + 1;
+                self.position = index;
+                let (width, columns) = definition_start(self.line_text(index)).unwrap();
+                let lines = self.indented_lines(width, columns);
+                let definition = self.parse_lines(&lines);
+                tight &= !definition.has_blank_lines();
+                definitions.push(definition);
+            }
+            items.push(DefinitionItem { term, definitions });
+
+            // Look past any blank lines for the next term
+            let mut index = self.position;
+            while index < self.tokens.len() && self.line_is_blank(index + 1) {
+                index = self.line_end(index + 1);
+            }
+            if index >= self.tokens.len() || !self.definition_term(index + 1) {
+                break;
+            }
+            self.position = index + 1;
+        }
+        DefinitionList {
+            items,
+            tight,
+            span: start.to(self.previous_span()),
+        }
+    }
+
+    // Take a task list marker, [ ] or [x], off the first line of a list item
+    fn task(&mut self, line: &mut StrippedLine) -> Option<bool> {
+        if !self.options.extensions.task_lists {
+            return None;
+        }
+        let done = match line.text.get(..3)? {
+            "[ ]" => false,
+            "[x]" | "[X]" => true,
+            _ => return None,
+        };
+        let rest = line.text[3..].trim_start_matches([' ', '\t']);
+        if rest.len() == line.text.len() - 3 {
+            return None;
+        }
+        let width = line.text.len() - rest.len();
+        line.text = rest.to_string();
+        line.byte += width;
+        line.column += width as u32;
+        match done {
+            true => self.tasks.completed += 1,
+            false => self.tasks.open += 1,
+        }
+        Some(done)
+    }
+
+    /* Parse a list
+     * (list-item-marker blocks)+
+     * The items all use the same kind of marker. The list is loose if there are blank lines
+     * between its items, or between the blocks of an item
+     */
+    fn list(&mut self, first: ItemStart) -> List {
+        let start = self.point();
+        let mut items = vec![];
+        let mut tight = true;
+        let mut item = first;
+        let mut previous_end: Option<Span> = None;
+        loop {
+            if let Some(previous) = previous_end {
+                tight &= self.point().line <= previous.end_line + 1;
+            }
+            let mut lines = self.indented_lines(item.width, item.columns);
+            let task = self.task(&mut lines[0]);
+            let children = self.parse_lines(&lines);
+            tight &= !children.has_blank_lines();
+            items.push(ListItem { children, task });
+            previous_end = Some(self.previous_span());
+
+            // Look past any blank lines for the next item of the same list
+            let mut index = self.position;
+            while index < self.tokens.len() && self.line_is_blank(index + 1) {
+                index = self.line_end(index + 1);
+            }
+            if index >= self.tokens.len() || self.is_thematic_break(index + 1) {
+                break;
+            }
+            match list_item_start(self.line_text(index + 1)) {
+                Some(next) if next.marker.same_list(&item.marker) => {
+                    self.position = index + 1;
+                    item = next;
+                }
+                _ => break,
+            }
+        }
+        let (ordered, first_number) = match first.marker {
+            ListMarker::Bullet(_) => (false, None),
+            ListMarker::Ordered(number, _) => (true, Some(number)),
+        };
+        List {
+            ordered,
+            start: first_number.filter(|&number| number != 1),
+            items,
+            tight,
+            span: start.to(self.previous_span()),
+        }
+    }
+
+    /* exp
+     * paragraph | heading | setext-heading | code-block | math-block | html-block | blockquote |
+     * container | thematic-break | list | definition-list | toc
+     *
+     */
+    fn exp(&mut self) -> Exp {
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => {
+                return Exp {
+                    item: Box::new(Noop { span: self.point() }),
+                }
+            }
+        };
+        let after_paragraph = std::mem::replace(&mut self.paragraph_open, false);
+
+        if token.token_type == TokenType::INDENT {
+            // An indented line can't interrupt a paragraph, it carries on with it
+            if after_paragraph {
+                return Exp {
+                    item: Box::new(self.paragraph()),
+                };
+            }
+            let code = self.indented_code();
+            return Exp {
+                item: Box::new(code),
+            };
+        }
+
+        if token.token_type == TokenType::BACKTICK {
+            // Let's check for code block first
+            let is_backtick = |token: Option<&Token>| matches!(token, Some(t) if t.token_type == TokenType::BACKTICK);
+            if is_backtick(self.peek_nth(1)) && is_backtick(self.peek_nth(2)) {
+                // 3 backticks, it's a code block
+                // Let's also move the iterator ahead so we don't
+                // include backticks in the block
+                self.position += 3;
+                let code = self.code_block(token.span);
+                return Exp {
+                    item: Box::new(code),
+                };
+            }
+            /*Leave it to other if branches*/
+        }
+        if self.options.extensions.raw_html {
+            let line = self.line_text(self.position);
+            if let Some(kind) = BlockKind::start(line, after_paragraph) {
+                let block = self.html_block(kind);
+                return Exp {
+                    item: Box::new(block),
+                };
+            }
+        }
+
+        if let Some(math) = self.math_block() {
+            return Exp {
+                item: Box::new(math),
+            };
+        }
+
+        if self.can_nest() && quote_marker(self.line_text(self.position)).is_some() {
+            return Exp {
+                item: self.blockquote(),
+            };
+        }
+
+        if let Some(container) = self.container() {
+            return Exp {
+                item: Box::new(container),
+            };
+        }
+
+        if let Some(level) = self.atx_level() {
+            let tree = self.heading(level);
+            return Exp {
+                item: Box::new(tree),
+            };
+        }
+
+        // Before anything that could read the marks as emphasis, or as an underline
+        if self.is_thematic_break(self.position) {
+            let start = self.point();
+            self.position = self.line_end(self.position);
+            return Exp {
+                item: Box::new(ThematicBreak {
+                    span: start.to(self.previous_span()),
+                }),
+            };
+        }
+
+        if let Some(item) =
+            list_item_start(self.line_text(self.position)).filter(|_| self.can_nest())
+        {
+            // Only a list that clearly starts can interrupt a paragraph
+            let interrupts = !item.empty
+                && matches!(
+                    item.marker,
+                    ListMarker::Bullet(_) | ListMarker::Ordered(1, _)
+                );
+            if !after_paragraph || interrupts {
+                let list = self.list(item);
+                return Exp {
+                    item: Box::new(list),
+                };
+            }
+        }
+
+        if self.options.extensions.toc
+            && self
+                .line_text(self.position)
+                .trim()
+                .eq_ignore_ascii_case("[[toc]]")
+        {
+            let start = self.point();
+            self.position = self.line_end(self.position);
+            return Exp {
+                item: Box::new(TableOfContents {
+                    entries: Rc::clone(&self.toc),
+                    span: start.to(self.previous_span()),
+                }),
+            };
+        }
+
+        if self.definition_term(self.position) {
+            let list = self.definition_list();
+            return Exp {
+                item: Box::new(list),
+            };
+        }
+
+        // A line of text underlined by the next one
+        let end = self.line_end(self.position);
+        if end < self.tokens.len() && !self.line_is_blank(self.position) {
+            if let Some(level) = self.setext_level(end + 1) {
+                let tree = self.setext_heading(level);
+                return Exp {
+                    item: Box::new(tree),
+                };
+            }
+        }
+
+        let tree = self.paragraph();
+        Exp {
+            item: Box::new(tree),
+        }
+    }
+
+    /* Read an abbreviation definition, if there is one at the cursor
+     * *[abbreviation]: meaning NEWLINE
+     * The definition itself renders as nothing
+     */
+    fn abbreviation_definition(&mut self) -> bool {
+        if !self.options.extensions.abbreviations {
+            return false;
+        }
+        let Some((abbreviation, title)) = abbreviations::definition(self.line_text(self.position))
+        else {
+            return false;
+        };
+        self.abbreviations.borrow_mut().define(abbreviation, title);
+        self.position = self.line_end(self.position);
+        true
+    }
+
+    /* Give the last block in node the attributes on the line at the cursor, if there are any
+     * attributes NEWLINE
+     * The line must hold nothing but an attribute block, and come straight after the block
+     */
+    fn block_attributes(&mut self, node: &mut Node) -> bool {
+        if !self.options.extensions.attributes {
+            return false;
+        }
+        let Some(last) = node.children.last() else {
+            return false;
+        };
+        let text = self.line_text(self.position).trim();
+        let Some((attributes, length)) = attributes::parse(text) else {
+            return false;
+        };
+        if length != text.len() || self.point().line > last.item.span().end_line + 1 {
+            return false;
+        }
+        self.position = self.line_end(self.position);
+        let item = node.children.pop().unwrap().item;
+        let span = item.span().to(self.previous_span());
+        node.children.push(Exp {
+            item: Box::new(Attributed {
+                item,
+                attributes,
+                span,
+            }),
+        });
+        true
+    }
+
+    /* Node
+     * (exp | footnote-definition | abbreviation-definition | attributes | blank-line)
+     * (NEWLINE node)?
+     */
+    fn node(&mut self) -> Node {
+        let mut node = Node { children: vec![] };
+        loop {
+            if self.line_is_blank(self.position) {
+                // A blank line ends the paragraph before it
+                self.paragraph_open = false;
+                self.position = self.line_end(self.position);
+            } else if self.footnote_definition()
+                || self.abbreviation_definition()
+                || self.block_attributes(&mut node)
+            {
+                self.paragraph_open = false;
+            } else {
+                node.children.push(self.exp());
+            }
+            if self.peek().is_none() {
+                break;
+            }
+            self.next_if(TokenType::NEWLINE);
+        }
+        node
+    }
+
+    // Entrypoint - we always begin with a node
+    pub fn parse(&mut self) {
+        self.front_matter();
+        self.tree = self.node();
+        *self.toc.borrow_mut() = self.tree.toc(&self.options.toc_levels);
+        if self.footnotes.borrow().section().is_some() {
+            let span = self.point();
+            self.tree.children.push(Exp {
+                item: Box::new(FootnoteSection {
+                    footnotes: Rc::clone(&self.footnotes),
+                    span,
+                }),
+            });
+        }
+    }
+
+    // Read the front matter at the start of the document, leaving the cursor after it
+    fn front_matter(&mut self) {
+        if !self.options.extensions.front_matter {
+            return;
+        }
+        if let Some((front_matter, length)) = front_matter::parse(&self.source) {
+            self.front_matter = front_matter;
+            while self.peek().is_some_and(|token| token.span.start < length) {
+                self.position += 1;
+            }
+        }
+    }
+
+    // Problems with the document worth telling its author about, such as undefined footnotes
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = self.footnotes.borrow().warnings();
+        warnings.extend(self.wiki_links.borrow().warnings());
+        for (id, unique, line) in &self.duplicate_ids {
+            warnings.push(format!(
+                "line {}: heading id #{} is already taken, using #{}",
+                line + 1,
+                id,
+                unique
+            ));
+        }
+        warnings
+    }
+
+    // What a template may want to know about the page, once it has been parsed
+    pub fn page_data(&self) -> PageData {
+        PageData {
+            title: self.front_matter.title().map(|title| title.to_string()),
+            toc: self.toc.borrow().clone(),
+            heading_ids: self.heading_ids.clone(),
+            tasks: self.tasks,
+            warnings: self.warnings(),
+        }
+    }
+}
+
+// A line of a block, such as a list item, which has had its indentation taken off
+struct StrippedLine {
+    text: String,
+    // Where the text starts in the source
+    byte: usize,
+    line: u32,
+    column: u32,
+}
+
+// The marker of a list item: a bullet, or a number followed by . or )
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ListMarker {
+    Bullet(char),
+    Ordered(u64, char),
+}
+
+impl ListMarker {
+    // Changing the bullet, or the character after the number, starts a new list
+    fn same_list(&self, other: &ListMarker) -> bool {
+        match (self, other) {
+            (ListMarker::Bullet(a), ListMarker::Bullet(b)) => a == b,
+            (ListMarker::Ordered(_, a), ListMarker::Ordered(_, b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ItemStart {
+    marker: ListMarker,
+    // The bytes of the line before the content of the item
+    width: usize,
+    // The column the content starts at, which the lines after it must be indented to
+    columns: usize,
+    // Is there nothing after the marker?
+    empty: bool,
+}
+
+/*
+ * Does the line start a list item: up to three spaces, a marker, then whitespace or the end of
+ * the line
+ */
+fn list_item_start(line: &str) -> Option<ItemStart> {
+    let rest = line.trim_start_matches(' ');
+    let indent = line.len() - rest.len();
+    if indent > 3 {
+        return None;
+    }
+    let (marker, length) = match rest.chars().next()? {
+        c @ ('-' | '+' | '*') => (ListMarker::Bullet(c), 1),
+        _ => {
+            let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            if !(1..=9).contains(&digits) {
+                return None;
+            }
+            match rest[digits..].chars().next()? {
+                delimiter @ ('.' | ')') => (
+                    ListMarker::Ordered(rest[..digits].parse().ok()?, delimiter),
+                    digits + 1,
+                ),
+                _ => return None,
+            }
+        }
+    };
+    let (width, columns, empty) = item_content(line, indent + length)?;
+    Some(ItemStart {
+        marker,
+        width,
+        columns,
+        empty,
+    })
+}
+
+// The bytes of the `>` which starts a line of a blockquote, and the space after it if any
+fn quote_marker(line: &str) -> Option<usize> {
+    let rest = line.trim_start_matches(' ');
+    let indent = line.len() - rest.len();
+    let after = rest.strip_prefix('>')?;
+    if indent > 3 {
+        return None;
+    }
+    match after.starts_with([' ', '\t']) {
+        true => Some(indent + 2),
+        false => Some(indent + 1),
+    }
+}
+
+/*
+ * A line of three or more colons fencing a container, with the kind and title of the container
+ * after it if it opens one. Returns the number of colons, the kind and the title
+ */
+fn container_fence(line: &str) -> Option<(usize, &str, &str)> {
+    let rest = line.trim_start_matches(' ');
+    if line.len() - rest.len() > 3 {
+        return None;
+    }
+    let after = rest.trim_start_matches(':');
+    let colons = rest.len() - after.len();
+    if colons < 3 {
+        return None;
+    }
+    let after = after.trim();
+    let (kind, title) = after.split_once([' ', '\t']).unwrap_or((after, ""));
+    if !kind
+        .chars()
+        .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_'))
+    {
+        return None;
+    }
+    Some((colons, kind, title))
+}
+
+/*
+ * Does the line start a definition in a definition list: up to three spaces, a colon, then
+ * whitespace. Returns the bytes before the content and the column it starts at
+ */
+fn definition_start(line: &str) -> Option<(usize, usize)> {
+    let rest = line.trim_start_matches(' ');
+    let indent = line.len() - rest.len();
+    if indent > 3 || !rest.starts_with(':') {
+        return None;
+    }
+    match item_content(line, indent + 1)? {
+        (_, _, true) => None,
+        (width, columns, false) => Some((width, columns)),
+    }
+}
+
+/*
+ * Where the content starts after the marker of a list item or definition, which ends at byte
+ * `marker_end`: the bytes before it, its column, and whether the line is empty after the marker.
+ * Five or more spaces after the marker make indented code, so only one of them counts
+ */
+fn item_content(line: &str, marker_end: usize) -> Option<(usize, usize, bool)> {
+    let after = &line[marker_end..];
+    if after.trim().is_empty() {
+        return Some((line.len(), marker_end + 1, true));
+    }
+    let content = after.trim_start_matches([' ', '\t']);
+    if content.len() == after.len() {
+        return None;
+    }
+    let mut columns = marker_end;
+    for c in after[..after.len() - content.len()].chars() {
+        columns += if c == '\t' { 4 - columns % 4 } else { 1 };
+    }
+    if columns - marker_end >= 5 {
+        return Some((marker_end + 1, marker_end + 1, false));
+    }
+    Some((line.len() - content.len(), columns, false))
+}
+
+/*
+ * The bytes of whitespace covering the first `columns` columns of the line, or None if the line
+ * isn't indented that far
+ */
+fn strip_columns(line: &str, columns: usize) -> Option<usize> {
+    let mut width = 0;
+    let mut column = 0;
+    for c in line.chars() {
+        if column >= columns {
+            break;
+        }
+        match c {
+            ' ' => column += 1,
+            '\t' => column += 4 - column % 4,
+            _ => return None,
+        }
+        width += 1;
+    }
+    (column >= columns).then_some(width)
+}
+
+// Is the token only whitespace?
+fn is_blank(token: &Token) -> bool {
+    matches!(token.token_type, TokenType::TEXT | TokenType::INDENT) && token.value.trim().is_empty()
+}
+
+// Can the token go before a block marker: no more than three spaces?
+fn is_indent(token: &Token) -> bool {
+    token.token_type == TokenType::TEXT
+        && token.value.len() <= 3
+        && token.value.chars().all(|c| c == ' ')
+}
+
+/*
+ * Take an attribute block, as in `# Heading {#id .class}`, off the end of some inline text, if
+ * `accept` takes the attributes
+ */
+fn trailing_attributes(
+    inlines: &mut Vec<Inline>,
+    accept: impl Fn(&elements::Attributes) -> bool,
+) -> Option<elements::Attributes> {
+    let last = inlines.last_mut().and_then(Inline::text_mut)?;
+    let open = last.text.rfind('{')?;
+    let (attributes, length) = attributes::parse(&last.text[open..])?;
+    if open + length != last.text.len() || !accept(&attributes) {
+        return None;
+    }
+    last.text.truncate(open);
+    trim_inlines(inlines);
+    Some(attributes)
+}
+
+// Strip the whitespace around inline text, such as a heading
+fn trim_inlines(inlines: &mut Vec<Inline>) {
+    if let Some(first) = inlines.first_mut().and_then(Inline::text_mut) {
+        first.text = first.text.trim_start().to_string();
+    }
+    if let Some(last) = inlines.last_mut().and_then(Inline::text_mut) {
+        last.text = last.text.trim_end().to_string();
+    }
+    inlines.retain_mut(|inline| !inline.text_mut().is_some_and(|text| text.text.is_empty()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::options::{Extensions, HtmlFilter, HtmlStyle};
+    use crate::{markdown_to_html, markdown_to_html_with_page_data};
+
+    fn headings_without_ids(source: &str) -> String {
+        let options = Options {
+            extensions: Extensions {
+                heading_ids: false,
+                ..Extensions::default()
+            },
+            ..Options::default()
+        };
+        markdown_to_html(source, &options)
+    }
+
+    #[test]
+    fn atx_headings() {
+        let html = headings_without_ids;
+        assert_eq!(
+            html("# foo\n## *foo*\n###### foo\n"),
+            "<h1>foo</h1>\n<h2><i>foo</i></h2>\n<h6>foo</h6>\n"
+        );
+        assert_eq!(html("####### foo\n"), "<p>####### foo</p>\n");
+        assert_eq!(
+            html("#5 bolt\n#hashtag\n"),
+            "<p>#5 bolt</p>\n<p>#hashtag</p>\n"
+        );
+        assert_eq!(html("\\## foo\n"), "<p>## foo</p>\n");
+        assert_eq!(html("#    foo   \n"), "<h1>foo</h1>\n");
+        assert_eq!(html("   ### foo\n"), "<h3>foo</h3>\n");
+        assert_eq!(html("    # foo\n"), "<pre><code># foo\n</code></pre>\n");
+    }
+
+    #[test]
+    fn atx_closing_sequence() {
+        let html = headings_without_ids;
+        assert_eq!(html("## foo ##\n"), "<h2>foo</h2>\n");
+        assert_eq!(html("# foo ##################\n"), "<h1>foo</h1>\n");
+        assert_eq!(html("### foo ###     \n"), "<h3>foo</h3>\n");
+        assert_eq!(html("### foo ### b\n"), "<h3>foo ### b</h3>\n");
+        assert_eq!(html("# foo#\n"), "<h1>foo#</h1>\n");
+        assert_eq!(html("### foo \\###\n"), "<h3>foo ###</h3>\n");
+        assert_eq!(
+            html("## \n#\n### ###\n"),
+            "<h2></h2>\n<h1></h1>\n<h3></h3>\n"
+        );
+    }
+
+    #[test]
+    fn setext_headings() {
+        let html = headings_without_ids;
+        assert_eq!(
+            html("Foo *bar*\n=========\n\nFoo *bar*\n---------\n"),
+            "<h1>Foo <i>bar</i></h1>\n<h2>Foo <i>bar</i></h2>\n"
+        );
+        assert_eq!(html("Foo\n=\n"), "<h1>Foo</h1>\n");
+        assert_eq!(html("   Foo\n   ===   \n"), "<h1>Foo</h1>\n");
+        assert_eq!(html("Foo\n    ---\n"), "<p>Foo</p>\n<p>---</p>\n");
+        assert_eq!(html("Foo\n= =\n"), "<p>Foo</p>\n<p>= =</p>\n");
+        assert_eq!(html("Foo\n\n===\n"), "<p>Foo</p>\n<p>===</p>\n");
+        assert_eq!(html("# Foo\n===\n"), "<h1>Foo</h1>\n<p>===</p>\n");
+        let spans: Vec<Span> = parse("Foo\n---\n")
+            .tree
+            .children
+            .iter()
+            .map(|child| child.item.span())
+            .collect();
+        assert_eq!(spans.len(), 1);
+        assert_eq!((spans[0].start, spans[0].end, spans[0].end_line), (0, 7, 1));
+    }
+
+    #[test]
+    fn heading_ids() {
+        let html = |source: &str| markdown_to_html(source, &Options::default());
+        assert_eq!(
+            html("# Hello, *World*!\nIntro\n---\n## Intro\n"),
+            "<h1 id=\"hello-world\">Hello, <i>World</i>!</h1>\n\
+             <h2 id=\"intro\">Intro</h2>\n<h2 id=\"intro-1\">Intro</h2>\n"
+        );
+        assert_eq!(
+            html("# Install {#setup}\n## Setup\nUsage {#use-it}\n===\n"),
+            "<h1 id=\"setup\">Install</h1>\n<h2 id=\"setup-1\">Setup</h2>\n\
+             <h1 id=\"use-it\">Usage</h1>\n"
+        );
+        // Not an id
+        assert_eq!(html("# a {# b}\n"), "<h1 id=\"a--b\">a {# b}</h1>\n");
+        assert_eq!(headings_without_ids("# a {#b}\n"), "<h1>a {#b}</h1>\n");
+
+        let parser = parse("# One\ntext\n## Two\n# One\n");
+        assert_eq!(parser.heading_ids, ["one", "two", "one-1"]);
+
+        // Ids given twice are made unique like slugs, with a warning
+        let parser = parse("# a {#x}\n\n# b {#x}\n");
+        assert_eq!(parser.heading_ids, ["x", "x-1"]);
+        assert_eq!(
+            parser.warnings(),
+            ["line 3: heading id #x is already taken, using #x-1"]
+        );
+        // As on GitHub, the first heading without letters or numbers has an empty slug
+        assert_eq!(
+            html(
+                "# !!
+# ?
+"
+            ),
+            "<h1>!!</h1>\n<h1 id=\"-1\">?</h1>\n"
+        );
+    }
+
+    #[test]
+    fn permalinks() {
+        let options = Options {
+            heading_permalinks: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            markdown_to_html("## Usage\n", &options),
+            "<h2 id=\"usage\">Usage<a class=\"permalink\" href=\"#usage\" aria-hidden=\"true\">¶</a></h2>\n"
+        );
+    }
+
+    #[test]
+    fn toc() {
+        let source = "# Guide\n[[toc]]\n## Install\n### Linux\n## Usage\n#### Flags\n# Appendix\n";
+        let html = markdown_to_html(source, &Options::default());
+        assert_eq!(
+            html.lines().nth(1).unwrap(),
+            "<nav class=\"toc\"><ul><li><a href=\"#guide\">Guide</a><ul>\
+             <li><a href=\"#install\">Install</a><ul><li><a href=\"#linux\">Linux</a></li></ul></li>\
+             <li><a href=\"#usage\">Usage</a><ul><li><a href=\"#flags\">Flags</a></li></ul></li>\
+             </ul></li><li><a href=\"#appendix\">Appendix</a></li></ul></nav>"
+        );
+
+        let options = Options {
+            toc_levels: 2..=3,
+            ..Options::default()
+        };
+        let html = markdown_to_html(source, &options);
+        assert_eq!(
+            html.lines().nth(1).unwrap(),
+            "<nav class=\"toc\"><ul><li><a href=\"#install\">Install</a>\
+             <ul><li><a href=\"#linux\">Linux</a></li></ul></li>\
+             <li><a href=\"#usage\">Usage</a></li></ul></nav>"
+        );
+    }
+
+    #[test]
+    fn toc_structure() {
+        let parser = parse("## *Two*\n# One\n### Three\n");
+        let toc = parser.tree.toc(&(1..=6));
+        assert_eq!(toc.len(), 2);
+        assert_eq!((toc[0].level, toc[0].text.as_str()), (2, "Two"));
+        assert_eq!(toc[1].id.as_deref(), Some("one"));
+        assert_eq!(toc[1].children[0].text, "Three");
+
+        let html = markdown_to_html("[[TOC]]\n", &Options::default());
+        assert_eq!(html, "<nav class=\"toc\"></nav>\n");
+        let options = Options {
+            extensions: Extensions::none(),
+            ..Options::default()
+        };
+        let html = markdown_to_html("[[toc]]\n# Title\n", &options);
+        assert_eq!(html, "<p>[[toc]]</p>\n<h1>Title</h1>\n");
+    }
+
+    #[test]
+    fn thematic_breaks() {
+        let html = |source: &str| markdown_to_html(source, &Options::default());
+        assert_eq!(html("***\n---\n___\n"), "<hr>\n<hr>\n<hr>\n");
+        assert_eq!(
+            html(" - - -\n**  * ** * ** * **\n-     -      -      -\n_____________________________________\n"),
+            "<hr>\n<hr>\n<hr>\n<hr>\n"
+        );
+        assert_eq!(
+            html("+++\n\n===\n\n--\n\n**\n"),
+            "<p>+++</p>\n<p>===</p>\n<p>--</p>\n<p>**</p>\n"
+        );
+        assert_eq!(html("    ***\n"), "<pre><code>***\n</code></pre>\n");
+        assert_eq!(
+            html("_ _ _ _ a\n---a---\n*-*\n"),
+            "<p>_ _ _ _ a</p>\n<p>---a---</p>\n<p><i>-</i></p>\n"
+        );
+        // An underline makes a heading instead
+        assert_eq!(
+            html("Foo\n---\nbar\n***\n"),
+            "<h2 id=\"foo\">Foo</h2>\n<p>bar</p>\n<hr>\n"
+        );
+        let options = Options {
+            html_style: HtmlStyle::Xhtml,
+            sourcepos: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            markdown_to_html("* * *\n", &options),
+            "<hr data-sourcepos=\"1:1-1:5\" />\n"
+        );
+    }
+
+    #[test]
+    fn indented_code() {
+        let html = |source: &str| markdown_to_html(source, &Options::default());
+        assert_eq!(
+            html("    a simple\n      indented code block\n"),
+            "<pre><code>a simple\n  indented code block\n</code></pre>\n"
+        );
+        assert_eq!(
+            html("    chunk1\n\n    chunk2\n  \n \n \n    chunk3\n"),
+            "<pre><code>chunk1\n\nchunk2\n\n\n\nchunk3\n</code></pre>\n"
+        );
+        assert_eq!(
+            html("    chunk1\n      \n      chunk2\n"),
+            "<pre><code>chunk1\n  \n  chunk2\n</code></pre>\n"
+        );
+        assert_eq!(
+            html("\tfoo\tbaz\n    <a/>\n    *hi*\n\n    - one\n"),
+            "<pre><code>foo\tbaz\n&lt;a/&gt;\n*hi*\n\n- one\n</code></pre>\n"
+        );
+        // Blank lines around the block are not part of it
+        assert_eq!(
+            html("# Heading\n\n    foo\n\n\nbar\n"),
+            "<h1 id=\"heading\">Heading</h1>\n<pre><code>foo\n</code></pre>\n<p>bar</p>\n"
+        );
+        // Indentation can't interrupt a paragraph
+        assert_eq!(html("Foo\n    bar\n"), "<p>Foo</p>\n<p>bar</p>\n");
+        assert_eq!(
+            html("Foo\n\n    bar\n"),
+            "<p>Foo</p>\n<pre><code>bar\n</code></pre>\n"
+        );
+
+        let parser = parse("    a\n\n    b\nc\n");
+        let span = parser.tree.children[0].item.span();
+        assert_eq!((span.start, span.end, span.end_line), (0, 12, 2));
+    }
+
+    #[test]
+    fn lists() {
+        let html = |source: &str| markdown_to_html(source, &Options::default());
+        assert_eq!(
+            html("- a\n- *b*\n\n+ c\n"),
+            "<ul><li>a</li><li><i>b</i></li></ul>\n<ul><li>c</li></ul>\n"
+        );
+        assert_eq!(
+            html("3. three\n4. four\n1) one\n"),
+            "<ol start=\"3\"><li>three</li><li>four</li></ol>\n<ol><li>one</li></ol>\n"
+        );
+        // Nested lists, and blocks inside an item
+        assert_eq!(
+            html("- a\n  - b\n\n    ```\n    c\n    ```\n- d\n"),
+            "<ul><li>a\n<ul><li><p>b</p>\n<pre><code>\nc\n</code></pre></li></ul></li><li>d</li></ul>\n"
+        );
+        // A blank line between items makes the list loose
+        assert_eq!(
+            html("1. a\n\n2. b\n"),
+            "<ol><li><p>a</p></li><li><p>b</p></li></ol>\n"
+        );
+        assert_eq!(
+            html("-     code\n\n      more\n"),
+            "<ul><li><pre><code>code\n\nmore\n</code></pre></li></ul>\n"
+        );
+        assert_eq!(
+            html("- a\nb\n* * *\n-\n  c\n"),
+            "<ul><li>a</li></ul>\n<p>b</p>\n<hr>\n<ul><li>c</li></ul>\n"
+        );
+        // Only a bullet or a 1 can interrupt a paragraph
+        assert_eq!(
+            html("a\n2. b\nc\n*\n"),
+            "<p>a</p>\n<p>2. b</p>\n<p>c</p>\n<p>*</p>\n"
+        );
+    }
+
+    #[test]
+    fn task_lists() {
+        let html = |source: &str, options: &Options| markdown_to_html(source, options);
+        let source = "- [ ] todo\n- [x] *done*\n  - [X] sub\n- [ ]\n- [y] no\n";
+        assert_eq!(
+            html(source, &Options::default()),
+            "<ul><li class=\"task-list-item\"><input type=\"checkbox\" disabled> todo</li>\
+             <li class=\"task-list-item\"><input type=\"checkbox\" disabled checked> <i>done</i>\n\
+             <ul><li class=\"task-list-item\"><input type=\"checkbox\" disabled checked> sub</li></ul></li>\
+             <li>[ ]</li><li>[y] no</li></ul>\n"
+        );
+        let options = Options {
+            html_style: HtmlStyle::Xhtml,
+            ..Options::default()
+        };
+        assert_eq!(
+            html("1. [ ] a\n", &options),
+            "<ol><li class=\"task-list-item\"><input type=\"checkbox\" disabled=\"disabled\" /> a</li></ol>\n"
+        );
+        let options = Options {
+            extensions: Extensions {
+                task_lists: false,
+                ..Extensions::default()
+            },
+            ..Options::default()
+        };
+        assert_eq!(html("- [x] a\n", &options), "<ul><li>[x] a</li></ul>\n");
+    }
+
+    #[test]
+    fn page_data() {
+        let source = "# Status\n\n- [x] a\n- [ ] b\n  - [x] c\n\n1. [ ] d\n";
+        let (_, page) = markdown_to_html_with_page_data(source, &Options::default());
+        assert_eq!(page.tasks.open, 2);
+        assert_eq!(page.tasks.completed, 2);
+        assert_eq!(page.tasks.total(), 4);
+        assert_eq!(page.heading_ids, vec!["status"]);
+        assert_eq!(page.toc.len(), 1);
+    }
+
+    #[test]
+    fn list_spans() {
+        let parser = parse("- a\n  - *b*\n");
+        assert_eq!(
+            parser.tree.children[0].item.span(),
+            Span {
+                start: 0,
+                end: 11,
+                line: 0,
+                column: 0,
+                end_line: 1,
+                end_column: 7,
+            }
+        );
+        let html = markdown_to_html(
+            "- a\n  - *b*\n",
+            &Options {
+                sourcepos: true,
+                ..Options::default()
+            },
+        );
+        assert_eq!(
+            html,
+            "<ul data-sourcepos=\"1:1-2:7\"><li>a\n<ul data-sourcepos=\"2:3-2:7\"><li><i>b</i></li></ul></li></ul>\n"
+        );
+    }
+
+    #[test]
+    fn definition_lists() {
+        let html = |source: &str| markdown_to_html(source, &Options::default());
+        assert_eq!(
+            html("Apple\n: A *fruit*\n: A company\nPear\n:   Also a fruit\n"),
+            "<dl><dt>Apple</dt><dd>A <i>fruit</i></dd><dd>A company</dd>\
+             <dt>Pear</dt><dd>Also a fruit</dd></dl>\n"
+        );
+        assert_eq!(
+            html("Term\n\n: One\n\n      code\n\n  Two\nAfter\n"),
+            "<dl><dt>Term</dt><dd><p>One</p>\n<pre><code>code\n</code></pre>\n<p>Two</p></dd></dl>\n\
+             <p>After</p>\n"
+        );
+        assert_eq!(html(": no term\n"), "<p>: no term</p>\n");
+        let options = Options {
+            extensions: Extensions {
+                definition_lists: false,
+                ..Extensions::default()
+            },
+            ..Options::default()
+        };
+        assert_eq!(
+            markdown_to_html("Term\n: def\n", &options),
+            "<p>Term</p>\n<p>: def</p>\n"
+        );
+    }
+
+    #[test]
+    fn blockquotes() {
+        let html = |source: &str| markdown_to_html(source, &Options::default());
+        assert_eq!(
+            html("> a *b*\n>\n>- c\n> > d\ne\n"),
+            "<blockquote><p>a <i>b</i></p>\n<ul><li>c</li></ul>\n\
+             <blockquote><p>d</p></blockquote></blockquote>\n<p>e</p>\n"
+        );
+        assert_eq!(
+            html(">     code\n    > not a quote\n"),
+            "<blockquote><pre><code>code\n</code></pre></blockquote>\n\
+             <pre><code>&gt; not a quote\n</code></pre>\n"
+        );
+    }
+
+    #[test]
+    fn admonitions() {
+        let html = |source: &str, options: &Options| markdown_to_html(source, options);
+        let options = Options::default();
+        assert_eq!(
+            html("> [!WARNING]\n> Be *careful*\n", &options),
+            "<div class=\"admonition warning\"><p class=\"admonition-title\">Warning</p>\n\
+             <p>Be <i>careful</i></p></div>\n"
+        );
+        assert_eq!(
+            html("> [!Tip] Pro <tip>\n", &options),
+            "<div class=\"admonition tip\"><p class=\"admonition-title\">Pro &lt;tip&gt;</p></div>\n"
+        );
+        assert_eq!(
+            html(
+                ":::note\na\n\n::::caution Watch out\n```\nb\n```\n::::\n:::\nc\n",
+                &options
+            ),
+            "<div class=\"admonition note\"><p class=\"admonition-title\">Note</p>\n<p>a</p>\n\
+             <div class=\"admonition caution\"><p class=\"admonition-title\">Watch out</p>\n\
+             <pre><code>\nb\n</code></pre></div></div>\n<p>c</p>\n"
+        );
+        assert_eq!(
+            html("> [!DANGER]\n:::danger\n", &options),
+            "<blockquote><p>[!DANGER]</p></blockquote>\n<p>:::danger</p>\n"
+        );
+        let options = Options {
+            admonition_types: vec!["danger".to_string()],
+            ..Options::default()
+        };
+        assert_eq!(
+            html(":::danger\n", &options),
+            "<div class=\"admonition danger\"><p class=\"admonition-title\">Danger</p></div>\n"
+        );
+        let options = Options {
+            extensions: Extensions {
+                admonitions: false,
+                ..Extensions::default()
+            },
+            ..Options::default()
+        };
+        assert_eq!(
+            html("> [!NOTE]\n", &options),
+            "<blockquote><p>[!NOTE]</p></blockquote>\n"
+        );
+    }
+
+    #[test]
+    fn math() {
+        let html = |source: &str| markdown_to_html(source, &Options::default());
+        assert_eq!(
+            html("$$\n\\frac{1}{x}\n$$\n$$ \\alpha $$\n"),
+            "<math display=\"block\"><mfrac><mn>1</mn><mi>x</mi></mfrac></math>\n\
+             <math display=\"block\"><mi>α</mi></math>\n"
+        );
+        assert_eq!(
+            html("a $\\unknown<x>$ b\n\n$$\n\\foo\n"),
+            "<p>a <code class=\"math\">\\unknown&lt;x&gt;</code> b</p>\n\
+             <code class=\"math math-display\">\\foo</code>\n"
+        );
+        let options = Options {
+            extensions: Extensions {
+                math: false,
+                ..Extensions::default()
+            },
+            ..Options::default()
+        };
+        assert_eq!(
+            markdown_to_html("$x$\n$$\n", &options),
+            "<p>$x$</p>\n<p>$$</p>\n"
+        );
+    }
+
+    #[test]
+    fn custom_emoji() {
+        let mut options = Options::default();
+        options
+            .custom_emoji
+            .insert("shipit".to_string(), "/emoji/shipit.png".to_string());
+        assert_eq!(
+            markdown_to_html(":shipit: :warning:\n", &options),
+            "<p><img class=\"emoji\" src=\"/emoji/shipit.png\" alt=\":shipit:\" \
+             title=\":shipit:\"> ⚠\u{fe0f}</p>\n"
+        );
+    }
+
+    #[test]
+    fn footnotes() {
+        let source = "a[^x] b[^1] c[^X] d[^none]\n\n[^1]: One\n[^x]: *Two*\n\n    more\n";
+        let backref = |id: &str, number: usize, label: &str| {
+            format!(
+                " <a href=\"#{}\" class=\"footnote-backref\" aria-label=\"Back to reference {}\">↩{}</a>",
+                id, number, label
+            )
+        };
+        assert_eq!(
+            markdown_to_html(source, &Options::default()),
+            format!(
+                "<p>a<sup class=\"footnote-ref\"><a href=\"#fn-1\" id=\"fnref-1\">1</a></sup> \
+                 b<sup class=\"footnote-ref\"><a href=\"#fn-2\" id=\"fnref-2\">2</a></sup> \
+                 c<sup class=\"footnote-ref\"><a href=\"#fn-1\" id=\"fnref-1-2\">1</a></sup> d[^none]</p>\n\
+                 <section class=\"footnotes\"><ol><li id=\"fn-1\"><p><i>Two</i></p>\n<p>more{}{}</p></li>\
+                 <li id=\"fn-2\"><p>One{}</p></li></ol></section>\n",
+                backref("fnref-1", 1, ""),
+                backref("fnref-1-2", 1, "<sup>2</sup>"),
+                backref("fnref-2", 2, "")
+            )
+        );
+        let options = Options {
+            extensions: Extensions {
+                footnotes: false,
+                superscript: false,
+                ..Extensions::default()
+            },
+            ..Options::default()
+        };
+        assert_eq!(
+            markdown_to_html("a[^1]\n\n[^1]: b\n", &options),
+            "<p>a[^1]</p>\n<p>[^1]: b</p>\n"
+        );
+    }
+
+    #[test]
+    fn footnote_warnings() {
+        let source = "[^unused]: x\n\na[^1] b[^missing]\n\n[^1]: One\n";
+        let (_, page) = markdown_to_html_with_page_data(source, &Options::default());
+        assert_eq!(
+            page.warnings,
+            [
+                "line 3: footnote [^missing] is never defined",
+                "line 1: footnote [^unused] is never used"
+            ]
+        );
+    }
+
+    #[test]
+    fn front_matter() {
+        let options = Options {
+            extensions: Extensions {
+                front_matter: true,
+                ..Extensions::default()
+            },
+            ..Options::default()
+        };
+        let source = "---\ntitle: Release notes\n---\n# 2.0\n";
+        let (html, page) = markdown_to_html_with_page_data(source, &options);
+        assert_eq!(html, "<h1 id=\"20\">2.0</h1>\n");
+        assert_eq!(page.title.as_deref(), Some("Release notes"));
+        // A thematic break above a paragraph isn't front matter
+        assert_eq!(
+            markdown_to_html("---\nSome intro paragraph\n\n---\nMore text\n", &options),
+            "<hr>\n<p>Some intro paragraph</p>\n<hr>\n<p>More text</p>\n"
+        );
+        assert_eq!(
+            markdown_to_html("---\ntitle: a\n---\n", &Options::default()),
+            "<hr>\n<h2 id=\"title-a\">title: a</h2>\n"
+        );
+    }
+
+    #[test]
+    fn wiki_links() {
+        let mut options = Options::default();
+        options
+            .wiki_pages
+            .insert("Home".to_string(), "../index.html".to_string());
+        let (html, page) =
+            markdown_to_html_with_page_data("Back [[home]]\n\n[[Nowhere|x]]\n", &options);
+        assert_eq!(
+            html,
+            "<p>Back <a href=\"../index.html\">home</a></p>\n<p>[[Nowhere|x]]</p>\n"
+        );
+        assert_eq!(
+            page.warnings,
+            ["line 3: wiki link [[Nowhere]] does not match any page"]
+        );
+    }
+
+    #[test]
+    fn block_attributes() {
+        let html = |source: &str| markdown_to_html(source, &Options::default());
+        assert_eq!(
+            html("Intro text\n{: .lead #intro}\n\n# Setup {#setup .wide}\n"),
+            "<p id=\"intro\" class=\"lead\">Intro text</p>\n\
+             <h1 id=\"setup\" class=\"wide\">Setup</h1>\n"
+        );
+        assert_eq!(
+            html("- a\n- b\n{.steps}\n\n> quote\n{data-x=\"<&>\"}\n"),
+            "<ul class=\"steps\"><li>a</li><li>b</li></ul>\n\
+             <blockquote data-x=\"&lt;&amp;&gt;\"><p>quote</p></blockquote>\n"
+        );
+        // After a blank line, or with nothing to go on, the line is text
+        assert_eq!(
+            html("{.a}\n\nb\n\n{.c}\n"),
+            "<p>{.a}</p>\n<p>b</p>\n<p>{.c}</p>\n"
+        );
+        // Headings keep their ids in the table of contents
+        assert_eq!(
+            html("[[toc]]\n# A\n{#first}\n"),
+            "<nav class=\"toc\"><ul><li><a href=\"#first\">A</a></li></ul></nav>\n\
+             <h1 id=\"first\">A</h1>\n"
+        );
+        assert_eq!(
+            markdown_to_html(
+                "[a](/b){onclick=\"x()\" title=t .c}\n:::note\nn\n:::\n{.d}\n",
+                &Options::safe()
+            ),
+            "<p><a href=\"/b\" class=\"c\" title=\"t\">a</a></p>\n\
+             <div class=\"admonition note d\"><p class=\"admonition-title\">Note</p>\n\
+             <p>n</p></div>\n"
+        );
+        // Attributes the element writes itself aren't written again
+        assert_eq!(
+            html("![a](b.png \"t\"){alt=\"z\" title=u width=2}\n\n3. c\n{start=1}\n"),
+            "<p><img src=\"b.png\" alt=\"a\" title=\"t\" width=\"2\"></p>\n\
+             <ol start=\"3\"><li>c</li></ol>\n"
+        );
+        assert_eq!(
+            markdown_to_html(
+                "[x](y){href=\"javascript:alert(1)\" title=t}\n",
+                &Options::safe()
+            ),
+            "<p><a href=\"y\" title=\"t\">x</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn abbreviations() {
+        // Defined after it is used, and not marked up in code or inside other words
+        let source =
+            "The HTML spec, `HTML` and HTMLish\n\n*[HTML]: Hyper Text \"Markup\" Language\n";
+        assert_eq!(
+            markdown_to_html(source, &Options::default()),
+            "<p>The <abbr title=\"Hyper Text &quot;Markup&quot; Language\">HTML</abbr> spec, \
+             <code>HTML</code> and HTMLish</p>\n"
+        );
+        let mut options = Options::default();
+        options.extensions.abbreviations = false;
+        assert_eq!(
+            markdown_to_html("HTML\n*[HTML]: x\n", &options),
+            "<p>HTML</p>\n<p>*[HTML]: x</p>\n"
+        );
+    }
+
+    #[test]
+    fn inline_extensions() {
+        let html = markdown_to_html("~~old~~ ==new== E=mc^2^ H~2~O\n", &Options::default());
+        assert_eq!(
+            html,
+            "<p><del>old</del> <mark>new</mark> E=mc<sup>2</sup> H<sub>2</sub>O</p>\n"
+        );
+    }
+
+    #[test]
+    fn html_blocks() {
+        let html = |source: &str| markdown_to_html(source, &Options::default());
+        assert_eq!(
+            html("<details>\n<summary>*More*</summary>\n\n*text*\n\n</details>\n"),
+            "<details>\n<summary>*More*</summary>\n<p><i>text</i></p>\n</details>\n"
+        );
+        assert_eq!(
+            html("<pre>\n**raw**\n\nstill raw\n</pre>\nafter\n"),
+            "<pre>\n**raw**\n\nstill raw\n</pre>\n<p>after</p>\n"
+        );
+        assert_eq!(
+            html("<!-- a\n\ncomment -->\n<iframe src=\"/x\"></iframe>\n"),
+            "<!-- a\n\ncomment -->\n<iframe src=\"/x\"></iframe>\n"
+        );
+        assert_eq!(html("<custom-element>\n*hi*\n"), "<custom-element>\n*hi*\n");
+        // A lone tag of another kind can't interrupt a paragraph
+        assert_eq!(html("Foo\n<custom>\n"), "<p>Foo</p>\n<p><custom></p>\n");
+        assert_eq!(html("<span>*a*</span>\n"), "<p><span><i>a</i></span></p>\n");
+        assert_eq!(
+            html("    <div>\n"),
+            "<pre><code>&lt;div&gt;\n</code></pre>\n"
+        );
+    }
+
+    #[test]
+    fn raw_html_disabled() {
+        let options = Options {
+            extensions: Extensions {
+                raw_html: false,
+                ..Extensions::default()
+            },
+            ..Options::default()
+        };
+        assert_eq!(
+            markdown_to_html("<div>\n<b>x</b> & y\n", &options),
+            "<p>&lt;div&gt;</p>\n<p>&lt;b&gt;x&lt;/b&gt; &amp; y</p>\n"
+        );
+    }
+
+    #[test]
+    fn safe_mode_html() {
+        let html = |source: &str| markdown_to_html(source, &Options::safe());
+        assert_eq!(
+            html("<details open onclick=\"x()\">\n<script>alert(1)</script>\n</details>\n"),
+            "<details open>\n&lt;script&gt;alert(1)&lt;/script&gt;\n</details>\n"
+        );
+        assert_eq!(
+            html("<b>ok</b> <a href=\"javascript:x\" style=\"y\">no</a>\n"),
+            "<p><b>ok</b> <a>no</a></p>\n"
+        );
+        let options = Options {
+            html_filter: HtmlFilter::Strip,
+            ..Options::safe()
+        };
+        assert_eq!(
+            markdown_to_html(
+                "<iframe src=\"/x\"></iframe>\n<u>a</u><font>b</font>\n",
+                &options
+            ),
+            "\n<u>a</u>b\n"
+        );
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        let source = "1 < 2 & &lt;b&gt; \"3\" > 2\n";
+        let html = markdown_to_html(source, &Options::default());
+        assert_eq!(
+            html,
+            "<p>1 &lt; 2 &amp; &lt;b&gt; &quot;3&quot; &gt; 2</p>\n"
+        );
+    }
+
+    #[test]
+    fn backslash_escapes() {
+        let html = |source: &str| markdown_to_html(source, &Options::default());
+        assert_eq!(
+            html("\\!\\\"\\#\\$\\%\\&\\'\\(\\)\\*\\+\\,\\-\\.\\/\\:\\;\\<\\=\\>\\?\\@\\[\\\\\\]\\^\\_\\`\\{\\|\\}\\~\n"),
+            "<p>!&quot;#$%&amp;'()*+,-./:;&lt;=&gt;?@[\\]^_`{|}~</p>\n"
+        );
+        // Only punctuation can be escaped
+        assert_eq!(
+            html("\\\t\\A\\a\\ \\3\\φ\\«\n"),
+            "<p>\\\t\\A\\a\\ \\3\\φ\\«</p>\n"
+        );
+        assert_eq!(html("\\*not emphasized*\n"), "<p>*not emphasized*</p>\n");
+        assert_eq!(
+            html("\\[not a link](/foo)\n"),
+            "<p>[not a link](/foo)</p>\n"
+        );
+        assert_eq!(html("\\## not a heading\n"), "<p>## not a heading</p>\n");
+        assert_eq!(html("\\\\*emphasis*\n"), "<p>\\<i>emphasis</i></p>\n");
+        // Code is left alone
+        assert_eq!(html("`\\[\\*`\n"), "<p><code>\\[\\*</code></p>\n");
+        assert_eq!(
+            html("```\n\\[\\]\n```\n"),
+            "<pre><code>\n\\[\\]\n</code></pre>\n"
+        );
+    }
+
+    #[test]
+    fn entities() {
+        let html = |source: &str| markdown_to_html(source, &Options::default());
+        assert_eq!(
+            html("&nbsp; &amp; &copy; &AElig; &Dcaron;\n"),
+            "<p>\u{a0} &amp; © Æ Ď</p>\n"
+        );
+        assert_eq!(
+            html("&frac34; &HilbertSpace; &DifferentialD; &ngE;\n"),
+            "<p>¾ ℋ ⅆ ≧̸</p>\n"
+        );
+        assert_eq!(
+            html("&#35; &#1234; &#992; &#0;\n"),
+            "<p># Ӓ Ϡ \u{fffd}</p>\n"
+        );
+        assert_eq!(html("&#X22; &#XD06; &#xcab;\n"), "<p>&quot; ആ ಫ</p>\n");
+        assert_eq!(html("&#x2014;\n"), "<p>\u{2014}</p>\n");
+        // Not entities at all
+        assert_eq!(
+            html("&nbsp &x; &#; &#x; &#87654321; &#abcdef0; &ThisIsNotDefined; &hi?;\n"),
+            "<p>&amp;nbsp &amp;x; &amp;#; &amp;#x; &amp;#87654321; &amp;#abcdef0; \
+             &amp;ThisIsNotDefined; &amp;hi?;</p>\n"
+        );
+        assert_eq!(html("&copy\n"), "<p>&amp;copy</p>\n");
+        // Entities are text, not Markdown
+        assert_eq!(html("&#42;foo&#42;\n"), "<p>*foo*</p>\n");
+        assert_eq!(
+            html("`f&ouml;&ouml;`\n"),
+            "<p><code>f&amp;ouml;&amp;ouml;</code></p>\n"
+        );
+        assert_eq!(
+            html("[a](/f&ouml;&ouml; \"f&ouml;&ouml;\")\n"),
+            "<p><a href=\"/föö\" title=\"föö\">a</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn nested_inlines() {
+        let source = "**bold [a link with `code`](/url \"Title\")** ![*alt*](/a.png)\n";
+        let html = markdown_to_html(source, &Options::default());
+        assert_eq!(
+            html,
+            "<p><b>bold <a href=\"/url\" title=\"Title\">a link with <code>code</code></a></b> \
+             <img src=\"/a.png\" alt=\"alt\"></p>\n"
+        );
+    }
+
+    #[test]
+    fn safe_mode_links() {
+        let source = "[a](javascript:alert(1))\n";
+        let html = markdown_to_html(source, &Options::default());
+        assert_eq!(html, "<p><a href=\"javascript:alert(1)\">a</a></p>\n");
+        let html = markdown_to_html(source, &Options::safe());
+        assert_eq!(html, "<p><a>a</a></p>\n");
+        let html = markdown_to_html("![a](data:image/png;base64,x)\n", &Options::safe());
+        assert_eq!(html, "<p><img alt=\"a\"></p>\n");
+    }
+
+    #[test]
+    fn xhtml() {
+        use crate::types::elements::{Renderable, Stylesheet};
+        let options = Options {
+            html_style: HtmlStyle::Xhtml,
+            ..Options::default()
+        };
+        let link = Stylesheet::new("palpad.css");
+        assert_eq!(
+            link.render(&options),
+            "<link rel=\"stylesheet\" href=\"palpad.css\" />"
+        );
+        assert_eq!(
+            link.render(&Options::default()),
+            "<link rel=\"stylesheet\" href=\"palpad.css\">"
+        );
+    }
+
+    fn parse(source: &str) -> Parser {
+        let options = Options::default();
+        let mut lexer = Lexer::new(source, &options);
+        lexer.scan();
+        let mut parser = Parser::new(lexer, &options);
+        parser.parse();
+        parser
+    }
+
+    #[test]
+    fn spans() {
+        let parser = parse("# Title\n\nSome *text*\n```\ncode\n```\n");
+        let spans: Vec<Span> = parser.tree.children.iter().map(|c| c.item.span()).collect();
+        assert_eq!(
+            spans,
+            [
+                Span {
+                    start: 0,
+                    end: 7,
+                    line: 0,
+                    column: 0,
+                    end_line: 0,
+                    end_column: 7,
+                },
+                Span {
+                    start: 9,
+                    end: 20,
+                    line: 2,
+                    column: 0,
+                    end_line: 2,
+                    end_column: 11,
+                },
+                Span {
+                    start: 21,
+                    end: 33,
+                    line: 3,
+                    column: 0,
+                    end_line: 5,
+                    end_column: 3,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn long_document() {
+        let mut source = String::new();
+        for line in 0..3000 {
+            source.push_str(&format!(
+                "{} *emphasis* on line {}\n",
+                "x".repeat(300),
+                line
+            ));
+        }
+        let parser = parse(&source);
+        assert_eq!(parser.tree.children.len(), 3000);
+        let last = parser.tree.children.last().unwrap().item.span();
+        assert_eq!(last.line, 2999);
+        assert_eq!(last.column, 0);
+        assert_eq!(last.end_line, 2999);
+        assert_eq!(last.end_column, 324);
+        assert_eq!(last.end, source.len() - 1);
+    }
+
+    #[test]
+    fn deep_nesting() {
+        // Past the limit the markers are text, rather than running the parser out of stack
+        let html = markdown_to_html(&format!("{} a\n", ">".repeat(5000)), &Options::default());
+        assert_eq!(html.matches("<blockquote>").count(), MAX_NESTING);
+        assert!(html.contains(&format!("<p>{} a</p>", "&gt;".repeat(5000 - MAX_NESTING))));
+        let html = markdown_to_html(&":::note\n> ".repeat(5000), &Options::default());
+        assert_eq!(
+            html.matches("<div class=\"admonition note\">").count()
+                + html.matches("<blockquote>").count(),
+            MAX_NESTING
+        );
+        let html = markdown_to_html(&format!("{}a\n", "- ".repeat(5000)), &Options::default());
+        assert_eq!(html.matches("<ul>").count(), MAX_NESTING);
+        let source: String = (0..300)
+            .map(|level| format!("{}- a\n", "  ".repeat(level)))
+            .collect();
+        let html = markdown_to_html(&source, &Options::default());
+        assert_eq!(html.matches("<ul>").count(), MAX_NESTING);
+        // The definitions past the limit are left as references followed by a colon
+        let source = format!("{}b\n\n[^a]\n", "[^a]: ".repeat(5000));
+        let html = markdown_to_html(&source, &Options::default());
+        assert_eq!(html.matches("</sup>: ").count(), 5000 - MAX_NESTING);
+    }
+
+    #[test]
+    fn unterminated_input() {
+        // None of these end in a newline, or close what they open
+        for source in [
+            "text",
+            "**bold",
+            "`code",
+            "# heading",
+            "```\ncode",
+            "| a |\n|---|",
+        ] {
+            parse(source);
+        }
     }
 }
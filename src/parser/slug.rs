@@ -0,0 +1,86 @@
+/*
+ * Heading slugs.
+ *
+ * Turn heading text into an id the same way GitHub does, so that links written against a
+ * README on GitHub keep working on the generated page.
+ */
+use std::collections::HashMap;
+
+/*
+ * Lower case the text, drop everything but letters, numbers, `-` and `_`, and turn spaces into
+ * hyphens. "Hello, World!" becomes "hello-world"
+ */
+pub fn slugify(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ' '))
+        .map(|c| if c == ' ' { '-' } else { c })
+        .collect()
+}
+
+/*
+ * Hands out unique ids. A slug that is already taken gets a -1, -2, ... suffix, in the order the
+ * duplicates appear. Like on GitHub, text without any letters or numbers slugs to "", and then to
+ * "-1", "-2", ...
+ */
+#[derive(Debug, Default)]
+pub struct Slugger {
+    occurrences: HashMap<String, usize>,
+}
+
+impl Slugger {
+    pub fn new() -> Self {
+        Slugger {
+            occurrences: HashMap::new(),
+        }
+    }
+
+    // A unique id for a heading with this text
+    pub fn slug(&mut self, text: &str) -> String {
+        self.unique(slugify(text))
+    }
+
+    // An id chosen by the author, suffixed the same way if an earlier heading already has it
+    pub fn reserve(&mut self, id: &str) -> String {
+        self.unique(id.to_string())
+    }
+
+    fn unique(&mut self, original: String) -> String {
+        let mut slug = original.clone();
+        while self.occurrences.contains_key(&slug) {
+            let count = self.occurrences.get_mut(&original).unwrap();
+            *count += 1;
+            slug = format!("{}-{}", original, count);
+        }
+        self.occurrences.insert(slug.clone(), 0);
+        slug
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("Options & flags"), "options--flags");
+        assert_eq!(slugify("snake_case and-kebab"), "snake_case-and-kebab");
+        assert_eq!(slugify("Überblick über Ärger"), "überblick-über-ärger");
+        assert_eq!(slugify("v1.2 (beta)"), "v12-beta");
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut slugger = Slugger::new();
+        assert_eq!(slugger.slug("Intro"), "intro");
+        assert_eq!(slugger.slug("Intro"), "intro-1");
+        assert_eq!(slugger.slug("Intro"), "intro-2");
+        assert_eq!(slugger.slug("Intro 1"), "intro-1-1");
+        assert_eq!(slugger.reserve("usage"), "usage");
+        assert_eq!(slugger.slug("Usage"), "usage-1");
+        assert_eq!(slugger.reserve("usage"), "usage-2");
+        assert_eq!(slugger.slug("!!"), "");
+        assert_eq!(slugger.slug("?"), "-1");
+    }
+}
@@ -0,0 +1,101 @@
+/*
+ * Smart punctuation.
+ *
+ * Typographic punctuation for published text: straight quotes become curly ones, `--` and `---`
+ * become en and em dashes, and `...` an ellipsis. Whether a quote opens or closes depends on
+ * the character before it, so apostrophes come out right too.
+ */
+
+/*
+ * Replace the punctuation in text. `previous` and `following` are the characters just before
+ * and after it, which may be the delimiters of an emphasis it is in
+ */
+pub fn punctuate(text: &str, mut previous: Option<char>, following: Option<char>) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut punctuated = String::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let next = chars.get(index + 1).copied().or(following);
+        // A quote opens after a space or an opening bracket, with something to quote after it
+        let opening = opens(previous) && next.is_some_and(|c| !c.is_whitespace());
+        let (replacement, length) = match chars[index] {
+            '"' if opening => ("“".to_string(), 1),
+            '"' => ("”".to_string(), 1),
+            // A quote before a number is an apostrophe, as in '90s
+            '\'' if opening && !next.is_some_and(|c| c.is_ascii_digit()) => ("‘".to_string(), 1),
+            '\'' => ("’".to_string(), 1),
+            '.' if chars[index..].starts_with(&['.', '.', '.']) => ("…".to_string(), 3),
+            '-' => {
+                let length = chars[index..].iter().take_while(|&&c| c == '-').count();
+                (dashes(length), length)
+            }
+            c => (c.to_string(), 1),
+        };
+        punctuated.push_str(&replacement);
+        previous = replacement.chars().next_back();
+        index += length;
+    }
+    punctuated
+}
+
+// Does a quote after this character open a quotation?
+fn opens(previous: Option<char>) -> bool {
+    previous.is_none_or(|c| {
+        c.is_whitespace()
+            || matches!(
+                c,
+                '(' | '[' | '{' | '-' | '–' | '—' | '“' | '‘' | '*' | '_' | '~' | '=' | '^' | '/'
+            )
+    })
+}
+
+/*
+ * A run of hyphens as dashes. Runs which divide evenly are all em dashes or all en dashes,
+ * others are em dashes followed by as few en dashes as it takes
+ */
+fn dashes(length: usize) -> String {
+    let (em, en) = match length {
+        1 => return "-".to_string(),
+        length if length % 3 == 0 => (length / 3, 0),
+        length if length % 2 == 0 => (0, length / 2),
+        length if length % 3 == 2 => ((length - 2) / 3, 1),
+        length => ((length - 4) / 3, 2),
+    };
+    "—".repeat(em) + &"–".repeat(en)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes() {
+        assert_eq!(
+            punctuate("\"Hello,\" she said, 'it's the '90s.'", None, None),
+            "“Hello,” she said, ‘it’s the ’90s.’"
+        );
+        assert_eq!(
+            punctuate("\" (\"a\") [\"b\"]", Some('x'), None),
+            "” (“a”) [“b”]"
+        );
+        assert_eq!(punctuate("\"'nested'\"", None, None), "“‘nested’”");
+        // Before emphasis, or after the end of it
+        assert_eq!(punctuate("\"", Some(' '), Some('*')), "“");
+        assert_eq!(punctuate("\" a", Some('*'), None), "” a");
+        assert_eq!(punctuate("' and", Some('s'), None), "’ and");
+        assert_eq!(punctuate("a \"", None, None), "a ”");
+    }
+
+    #[test]
+    fn dashes_and_ellipses() {
+        assert_eq!(
+            punctuate("a - b -- c --- d...", None, None),
+            "a - b – c — d…"
+        );
+        assert_eq!(punctuate("----", None, None), "––");
+        assert_eq!(punctuate("-----", None, None), "—–");
+        assert_eq!(punctuate("-------", None, None), "—––");
+        assert_eq!(punctuate("......", None, None), "……");
+        assert_eq!(punctuate("..", None, None), "..");
+    }
+}
@@ -0,0 +1,123 @@
+/*
+ * Wiki links.
+ *
+ * A wiki links to another page by its name, as in `[[Page Name]]`, or `[[Page Name|label]]` to
+ * show some other text. The names are looked up among the pages of the site, which the caller
+ * passes in along with their URLs. Case, and underscores in place of spaces, don't matter.
+ */
+use std::collections::HashMap;
+
+use super::lexer::Span;
+
+// The pages wiki links can go to, and the links which didn't match any of them
+pub struct WikiLinks {
+    pages: HashMap<String, String>,
+    missing: Vec<(String, Span)>,
+}
+
+impl WikiLinks {
+    // Pages by name, with their URLs
+    pub fn new(pages: &HashMap<String, String>) -> Self {
+        WikiLinks {
+            pages: pages
+                .iter()
+                .map(|(name, url)| (page_name(name), url.clone()))
+                .collect(),
+            missing: vec![],
+        }
+    }
+
+    // The URL of the page a link at span names, noting the link when there is no such page
+    pub fn resolve(&mut self, name: &str, span: Span) -> Option<String> {
+        let url = self.pages.get(&page_name(name)).cloned();
+        if url.is_none() {
+            self.missing.push((name.to_string(), span));
+        }
+        url
+    }
+
+    pub fn warnings(&self) -> Vec<String> {
+        self.missing
+            .iter()
+            .map(|(name, span)| {
+                format!(
+                    "line {}: wiki link [[{}]] does not match any page",
+                    span.line + 1,
+                    name
+                )
+            })
+            .collect()
+    }
+}
+
+// A page name as it is compared: in lower case, with runs of spaces and underscores made one space
+pub fn page_name(name: &str) -> String {
+    name.split(|c: char| c.is_whitespace() || c == '_')
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/*
+ * A wiki link at the start of text. Returns the page name, the label if there is one, and the
+ * length of the whole link
+ */
+pub fn link(text: &str) -> Option<(&str, Option<&str>, usize)> {
+    let rest = text.strip_prefix("[[")?;
+    let end = rest.find("]]")?;
+    let inner = &rest[..end];
+    if inner.contains(['[', ']', '\n']) {
+        return None;
+    }
+    let (name, label) = match inner.split_once('|') {
+        Some((name, label)) => (name.trim(), Some(label.trim())),
+        None => (inner.trim(), None),
+    };
+    if name.is_empty() || label == Some("") {
+        return None;
+    }
+    Some((name, label, end + 4))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links() {
+        assert_eq!(link("[[Home]] page"), Some(("Home", None, 8)));
+        assert_eq!(
+            link("[[ Getting Started | start here ]]"),
+            Some(("Getting Started", Some("start here"), 34))
+        );
+        assert_eq!(link("[[]]"), None);
+        assert_eq!(link("[[a|]]"), None);
+        assert_eq!(link("[[a [b] c]]"), None);
+        assert_eq!(link("[[a]"), None);
+        assert_eq!(link("[a]]"), None);
+    }
+
+    #[test]
+    fn resolving() {
+        let mut pages = HashMap::new();
+        pages.insert(
+            "Getting Started".to_string(),
+            "guide/start.html".to_string(),
+        );
+        let mut links = WikiLinks::new(&pages);
+        let span = Span {
+            line: 2,
+            ..Span::default()
+        };
+        assert_eq!(
+            links.resolve("getting_started", span),
+            Some("guide/start.html".to_string())
+        );
+        assert_eq!(links.resolve("Nowhere", span), None);
+        assert_eq!(
+            links.warnings(),
+            vec!["line 3: wiki link [[Nowhere]] does not match any page"]
+        );
+    }
+}
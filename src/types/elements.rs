@@ -1,8 +1,90 @@
+use crate::parser::lexer::Span;
+use crate::types::mathml;
+use crate::types::options::{HtmlStyle, Options};
+use crate::types::sanitize;
+use crate::types::sourcemap::{self, SourceMap};
+
 pub trait Renderable {
-    fn render(&self) -> String;
+    fn render(&self, options: &Options) -> String;
+    // The part of the source this was rendered from, if it came from the source at all
+    fn span(&self) -> Option<Span> {
+        None
+    }
+    // The attributes written on the element's tag, for elements which have a tag of their own
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        None
+    }
+}
+
+// The data-sourcepos attribute of a block, when the options ask for it
+fn sourcepos_attribute(span: &Option<Span>, options: &Options) -> String {
+    match span {
+        Some(span) if options.sourcepos => {
+            format!(" data-sourcepos=\"{}\"", sourcemap::sourcepos(span))
+        }
+        _ => String::new(),
+    }
+}
+
+/*
+ * Render a list of blocks one per line, recording where the output of each block came from in
+ * the source map
+ */
+pub fn render_blocks(
+    items: &[Box<dyn Renderable>],
+    options: &Options,
+    map: &mut SourceMap,
+    offset: usize,
+) -> String {
+    let mut master = String::new();
+    for child in items {
+        let text = child.render(options);
+        let start = offset + master.len();
+        master.push_str(text.as_str());
+        if let Some(span) = child.span() {
+            map.add(start..offset + master.len(), span);
+        }
+        master.push('\n');
+    }
+    master
+}
+
+/*
+ * Write a void element such as <link> or <hr>, which has no closing tag.
+ * XHTML needs these to be self closing
+ */
+pub fn void_tag(name: &str, attributes: &str, options: &Options) -> String {
+    match options.html_style {
+        HtmlStyle::Html5 => format!("<{}{}>", name, attributes),
+        HtmlStyle::Xhtml => format!("<{}{} />", name, attributes),
+    }
+}
+
+// An attribute which is either there or not, such as disabled. XHTML needs it to have a value
+fn boolean_attribute(name: &str, options: &Options) -> String {
+    match options.html_style {
+        HtmlStyle::Html5 => format!(" {}", name),
+        HtmlStyle::Xhtml => format!(" {}=\"{}\"", name, name),
+    }
+}
+
+// Escape the characters that have a meaning in HTML
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
 }
 
 //Body
+#[derive(Default)]
 pub struct Body {
     items: Vec<Box<dyn Renderable>>,
 }
@@ -14,148 +96,1091 @@ impl Body {
     }
 }
 impl Renderable for Body {
-    fn render(&self) -> String {
+    fn render(&self, options: &Options) -> String {
         let mut master = String::new();
         let start_tag = "<body>";
         let end_tag = "</body>";
 
-        master.push_str(&start_tag);
+        master.push_str(start_tag);
         for child in &self.items {
-            let text = child.render();
+            let text = child.render(options);
             master.push_str(text.as_str());
         }
-        master.push_str(&end_tag);
+        master.push_str(end_tag);
         master
     }
 }
 
+// Render a list of renderables one after another
+pub fn render_all(items: &[Box<dyn Renderable>], options: &Options) -> String {
+    let mut master = String::new();
+    for item in items {
+        master.push_str(&item.render(options));
+    }
+    master
+}
+
+// The blocks inside a container such as a blockquote, one to a line
+fn render_children(children: &[Box<dyn Renderable>], options: &Options) -> String {
+    let children: Vec<String> = children.iter().map(|child| child.render(options)).collect();
+    children.join("\n")
+}
+
+/*
+ * An attribute holding the URL of a link or image. In safe mode, URLs which would run script are
+ * dropped along with the attribute, since an empty one would still point back at the page
+ */
+fn url_attribute(name: &str, url: &str, options: &Options) -> String {
+    if options.safe_mode && runs_script(url) {
+        return String::new();
+    }
+    format!(" {}=\"{}\"", name, escape_html(url))
+}
+
+// Does the URL have a scheme that could run script, or that could pull in other files?
+pub fn runs_script(url: &str) -> bool {
+    // Browsers ignore whitespace and control characters in the scheme
+    let scheme: String = url
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .take(11)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    ["javascript:", "vbscript:", "data:", "file:"]
+        .iter()
+        .any(|prefix| scheme.starts_with(prefix))
+}
+
+// The title attribute of a link or image, if it has one
+fn title_attribute(title: &Option<String>) -> String {
+    match title {
+        Some(title) => format!(" title=\"{}\"", escape_html(title)),
+        None => String::new(),
+    }
+}
+
+/*
+ * Attributes the author gave an element, such as `{#intro .wide}`. They are written after the
+ * element's own attributes, escaped, leaving out any the element already has. Safe mode keeps the
+ * id and classes, but otherwise only the attributes on the allowlist
+ */
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Attributes {
+    pub id: Option<String>,
+    pub classes: Vec<String>,
+    // Any other attributes, in the order they were given
+    pub pairs: Vec<(String, String)>,
+}
+
+impl Attributes {
+    pub fn is_empty(&self) -> bool {
+        self.id.is_none() && self.classes.is_empty() && self.pairs.is_empty()
+    }
+
+    // Add other to these attributes. Its id replaces this one
+    pub fn merge(&mut self, other: Attributes) {
+        if other.id.is_some() {
+            self.id = other.id;
+        }
+        self.classes.extend(other.classes);
+        self.pairs.extend(other.pairs);
+    }
+
+    // The attributes ready to go in a start tag, with the classes the element has of its own first
+    fn render(&self, classes: &[&str], options: &Options) -> String {
+        self.render_besides(classes, &[], options)
+    }
+
+    /*
+     * The attributes ready to go in a start tag which already has those named in `own`, so that
+     * none of them is written twice. Blocks write their own data-sourcepos when the options ask
+     */
+    fn render_besides(&self, classes: &[&str], own: &[&str], options: &Options) -> String {
+        let mut master = String::new();
+        if let Some(id) = &self.id {
+            master.push_str(&format!(" id=\"{}\"", escape_html(id)));
+        }
+        let classes: Vec<&str> = classes
+            .iter()
+            .copied()
+            .chain(self.classes.iter().map(String::as_str))
+            .collect();
+        if !classes.is_empty() {
+            master.push_str(&format!(" class=\"{}\"", escape_html(&classes.join(" "))));
+        }
+        for (name, value) in &self.pairs {
+            let name = name.to_ascii_lowercase();
+            if !is_attribute_name(&name)
+                || own.contains(&name.as_str())
+                || (options.sourcepos && name == "data-sourcepos")
+                || (options.safe_mode && !options.html_allowlist.attributes.contains(&name))
+            {
+                continue;
+            }
+            if options.safe_mode && sanitize::URL_ATTRIBUTES.contains(&name.as_str()) {
+                master.push_str(&url_attribute(&name, value, options));
+            } else {
+                master.push_str(&format!(" {}=\"{}\"", name, escape_html(value)));
+            }
+        }
+        master
+    }
+}
+
+// Can this be written as the name of an attribute, as HTML has them?
+pub fn is_attribute_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | ':' | '.' | '-'))
+}
+
+// Plain text
 pub struct Text {
     pub text: String,
-    pub style: u8,
 }
 
 impl Text {
-    /*
-     * Add styling to text and return it as a String
-     */
-    fn get_text(&self) -> String {
-        let mut s = self.text.clone();
-        if self.style & crate::parser::parser::ITALIC_STYLE != 0 {
-            s = format!("<i>{}</i>", s);
+    pub fn new(text: &str) -> Self {
+        Text {
+            text: text.to_string(),
         }
-        if self.style & crate::parser::parser::BOLD_STYLE != 0 {
-            s = format!("<b>{}</b>", s);
+    }
+}
+impl Renderable for Text {
+    fn render(&self, _options: &Options) -> String {
+        escape_html(&self.text)
+    }
+}
+
+// Text with abbreviations in it, each marked up with its meaning
+pub struct AbbreviatedText {
+    // The pieces of the text, with the meaning of those which are abbreviations
+    pub pieces: Vec<(String, Option<String>)>,
+}
+
+impl Renderable for AbbreviatedText {
+    fn render(&self, _options: &Options) -> String {
+        let mut master = String::new();
+        for (text, title) in &self.pieces {
+            match title {
+                Some(title) if !title.is_empty() => master.push_str(&format!(
+                    "<abbr title=\"{}\">{}</abbr>",
+                    escape_html(title),
+                    escape_html(text)
+                )),
+                Some(_) => master.push_str(&format!("<abbr>{}</abbr>", escape_html(text))),
+                None => master.push_str(&escape_html(text)),
+            }
         }
-        if self.style & crate::parser::parser::CODE_STYLE != 0 {
-            s = self.text.clone();
-            s = format!("<code>{}</code>", s);
+        master
+    }
+}
+
+// Inline code
+pub struct Code {
+    pub text: String,
+    pub attributes: Attributes,
+}
+
+impl Code {
+    pub fn new(text: &str) -> Self {
+        Code {
+            text: text.to_string(),
+            attributes: Attributes::default(),
         }
+    }
+}
+impl Renderable for Code {
+    fn render(&self, options: &Options) -> String {
+        format!(
+            "<code{}>{}</code>",
+            self.attributes.render(&[], options),
+            Text::new(&self.text).render(options)
+        )
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+}
+
+// Emphasis, rendered in italics
+pub struct Emphasis {
+    pub children: Vec<Box<dyn Renderable>>,
+    pub attributes: Attributes,
+}
+
+impl Emphasis {
+    pub fn new(children: Vec<Box<dyn Renderable>>) -> Self {
+        Emphasis {
+            children,
+            attributes: Attributes::default(),
+        }
+    }
+}
+impl Renderable for Emphasis {
+    fn render(&self, options: &Options) -> String {
+        format!(
+            "<i{}>{}</i>",
+            self.attributes.render(&[], options),
+            render_all(&self.children, options)
+        )
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+}
+
+// Strong emphasis, rendered in bold
+pub struct Strong {
+    pub children: Vec<Box<dyn Renderable>>,
+    pub attributes: Attributes,
+}
+
+impl Strong {
+    pub fn new(children: Vec<Box<dyn Renderable>>) -> Self {
+        Strong {
+            children,
+            attributes: Attributes::default(),
+        }
+    }
+}
+impl Renderable for Strong {
+    fn render(&self, options: &Options) -> String {
+        format!(
+            "<b{}>{}</b>",
+            self.attributes.render(&[], options),
+            render_all(&self.children, options)
+        )
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+}
+
+// Strikethrough, for deleted text
+pub struct Strikethrough {
+    pub children: Vec<Box<dyn Renderable>>,
+    pub attributes: Attributes,
+}
+
+impl Strikethrough {
+    pub fn new(children: Vec<Box<dyn Renderable>>) -> Self {
+        Strikethrough {
+            children,
+            attributes: Attributes::default(),
+        }
+    }
+}
+impl Renderable for Strikethrough {
+    fn render(&self, options: &Options) -> String {
+        format!(
+            "<del{}>{}</del>",
+            self.attributes.render(&[], options),
+            render_all(&self.children, options)
+        )
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+}
+
+// Highlighted text
+pub struct Highlight {
+    pub children: Vec<Box<dyn Renderable>>,
+    pub attributes: Attributes,
+}
+
+impl Highlight {
+    pub fn new(children: Vec<Box<dyn Renderable>>) -> Self {
+        Highlight {
+            children,
+            attributes: Attributes::default(),
+        }
+    }
+}
+impl Renderable for Highlight {
+    fn render(&self, options: &Options) -> String {
+        format!(
+            "<mark{}>{}</mark>",
+            self.attributes.render(&[], options),
+            render_all(&self.children, options)
+        )
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+}
+
+// Superscript
+pub struct Superscript {
+    pub children: Vec<Box<dyn Renderable>>,
+    pub attributes: Attributes,
+}
 
-        return s;
+impl Superscript {
+    pub fn new(children: Vec<Box<dyn Renderable>>) -> Self {
+        Superscript {
+            children,
+            attributes: Attributes::default(),
+        }
+    }
+}
+impl Renderable for Superscript {
+    fn render(&self, options: &Options) -> String {
+        format!(
+            "<sup{}>{}</sup>",
+            self.attributes.render(&[], options),
+            render_all(&self.children, options)
+        )
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+}
+
+// Subscript
+pub struct Subscript {
+    pub children: Vec<Box<dyn Renderable>>,
+    pub attributes: Attributes,
+}
+
+impl Subscript {
+    pub fn new(children: Vec<Box<dyn Renderable>>) -> Self {
+        Subscript {
+            children,
+            attributes: Attributes::default(),
+        }
     }
 }
+impl Renderable for Subscript {
+    fn render(&self, options: &Options) -> String {
+        format!(
+            "<sub{}>{}</sub>",
+            self.attributes.render(&[], options),
+            render_all(&self.children, options)
+        )
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+}
+
+// Link
+pub struct Link {
+    pub destination: String,
+    pub title: Option<String>,
+    pub children: Vec<Box<dyn Renderable>>,
+    pub attributes: Attributes,
+}
 
+impl Renderable for Link {
+    fn render(&self, options: &Options) -> String {
+        let own: &[&str] = match self.title {
+            Some(_) => &["href", "title"],
+            None => &["href"],
+        };
+        format!(
+            "<a{}{}{}>{}</a>",
+            url_attribute("href", &self.destination, options),
+            title_attribute(&self.title),
+            self.attributes.render_besides(&[], own, options),
+            render_all(&self.children, options)
+        )
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+}
+
+// Image
+pub struct Image {
+    pub source: String,
+    pub title: Option<String>,
+    pub alt: String,
+    pub attributes: Attributes,
+}
+
+impl Renderable for Image {
+    fn render(&self, options: &Options) -> String {
+        let own: &[&str] = match self.title {
+            Some(_) => &["src", "alt", "title"],
+            None => &["src", "alt"],
+        };
+        let attributes = format!(
+            "{} alt=\"{}\"{}{}",
+            url_attribute("src", &self.source, options),
+            escape_html(&self.alt),
+            title_attribute(&self.title),
+            self.attributes.render_besides(&[], own, options)
+        );
+        void_tag("img", &attributes, options)
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+}
+
+// A `[bracketed span]{.class}`, there only to carry its attributes
+pub struct BracketedSpan {
+    pub children: Vec<Box<dyn Renderable>>,
+    pub attributes: Attributes,
+}
+
+impl Renderable for BracketedSpan {
+    fn render(&self, options: &Options) -> String {
+        format!(
+            "<span{}>{}</span>",
+            self.attributes.render(&[], options),
+            render_all(&self.children, options)
+        )
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+}
+
+#[derive(Default)]
 pub struct CodeBlock {
     pub texts: Vec<Text>,
+    pub attributes: Attributes,
+    pub span: Option<Span>,
 }
 impl CodeBlock {
     pub fn new() -> Self {
-        CodeBlock { texts: vec![] }
+        CodeBlock {
+            texts: vec![],
+            attributes: Attributes::default(),
+            span: None,
+        }
     }
 }
 impl Renderable for CodeBlock {
-    fn render(&self) -> String {
+    fn span(&self) -> Option<Span> {
+        self.span
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+    fn render(&self, options: &Options) -> String {
         let mut master = String::new();
-        let start_tag = "<pre><code>";
+        let start_tag = format!(
+            "<pre{}{}><code>",
+            self.attributes.render(&[], options),
+            sourcepos_attribute(&self.span, options)
+        );
         let end_tag = "</code></pre>";
 
         master.push_str(&start_tag);
-        // Iterate over all the styled text and generate a paragraph
+        // No styling inside a code block, just text
         for text in &self.texts {
-            let s: String = text.get_text();
-            master.push_str(&s);
+            master.push_str(&text.render(options));
         }
-        master.push_str(&end_tag);
+        master.push_str(end_tag);
         master
     }
 }
 
+#[derive(Default)]
 pub struct Paragraph {
-    pub texts: Vec<Text>,
+    pub children: Vec<Box<dyn Renderable>>,
+    // In a tight list the text goes straight into the list item, without a <p>
+    pub tight: bool,
+    pub attributes: Attributes,
+    pub span: Option<Span>,
 }
 
 impl Paragraph {
     pub fn new() -> Self {
-        Paragraph { texts: vec![] }
+        Paragraph {
+            children: vec![],
+            tight: false,
+            attributes: Attributes::default(),
+            span: None,
+        }
     }
 }
 impl Renderable for Paragraph {
-    fn render(&self) -> String {
+    fn span(&self) -> Option<Span> {
+        self.span
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+    fn render(&self, options: &Options) -> String {
+        // Without a <p> there is nowhere for attributes to go
+        if self.tight {
+            return render_all(&self.children, options);
+        }
         let mut master = String::new();
-        let start_tag = "<p>";
+        let start_tag = format!(
+            "<p{}{}>",
+            self.attributes.render(&[], options),
+            sourcepos_attribute(&self.span, options)
+        );
         let end_tag = "</p>";
 
         master.push_str(&start_tag);
-        // Iterate over all the styled text and generate a paragraph
-        for text in &self.texts {
-            let s: String = text.get_text();
-            master.push_str(&s);
-        }
-        master.push_str(&end_tag);
+        // Render the text and everything inside it
+        master.push_str(&render_all(&self.children, options));
+        master.push_str(end_tag);
         master
     }
 }
 //Head
+#[derive(Default)]
 pub struct Head {
-    text: String,
+    pub items: Vec<Box<dyn Renderable>>,
 }
 
 impl Head {
-    pub fn new(text: String) -> Self {
-        Head {
-            text: text,
-        }
+    pub fn new() -> Self {
+        Head { items: vec![] }
     }
 }
 impl Renderable for Head {
-    fn render(&self) -> String {
+    fn render(&self, options: &Options) -> String {
         let mut master = String::new();
         let start_tag = "<head>";
         let end_tag = "</head>";
 
-        master.push_str(&start_tag);
-        master.push_str(&self.text);
-        master.push_str(&end_tag);
+        master.push_str(start_tag);
+        for child in &self.items {
+            master.push_str(&child.render(options));
+        }
+        master.push_str(end_tag);
         master
     }
 }
+// Stylesheet, linked from the head
+pub struct Stylesheet {
+    href: String,
+}
+
+impl Stylesheet {
+    pub fn new(href: &str) -> Self {
+        Stylesheet {
+            href: href.to_string(),
+        }
+    }
+}
+impl Renderable for Stylesheet {
+    fn render(&self, options: &Options) -> String {
+        let attributes = format!(" rel=\"stylesheet\" href=\"{}\"", escape_html(&self.href));
+        void_tag("link", &attributes, options)
+    }
+}
 // Heading
 pub struct Heading {
-    text: Paragraph,
+    pub children: Vec<Box<dyn Renderable>>,
     level: u8,
+    pub id: Option<String>,
+    pub attributes: Attributes,
+    pub span: Option<Span>,
 }
 
 impl Heading {
-    pub fn new(text: Paragraph, level: u8) -> Self {
+    pub fn new(level: u8) -> Self {
         Heading {
-            text: text,
-            level: level,
+            children: vec![],
+            level,
+            id: None,
+            attributes: Attributes::default(),
+            span: None,
         }
     }
 }
 impl Renderable for Heading {
-    fn render(&self) -> String {
+    fn span(&self) -> Option<Span> {
+        self.span
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+    fn render(&self, options: &Options) -> String {
         let mut master = String::new();
-        let start_tag = format!("<h{}>", self.level);
+        // An id among the attributes replaces the heading's own
+        let mut attributes = self.attributes.clone();
+        let id = attributes.id.take().or(self.id.clone());
+        let id_attribute = match &id {
+            Some(id) => format!(" id=\"{}\"", escape_html(id)),
+            None => String::new(),
+        };
+        let start_tag = format!(
+            "<h{}{}{}{}>",
+            self.level,
+            id_attribute,
+            attributes.render(&[], options),
+            sourcepos_attribute(&self.span, options)
+        );
         let end_tag = format!("</h{}>", self.level);
 
         master.push_str(&start_tag);
-        master.push_str(&self.text.render());
+        // The text goes straight inside the heading, not in a paragraph
+        master.push_str(&render_all(&self.children, options));
+        if let (Some(id), true) = (&id, options.heading_permalinks) {
+            master.push_str(&format!(
+                "<a class=\"permalink\" href=\"#{}\" aria-hidden=\"true\">¶</a>",
+                escape_html(id)
+            ));
+        }
         master.push_str(&end_tag);
         master
     }
 }
 
+/*
+ * List, bulleted or numbered. Items hold blocks, and may be task list items with a checkbox
+ */
+pub struct List {
+    pub ordered: bool,
+    // The number of the first item, when it isn't 1
+    pub start: Option<u64>,
+    pub items: Vec<ListItem>,
+    pub attributes: Attributes,
+    pub span: Option<Span>,
+}
+
+pub struct ListItem {
+    pub children: Vec<Box<dyn Renderable>>,
+    // Whether the task is done, for a task list item
+    pub task: Option<bool>,
+}
+
+impl ListItem {
+    fn render(&self, options: &Options) -> String {
+        let mut master = String::new();
+        match self.task {
+            Some(done) => {
+                master.push_str("<li class=\"task-list-item\">");
+                let mut attributes = format!(
+                    " type=\"checkbox\"{}",
+                    boolean_attribute("disabled", options)
+                );
+                if done {
+                    attributes.push_str(&boolean_attribute("checked", options));
+                }
+                master.push_str(&void_tag("input", &attributes, options));
+                master.push(' ');
+            }
+            None => master.push_str("<li>"),
+        }
+        master.push_str(&render_children(&self.children, options));
+        master.push_str("</li>");
+        master
+    }
+}
+
+impl Renderable for List {
+    fn span(&self) -> Option<Span> {
+        self.span
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+    fn render(&self, options: &Options) -> String {
+        let tag = if self.ordered { "ol" } else { "ul" };
+        let (start, own): (String, &[&str]) = match self.start {
+            Some(start) => (format!(" start=\"{}\"", start), &["start"]),
+            None => (String::new(), &[]),
+        };
+        let mut master = format!(
+            "<{}{}{}{}>",
+            tag,
+            start,
+            self.attributes.render_besides(&[], own, options),
+            sourcepos_attribute(&self.span, options)
+        );
+        for item in &self.items {
+            master.push_str(&item.render(options));
+        }
+        master.push_str(&format!("</{}>", tag));
+        master
+    }
+}
+
+/*
+ * Blockquote, holding any blocks
+ */
+pub struct Blockquote {
+    pub children: Vec<Box<dyn Renderable>>,
+    pub attributes: Attributes,
+    pub span: Option<Span>,
+}
+
+impl Renderable for Blockquote {
+    fn span(&self) -> Option<Span> {
+        self.span
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+    fn render(&self, options: &Options) -> String {
+        format!(
+            "<blockquote{}{}>{}</blockquote>",
+            self.attributes.render(&[], options),
+            sourcepos_attribute(&self.span, options),
+            render_children(&self.children, options)
+        )
+    }
+}
+
+/*
+ * Admonition: a note, warning or the like, set apart from the text in a box with a title
+ */
+pub struct Admonition {
+    // The kind, in lower case, which is also the class of the box
+    pub kind: String,
+    pub title: String,
+    pub children: Vec<Box<dyn Renderable>>,
+    pub attributes: Attributes,
+    pub span: Option<Span>,
+}
+
+impl Renderable for Admonition {
+    fn span(&self) -> Option<Span> {
+        self.span
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+    fn render(&self, options: &Options) -> String {
+        let mut master = format!(
+            "<div{}{}><p class=\"admonition-title\">{}</p>",
+            self.attributes.render(&["admonition", &self.kind], options),
+            sourcepos_attribute(&self.span, options),
+            escape_html(&self.title)
+        );
+        if !self.children.is_empty() {
+            master.push('\n');
+            master.push_str(&render_children(&self.children, options));
+        }
+        master.push_str("</div>");
+        master
+    }
+}
+
+// A custom emoji, shown as an image in place of its shortcode
+pub struct Emoji {
+    pub name: String,
+    pub source: String,
+    pub attributes: Attributes,
+}
+
+impl Renderable for Emoji {
+    fn render(&self, options: &Options) -> String {
+        let shortcode = escape_html(&format!(":{}:", self.name));
+        let attributes = format!(
+            "{}{} alt=\"{}\" title=\"{}\"",
+            self.attributes
+                .render_besides(&["emoji"], &["src", "alt", "title"], options),
+            url_attribute("src", &self.source, options),
+            shortcode,
+            shortcode
+        );
+        void_tag("img", &attributes, options)
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+}
+
+/*
+ * Math, written in LaTeX and rendered as MathML. LaTeX that can't be converted is shown as it is
+ */
+pub struct Math {
+    pub tex: String,
+    // Display math is set on a line of its own
+    pub display: bool,
+    pub attributes: Attributes,
+    pub span: Option<Span>,
+}
+
+impl Renderable for Math {
+    fn span(&self) -> Option<Span> {
+        self.span
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+    fn render(&self, options: &Options) -> String {
+        let sourcepos = sourcepos_attribute(&self.span, options);
+        match mathml::convert(&self.tex, self.display) {
+            Ok(mathml) => format!(
+                "<math{}{}{}>{}</math>",
+                if self.display {
+                    " display=\"block\""
+                } else {
+                    ""
+                },
+                match self.display {
+                    true => self.attributes.render_besides(&[], &["display"], options),
+                    false => self.attributes.render(&[], options),
+                },
+                sourcepos,
+                mathml
+            ),
+            Err(_) => {
+                let classes: &[&str] = match self.display {
+                    true => &["math", "math-display"],
+                    false => &["math"],
+                };
+                format!(
+                    "<code{}{}>{}</code>",
+                    self.attributes.render(classes, options),
+                    sourcepos,
+                    escape_html(&self.tex)
+                )
+            }
+        }
+    }
+}
+
+/*
+ * Definition list: terms, each followed by the blocks of its definitions
+ */
+pub struct DefinitionList {
+    pub items: Vec<DefinitionItem>,
+    pub attributes: Attributes,
+    pub span: Option<Span>,
+}
+
+pub struct DefinitionItem {
+    pub term: Vec<Box<dyn Renderable>>,
+    pub definitions: Vec<Vec<Box<dyn Renderable>>>,
+}
+
+impl Renderable for DefinitionList {
+    fn span(&self) -> Option<Span> {
+        self.span
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+    fn render(&self, options: &Options) -> String {
+        let mut master = format!(
+            "<dl{}{}>",
+            self.attributes.render(&[], options),
+            sourcepos_attribute(&self.span, options)
+        );
+        for item in &self.items {
+            master.push_str(&format!("<dt>{}</dt>", render_all(&item.term, options)));
+            for definition in &item.definitions {
+                master.push_str(&format!(
+                    "<dd>{}</dd>",
+                    render_children(definition, options)
+                ));
+            }
+        }
+        master.push_str("</dl>");
+        master
+    }
+}
+
+/*
+ * A reference to a footnote: its number, linking down to the footnote. The footnote links back up
+ * to every reference, so each one needs an id of its own
+ */
+pub struct FootnoteReference {
+    pub number: usize,
+    // Which reference to the footnote this is, counting from 1
+    pub occurrence: usize,
+}
+
+// The id of a reference to a footnote
+fn footnote_reference_id(number: usize, occurrence: usize) -> String {
+    match occurrence {
+        1 => format!("fnref-{}", number),
+        _ => format!("fnref-{}-{}", number, occurrence),
+    }
+}
+
+impl Renderable for FootnoteReference {
+    fn render(&self, _options: &Options) -> String {
+        format!(
+            "<sup class=\"footnote-ref\"><a href=\"#fn-{}\" id=\"{}\">{}</a></sup>",
+            self.number,
+            footnote_reference_id(self.number, self.occurrence),
+            self.number
+        )
+    }
+}
+
+/*
+ * The footnotes at the end of the page, each with links back to where it was referenced
+ */
+pub struct Footnotes {
+    pub notes: Vec<Footnote>,
+}
+
+pub struct Footnote {
+    pub number: usize,
+    // How many times the footnote was referenced
+    pub references: usize,
+    pub children: Vec<Box<dyn Renderable>>,
+}
+
+impl Footnote {
+    fn render(&self, options: &Options) -> String {
+        let mut backrefs = String::new();
+        for occurrence in 1..=self.references {
+            let label = match occurrence {
+                1 => String::new(),
+                _ => format!("<sup>{}</sup>", occurrence),
+            };
+            backrefs.push_str(&format!(
+                " <a href=\"#{}\" class=\"footnote-backref\" aria-label=\"Back to reference {}\">↩{}</a>",
+                footnote_reference_id(self.number, occurrence),
+                self.number,
+                label
+            ));
+        }
+        let mut content = render_children(&self.children, options);
+        // The links go at the end of the last paragraph, when there is one
+        match content.strip_suffix("</p>") {
+            Some(rest) => content = format!("{}{}</p>", rest, backrefs),
+            None => content.push_str(&backrefs),
+        }
+        format!("<li id=\"fn-{}\">{}</li>", self.number, content)
+    }
+}
+
+impl Renderable for Footnotes {
+    fn render(&self, options: &Options) -> String {
+        let mut master = String::from("<section class=\"footnotes\"><ol>");
+        for note in &self.notes {
+            master.push_str(&note.render(options));
+        }
+        master.push_str("</ol></section>");
+        master
+    }
+}
+
+// Raw HTML, from a block or inside a line of text. Safe mode filters it
+pub struct Html {
+    pub html: String,
+    pub span: Option<Span>,
+}
+
+impl Renderable for Html {
+    fn span(&self) -> Option<Span> {
+        self.span
+    }
+    fn render(&self, options: &Options) -> String {
+        match options.safe_mode {
+            true => sanitize::sanitize(&self.html, options),
+            false => self.html.clone(),
+        }
+    }
+}
+
+// Thematic break, the line between sections
+pub struct ThematicBreak {
+    pub attributes: Attributes,
+    pub span: Option<Span>,
+}
+
+impl Renderable for ThematicBreak {
+    fn span(&self) -> Option<Span> {
+        self.span
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+    fn render(&self, options: &Options) -> String {
+        let attributes = format!(
+            "{}{}",
+            self.attributes.render(&[], options),
+            sourcepos_attribute(&self.span, options)
+        );
+        void_tag("hr", &attributes, options)
+    }
+}
+
+/*
+ * Table of contents. One entry per heading, with the headings below it nested inside
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct TocEntry {
+    pub level: u8,
+    pub id: Option<String>,
+    pub text: String,
+    pub children: Vec<TocEntry>,
+}
+
+impl TocEntry {
+    /*
+     * Nest a flat list of headings: each one goes inside the closest heading before it with a
+     * smaller level. Levels may be skipped, an h3 straight after an h1 still belongs to it
+     */
+    pub fn nest(headings: Vec<TocEntry>) -> Vec<TocEntry> {
+        let mut roots: Vec<TocEntry> = vec![];
+        let mut open: Vec<TocEntry> = vec![];
+        let close = |open: &mut Vec<TocEntry>, roots: &mut Vec<TocEntry>| {
+            let entry = open.pop().unwrap();
+            match open.last_mut() {
+                Some(parent) => parent.children.push(entry),
+                None => roots.push(entry),
+            }
+        };
+        for heading in headings {
+            while open.last().is_some_and(|last| last.level >= heading.level) {
+                close(&mut open, &mut roots);
+            }
+            open.push(heading);
+        }
+        while !open.is_empty() {
+            close(&mut open, &mut roots);
+        }
+        roots
+    }
+
+    fn render(&self) -> String {
+        let text = escape_html(&self.text);
+        let mut master = match &self.id {
+            Some(id) => format!("<li><a href=\"#{}\">{}</a>", escape_html(id), text),
+            None => format!("<li>{}", text),
+        };
+        master.push_str(&render_toc_list(&self.children));
+        master.push_str("</li>");
+        master
+    }
+}
+
+fn render_toc_list(entries: &[TocEntry]) -> String {
+    if entries.is_empty() {
+        return String::new();
+    }
+    let items: String = entries.iter().map(TocEntry::render).collect();
+    format!("<ul>{}</ul>", items)
+}
+
+pub struct Toc {
+    pub entries: Vec<TocEntry>,
+    pub attributes: Attributes,
+    pub span: Option<Span>,
+}
+
+impl Renderable for Toc {
+    fn span(&self) -> Option<Span> {
+        self.span
+    }
+    fn attributes_mut(&mut self) -> Option<&mut Attributes> {
+        Some(&mut self.attributes)
+    }
+    fn render(&self, options: &Options) -> String {
+        format!(
+            "<nav{}{}>{}</nav>",
+            self.attributes.render(&["toc"], options),
+            sourcepos_attribute(&self.span, options),
+            render_toc_list(&self.entries)
+        )
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Default)]
 pub struct HTML {
     pub items: Vec<Box<dyn Renderable>>,
 }
@@ -165,20 +1190,26 @@ impl HTML {
         let _vec = Vec::<Box<dyn Renderable>>::new();
         HTML { items: _vec }
     }
-}
-impl Renderable for HTML {
-    fn render(&self) -> String {
+
+    // Render the document, and the source map of its blocks
+    pub fn render_with_source_map(&self, options: &Options) -> (String, SourceMap) {
         let mut master = String::new();
-        let start_tag = "<html>\n";
+        let start_tag = match options.html_style {
+            HtmlStyle::Html5 => "<html>\n",
+            HtmlStyle::Xhtml => "<html xmlns=\"http://www.w3.org/1999/xhtml\">\n",
+        };
         let end_tag = "</html>";
 
-        master.push_str(&start_tag);
-        for child in &self.items {
-            let text = child.render();
-            master.push_str(text.as_str());
-            master.push_str("\n");
-        }
-        master.push_str(&end_tag);
-        master
+        let mut map = SourceMap::new();
+        master.push_str(start_tag);
+        let blocks = render_blocks(&self.items, options, &mut map, master.len());
+        master.push_str(&blocks);
+        master.push_str(end_tag);
+        (master, map)
+    }
+}
+impl Renderable for HTML {
+    fn render(&self, options: &Options) -> String {
+        self.render_with_source_map(options).0
     }
 }
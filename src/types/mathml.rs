@@ -0,0 +1,655 @@
+/*
+ * LaTeX to MathML.
+ *
+ * Convert the LaTeX most formulas are written in to MathML, which browsers render without any
+ * JavaScript: fractions, roots, sub and superscripts, Greek letters, big operators such as sums
+ * and integrals, matrices and the common symbols. Anything else is an error, and the caller
+ * shows the LaTeX as it is instead.
+ */
+use crate::types::elements::escape_html;
+
+/*
+ * The MathML for a formula, without the <math> element around it. In display math, the limits
+ * of sums and the like go above and below them rather than beside them
+ */
+pub fn convert(tex: &str, display: bool) -> Result<String, String> {
+    let mut parser = TexParser {
+        chars: tex.chars().collect(),
+        position: 0,
+        display,
+    };
+    let nodes = parser.expression()?;
+    match parser.next() {
+        Some(token) => Err(format!("unexpected {}", token)),
+        None => Ok(nodes.concat()),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    // A backslash command, such as \frac or \{, without its backslash
+    Command(String),
+    Char(char),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Command(name) => write!(f, "\\{}", name),
+            Token::Char(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+struct TexParser {
+    chars: Vec<char>,
+    position: usize,
+    display: bool,
+}
+
+// Several nodes grouped into one
+fn row(nodes: Vec<String>) -> String {
+    match nodes.len() {
+        1 => nodes.into_iter().next().unwrap(),
+        _ => format!("<mrow>{}</mrow>", nodes.concat()),
+    }
+}
+
+// A row of a matrix, from the nodes in each of its cells
+fn table_row(cells: Vec<Vec<String>>) -> String {
+    let cells: Vec<String> = cells
+        .into_iter()
+        .map(|cell| format!("<mtd>{}</mtd>", row(cell)))
+        .collect();
+    format!("<mtr>{}</mtr>", cells.concat())
+}
+
+fn identifier(text: &str) -> String {
+    format!("<mi>{}</mi>", escape_html(text))
+}
+
+fn operator(text: &str) -> String {
+    format!("<mo>{}</mo>", escape_html(text))
+}
+
+impl TexParser {
+    fn skip_whitespace(&mut self) {
+        while self
+            .chars
+            .get(self.position)
+            .is_some_and(|c| c.is_whitespace())
+        {
+            self.position += 1;
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.skip_whitespace();
+        let c = *self.chars.get(self.position)?;
+        self.position += 1;
+        if c != '\\' {
+            return Some(Token::Char(c));
+        }
+        let start = self.position;
+        while self
+            .chars
+            .get(self.position)
+            .is_some_and(|c| c.is_ascii_alphabetic())
+        {
+            self.position += 1;
+        }
+        if self.position == start && self.position < self.chars.len() {
+            // A command made of one symbol, such as \{ or \,
+            self.position += 1;
+        }
+        Some(Token::Command(
+            self.chars[start..self.position].iter().collect(),
+        ))
+    }
+
+    fn peek(&mut self) -> Option<Token> {
+        let position = self.position;
+        let token = self.next();
+        self.position = position;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!("expected {} but found {}", expected, token)),
+            None => Err(format!("expected {}", expected)),
+        }
+    }
+
+    // Nodes up to the end of the group, cell or formula they are in
+    fn expression(&mut self) -> Result<Vec<String>, String> {
+        let mut nodes = vec![];
+        loop {
+            match self.peek() {
+                None | Some(Token::Char('}' | '&' | ']')) => break,
+                Some(Token::Command(name)) if matches!(name.as_str(), "\\" | "end" | "right") => {
+                    break
+                }
+                _ => nodes.push(self.scripted()?),
+            }
+        }
+        Ok(nodes)
+    }
+
+    // An atom along with its subscript and superscript, if it has them
+    fn scripted(&mut self) -> Result<String, String> {
+        let (base, limits) = self.atom()?;
+        let mut sub = None;
+        let mut sup = None;
+        loop {
+            match self.peek() {
+                Some(Token::Char('_')) if sub.is_none() => {
+                    self.next();
+                    sub = Some(self.argument()?);
+                }
+                Some(Token::Char('^')) if sup.is_none() => {
+                    self.next();
+                    sup = Some(self.argument()?);
+                }
+                Some(Token::Char('\'')) if sup.is_none() => {
+                    self.next();
+                    sup = Some(operator("′"));
+                }
+                _ => break,
+            }
+        }
+        let (under, over, both) = match limits && self.display {
+            true => ("munder", "mover", "munderover"),
+            false => ("msub", "msup", "msubsup"),
+        };
+        Ok(match (sub, sup) {
+            (None, None) => base,
+            (Some(sub), None) => format!("<{0}>{1}{2}</{0}>", under, base, sub),
+            (None, Some(sup)) => format!("<{0}>{1}{2}</{0}>", over, base, sup),
+            (Some(sub), Some(sup)) => format!("<{0}>{1}{2}{3}</{0}>", both, base, sub, sup),
+        })
+    }
+
+    // The argument of a command or script: a group, or a single character or command
+    fn argument(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        match self.chars.get(self.position) {
+            // Only the first digit of a number, as in x^23
+            Some(c) if c.is_ascii_digit() => {
+                self.position += 1;
+                Ok(format!("<mn>{}</mn>", c))
+            }
+            _ => self.atom().map(|(node, _)| node),
+        }
+    }
+
+    // The text of a group, exactly as written, for commands such as \text
+    fn raw_group(&mut self) -> Result<String, String> {
+        self.expect(Token::Char('{'))?;
+        let start = self.position;
+        let mut depth = 0;
+        while let Some(&c) = self.chars.get(self.position) {
+            self.position += 1;
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => {
+                    return Ok(self.chars[start..self.position - 1].iter().collect());
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+        Err("unclosed {".to_string())
+    }
+
+    /*
+     * A single node: a group, number, letter, symbol or command. Also returns whether it is a
+     * big operator like \sum, whose limits go above and below it in display math
+     */
+    fn atom(&mut self) -> Result<(String, bool), String> {
+        let token = self.next().ok_or("missing argument")?;
+        let node = match token {
+            Token::Char('{') => {
+                let nodes = self.expression()?;
+                self.expect(Token::Char('}'))?;
+                row(nodes)
+            }
+            Token::Char(c) if c.is_ascii_digit() => {
+                let start = self.position - 1;
+                while self
+                    .chars
+                    .get(self.position)
+                    .is_some_and(|&c| c.is_ascii_digit() || c == '.')
+                {
+                    self.position += 1;
+                }
+                let number: String = self.chars[start..self.position].iter().collect();
+                format!("<mn>{}</mn>", number)
+            }
+            Token::Char(c) if c.is_alphabetic() => identifier(&c.to_string()),
+            Token::Char('~') => "<mspace width=\"0.25em\"></mspace>".to_string(),
+            Token::Char(c @ ('}' | ']' | '&' | '_' | '^' | '#' | '$' | '%')) => {
+                return Err(format!("unexpected {}", c))
+            }
+            Token::Char('-') => operator("−"),
+            Token::Char('*') => operator("∗"),
+            Token::Char(c) => operator(&c.to_string()),
+            Token::Command(name) => return self.command(&name),
+        };
+        Ok((node, false))
+    }
+
+    fn command(&mut self, name: &str) -> Result<(String, bool), String> {
+        if let Some(letter) = greek(name) {
+            return Ok((identifier(letter), false));
+        }
+        if let Some(symbol) = symbol(name) {
+            return Ok((operator(symbol), false));
+        }
+        if let Some(symbol) = big_operator(name) {
+            return Ok((operator(symbol), !symbol.starts_with('∫') && symbol != "∮"));
+        }
+        if FUNCTIONS.contains(&name) {
+            let limits = matches!(name, "lim" | "max" | "min" | "sup" | "inf" | "det" | "gcd");
+            return Ok((identifier(name), limits));
+        }
+        let node = match name {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.argument()?;
+                let denominator = self.argument()?;
+                format!("<mfrac>{}{}</mfrac>", numerator, denominator)
+            }
+            "binom" => {
+                let top = self.argument()?;
+                let bottom = self.argument()?;
+                format!(
+                    "<mrow><mo>(</mo><mfrac linethickness=\"0\">{}{}</mfrac><mo>)</mo></mrow>",
+                    top, bottom
+                )
+            }
+            "sqrt" => match self.peek() {
+                Some(Token::Char('[')) => {
+                    self.next();
+                    let index = row(self.expression()?);
+                    self.expect(Token::Char(']'))?;
+                    let base = self.argument()?;
+                    format!("<mroot>{}{}</mroot>", base, index)
+                }
+                _ => format!("<msqrt>{}</msqrt>", self.argument()?),
+            },
+            "text" | "mbox" | "textrm" => {
+                format!("<mtext>{}</mtext>", escape_html(&self.raw_group()?))
+            }
+            "mathrm" | "operatorname" | "mathbf" | "mathit" | "mathbb" | "mathcal" => {
+                let variant = match name {
+                    "mathbf" => "bold",
+                    "mathit" => "italic",
+                    "mathbb" => "double-struck",
+                    "mathcal" => "script",
+                    _ => "normal",
+                };
+                format!(
+                    "<mi mathvariant=\"{}\">{}</mi>",
+                    variant,
+                    escape_html(&self.raw_group()?)
+                )
+            }
+            "hat" | "widehat" | "bar" | "overline" | "vec" | "dot" | "ddot" | "tilde"
+            | "widetilde" => {
+                let accent = match name {
+                    "hat" | "widehat" => "^",
+                    "bar" | "overline" => "¯",
+                    "vec" => "→",
+                    "dot" => "˙",
+                    "ddot" => "¨",
+                    _ => "~",
+                };
+                let base = self.argument()?;
+                format!("<mover accent=\"true\">{}<mo>{}</mo></mover>", base, accent)
+            }
+            "underline" => {
+                let base = self.argument()?;
+                format!("<munder accentunder=\"true\">{}<mo>_</mo></munder>", base)
+            }
+            "left" => {
+                let open = self.delimiter()?;
+                let nodes = self.expression()?;
+                self.expect(Token::Command("right".to_string()))?;
+                let close = self.delimiter()?;
+                format!("<mrow>{}{}{}</mrow>", open, nodes.concat(), close)
+            }
+            "begin" => self.environment()?,
+            "," | ":" | ">" | ";" | " " | "quad" | "qquad" => {
+                let width = match name {
+                    "," => "0.1667em",
+                    ":" | ">" => "0.2222em",
+                    ";" => "0.2778em",
+                    " " => "0.25em",
+                    "quad" => "1em",
+                    _ => "2em",
+                };
+                format!("<mspace width=\"{}\"></mspace>", width)
+            }
+            "!" => String::new(),
+            "{" | "}" | "|" | "%" | "$" | "&" | "#" | "_" => {
+                operator(if name == "|" { "‖" } else { name })
+            }
+            _ => return Err(format!("unsupported command \\{}", name)),
+        };
+        Ok((node, false))
+    }
+
+    // The delimiter after \left or \right, which `.` leaves out
+    fn delimiter(&mut self) -> Result<String, String> {
+        let delimiter = match self.next().ok_or("missing delimiter")? {
+            Token::Char('.') => return Ok(String::new()),
+            Token::Char(c @ ('(' | ')' | '[' | ']' | '|' | '/')) => c.to_string(),
+            Token::Command(name) => match name.as_str() {
+                "{" | "lbrace" => "{".to_string(),
+                "}" | "rbrace" => "}".to_string(),
+                "|" | "Vert" => "‖".to_string(),
+                "langle" => "⟨".to_string(),
+                "rangle" => "⟩".to_string(),
+                "lfloor" => "⌊".to_string(),
+                "rfloor" => "⌋".to_string(),
+                "lceil" => "⌈".to_string(),
+                "rceil" => "⌉".to_string(),
+                _ => return Err(format!("unsupported delimiter \\{}", name)),
+            },
+            token => return Err(format!("unsupported delimiter {}", token)),
+        };
+        Ok(format!(
+            "<mo fence=\"true\">{}</mo>",
+            escape_html(&delimiter)
+        ))
+    }
+
+    /*
+     * A matrix, or cases: rows separated by \\, with their cells separated by &, up to the
+     * matching \end
+     */
+    fn environment(&mut self) -> Result<String, String> {
+        let name = self.raw_group()?;
+        let (open, close) = match name.as_str() {
+            "matrix" => ("", ""),
+            "pmatrix" => ("(", ")"),
+            "bmatrix" => ("[", "]"),
+            "Bmatrix" => ("{", "}"),
+            "vmatrix" => ("|", "|"),
+            "Vmatrix" => ("‖", "‖"),
+            "cases" => ("{", ""),
+            _ => return Err(format!("unsupported environment {}", name)),
+        };
+        let mut rows = vec![];
+        let mut cells = vec![];
+        loop {
+            cells.push(self.expression()?);
+            match self.next() {
+                Some(Token::Char('&')) => {}
+                Some(Token::Command(command)) if command == "\\" => {
+                    rows.push(table_row(std::mem::take(&mut cells)));
+                }
+                Some(Token::Command(command)) if command == "end" => break,
+                Some(token) => return Err(format!("unexpected {}", token)),
+                None => return Err(format!("unclosed environment {}", name)),
+            }
+        }
+        // A \\ at the end of the last row doesn't start another
+        if cells.len() > 1 || !cells[0].is_empty() {
+            rows.push(table_row(cells));
+        }
+        let end = self.raw_group()?;
+        if end != name {
+            return Err(format!("\\begin{{{}}} ended by \\end{{{}}}", name, end));
+        }
+        let fence = |delimiter: &str| match delimiter {
+            "" => String::new(),
+            delimiter => format!("<mo fence=\"true\">{}</mo>", delimiter),
+        };
+        let align = match name.as_str() {
+            "cases" => " columnalign=\"left\"",
+            _ => "",
+        };
+        Ok(format!(
+            "<mrow>{}<mtable{}>{}</mtable>{}</mrow>",
+            fence(open),
+            align,
+            rows.concat(),
+            fence(close)
+        ))
+    }
+}
+
+// Named functions, written upright
+const FUNCTIONS: [&str; 22] = [
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "log", "ln", "lg", "exp", "lim", "max", "min", "sup", "inf", "det",
+];
+
+fn greek(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "alpha" => "α",
+        "beta" => "β",
+        "gamma" => "γ",
+        "delta" => "δ",
+        "epsilon" => "ϵ",
+        "varepsilon" => "ε",
+        "zeta" => "ζ",
+        "eta" => "η",
+        "theta" => "θ",
+        "vartheta" => "ϑ",
+        "iota" => "ι",
+        "kappa" => "κ",
+        "lambda" => "λ",
+        "mu" => "μ",
+        "nu" => "ν",
+        "xi" => "ξ",
+        "pi" => "π",
+        "varpi" => "ϖ",
+        "rho" => "ρ",
+        "varrho" => "ϱ",
+        "sigma" => "σ",
+        "varsigma" => "ς",
+        "tau" => "τ",
+        "upsilon" => "υ",
+        "phi" => "ϕ",
+        "varphi" => "φ",
+        "chi" => "χ",
+        "psi" => "ψ",
+        "omega" => "ω",
+        "Gamma" => "Γ",
+        "Delta" => "Δ",
+        "Theta" => "Θ",
+        "Lambda" => "Λ",
+        "Xi" => "Ξ",
+        "Pi" => "Π",
+        "Sigma" => "Σ",
+        "Upsilon" => "Υ",
+        "Phi" => "Φ",
+        "Psi" => "Ψ",
+        "Omega" => "Ω",
+        "infty" => "∞",
+        "partial" => "∂",
+        "nabla" => "∇",
+        "hbar" => "ℏ",
+        "ell" => "ℓ",
+        "emptyset" => "∅",
+        _ => return None,
+    })
+}
+
+fn symbol(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "times" => "×",
+        "cdot" => "⋅",
+        "div" => "÷",
+        "pm" => "±",
+        "mp" => "∓",
+        "ast" => "∗",
+        "circ" => "∘",
+        "le" | "leq" => "≤",
+        "ge" | "geq" => "≥",
+        "ne" | "neq" => "≠",
+        "ll" => "≪",
+        "gg" => "≫",
+        "approx" => "≈",
+        "equiv" => "≡",
+        "sim" => "∼",
+        "simeq" => "≃",
+        "cong" => "≅",
+        "propto" => "∝",
+        "to" | "rightarrow" => "→",
+        "leftarrow" | "gets" => "←",
+        "leftrightarrow" => "↔",
+        "Rightarrow" | "implies" => "⇒",
+        "Leftarrow" => "⇐",
+        "Leftrightarrow" | "iff" => "⇔",
+        "mapsto" => "↦",
+        "in" => "∈",
+        "notin" => "∉",
+        "ni" => "∋",
+        "subset" => "⊂",
+        "subseteq" => "⊆",
+        "supset" => "⊃",
+        "supseteq" => "⊇",
+        "cup" => "∪",
+        "cap" => "∩",
+        "setminus" => "∖",
+        "forall" => "∀",
+        "exists" => "∃",
+        "neg" | "lnot" => "¬",
+        "land" | "wedge" => "∧",
+        "lor" | "vee" => "∨",
+        "oplus" => "⊕",
+        "otimes" => "⊗",
+        "perp" => "⊥",
+        "parallel" => "∥",
+        "mid" => "∣",
+        "angle" => "∠",
+        "ldots" | "dots" => "…",
+        "cdots" => "⋯",
+        "vdots" => "⋮",
+        "ddots" => "⋱",
+        "langle" => "⟨",
+        "rangle" => "⟩",
+        "lfloor" => "⌊",
+        "rfloor" => "⌋",
+        "lceil" => "⌈",
+        "rceil" => "⌉",
+        "prime" => "′",
+        _ => return None,
+    })
+}
+
+fn big_operator(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "sum" => "∑",
+        "prod" => "∏",
+        "coprod" => "∐",
+        "bigcup" => "⋃",
+        "bigcap" => "⋂",
+        "bigoplus" => "⨁",
+        "bigotimes" => "⨂",
+        "int" => "∫",
+        "iint" => "∬",
+        "iiint" => "∭",
+        "oint" => "∮",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inline(tex: &str) -> String {
+        convert(tex, false).unwrap()
+    }
+
+    #[test]
+    fn basics() {
+        assert_eq!(
+            inline("x^2 + 1.5"),
+            "<msup><mi>x</mi><mn>2</mn></msup><mo>+</mo><mn>1.5</mn>"
+        );
+        assert_eq!(
+            inline("a_{ij}^{10} - b'"),
+            "<msubsup><mi>a</mi><mrow><mi>i</mi><mi>j</mi></mrow><mn>10</mn></msubsup>\
+             <mo>−</mo><msup><mi>b</mi><mo>′</mo></msup>"
+        );
+        assert_eq!(
+            inline("x^23"),
+            "<msup><mi>x</mi><mn>2</mn></msup><mn>3</mn>"
+        );
+        assert_eq!(
+            inline("\\frac{\\alpha}{2} < \\Omega"),
+            "<mfrac><mi>α</mi><mn>2</mn></mfrac><mo>&lt;</mo><mi>Ω</mi>"
+        );
+        assert_eq!(
+            inline("\\sqrt[3]{x}\\sqrt2"),
+            "<mroot><mi>x</mi><mn>3</mn></mroot><msqrt><mn>2</mn></msqrt>"
+        );
+        assert_eq!(
+            inline("\\text{if } x \\in \\mathbb{R}"),
+            "<mtext>if </mtext><mi>x</mi><mo>∈</mo><mi mathvariant=\"double-struck\">R</mi>"
+        );
+        assert_eq!(
+            inline("\\left( \\vec v \\right."),
+            "<mrow><mo fence=\"true\">(</mo><mover accent=\"true\"><mi>v</mi><mo>→</mo></mover></mrow>"
+        );
+    }
+
+    #[test]
+    fn big_operators() {
+        let sum = "\\sum_{i=1}^n i";
+        assert_eq!(
+            inline(sum),
+            "<msubsup><mo>∑</mo><mrow><mi>i</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></msubsup><mi>i</mi>"
+        );
+        assert_eq!(
+            convert(sum, true).unwrap(),
+            "<munderover><mo>∑</mo><mrow><mi>i</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></munderover><mi>i</mi>"
+        );
+        // Integrals keep their limits beside them
+        assert_eq!(
+            convert("\\int_0^1 f\\,dx", true).unwrap(),
+            "<msubsup><mo>∫</mo><mn>0</mn><mn>1</mn></msubsup><mi>f</mi>\
+             <mspace width=\"0.1667em\"></mspace><mi>d</mi><mi>x</mi>"
+        );
+        assert_eq!(
+            convert("\\lim_{x \\to 0}", true).unwrap(),
+            "<munder><mi>lim</mi><mrow><mi>x</mi><mo>→</mo><mn>0</mn></mrow></munder>"
+        );
+    }
+
+    #[test]
+    fn matrices() {
+        assert_eq!(
+            inline("\\begin{pmatrix} a & b \\\\ c & d \\\\ \\end{pmatrix}"),
+            "<mrow><mo fence=\"true\">(</mo><mtable>\
+             <mtr><mtd><mi>a</mi></mtd><mtd><mi>b</mi></mtd></mtr>\
+             <mtr><mtd><mi>c</mi></mtd><mtd><mi>d</mi></mtd></mtr>\
+             </mtable><mo fence=\"true\">)</mo></mrow>"
+        );
+        assert_eq!(
+            inline("\\begin{cases} 1 & x > 0 \\\\ 0 \\end{cases}"),
+            "<mrow><mo fence=\"true\">{</mo><mtable columnalign=\"left\">\
+             <mtr><mtd><mn>1</mn></mtd><mtd><mrow><mi>x</mi><mo>&gt;</mo><mn>0</mn></mrow></mtd></mtr>\
+             <mtr><mtd><mn>0</mn></mtd></mtr></mtable></mrow>"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            convert("\\unknown{x}", false),
+            Err("unsupported command \\unknown".to_string())
+        );
+        assert!(convert("\\frac{1}", false).is_err());
+        assert!(convert("{x", false).is_err());
+        assert!(convert("x}", false).is_err());
+        assert!(convert("\\begin{matrix} a \\end{pmatrix}", false).is_err());
+        assert!(convert("\\begin{align} a \\end{align}", false).is_err());
+    }
+}
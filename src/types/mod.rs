@@ -1 +1,6 @@
 pub mod elements;
+pub mod mathml;
+pub mod options;
+pub mod page;
+pub mod sanitize;
+pub mod sourcemap;
@@ -0,0 +1,263 @@
+/*
+ * Options.
+ *
+ * Controls which Markdown extensions the Lexer and Parser recognise, and how the renderers in
+ * `elements` write HTML. The same pipeline can then be used for trusted documents and for
+ * untrusted, user submitted content.
+ */
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+/*
+ * Syntax extensions on top of plain Markdown. Turning one off makes its syntax render as plain
+ * text.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extensions {
+    // ~~deleted~~
+    pub strikethrough: bool,
+    // ==marked==
+    pub highlight: bool,
+    // ^superscript^
+    pub superscript: bool,
+    // ~subscript~, which takes the single tildes strikethrough would otherwise use
+    pub subscript: bool,
+    pub footnotes: bool,
+    pub smart_punctuation: bool,
+    pub heading_ids: bool,
+    pub raw_html: bool,
+    // A line with just [[toc]] on it becomes a table of contents
+    pub toc: bool,
+    // List items starting with [ ] or [x] get a checkbox
+    pub task_lists: bool,
+    // URLs and email addresses in plain text become links, without needing angle brackets
+    pub autolinks: bool,
+    // A line of text followed by `: definition` lines makes a definition list
+    pub definition_lists: bool,
+    // > [!NOTE] callouts and :::warning containers
+    pub admonitions: bool,
+    // $inline$ and $$display$$ math, rendered as MathML
+    pub math: bool,
+    // :shortcode: emoji
+    pub emoji: bool,
+    /*
+     * Settings between --- lines at the very start, which are read rather than rendered. Off by
+     * default, as a document may just as well start with a thematic break
+     */
+    pub front_matter: bool,
+    // [[Page Name]] links to the pages in wiki_pages
+    pub wiki_links: bool,
+    // {#id .class key=value} attributes on blocks, links, images, code spans and [spans]
+    pub attributes: bool,
+    // *[HTML]: Hyper Text Markup Language definitions, marking the abbreviation up wherever it is used
+    pub abbreviations: bool,
+}
+
+impl Extensions {
+    // Every extension switched off
+    pub fn none() -> Self {
+        Extensions {
+            strikethrough: false,
+            highlight: false,
+            superscript: false,
+            subscript: false,
+            footnotes: false,
+            smart_punctuation: false,
+            heading_ids: false,
+            raw_html: false,
+            toc: false,
+            task_lists: false,
+            autolinks: false,
+            definition_lists: false,
+            admonitions: false,
+            math: false,
+            emoji: false,
+            front_matter: false,
+            wiki_links: false,
+            attributes: false,
+            abbreviations: false,
+        }
+    }
+}
+
+impl Default for Extensions {
+    fn default() -> Self {
+        Extensions {
+            strikethrough: true,
+            highlight: true,
+            superscript: true,
+            subscript: true,
+            footnotes: true,
+            smart_punctuation: false,
+            heading_ids: true,
+            raw_html: true,
+            toc: true,
+            task_lists: true,
+            autolinks: true,
+            definition_lists: true,
+            admonitions: true,
+            math: true,
+            emoji: true,
+            front_matter: false,
+            wiki_links: true,
+            attributes: true,
+            abbreviations: true,
+        }
+    }
+}
+
+// How void elements such as <link> and <hr> are written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HtmlStyle {
+    Html5,
+    Xhtml,
+}
+
+// What safe mode does with HTML that isn't on the allowlist
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HtmlFilter {
+    // Show it as text
+    Escape,
+    // Leave it out
+    Strip,
+}
+
+/*
+ * The HTML which safe mode lets through. Any other tag or attribute is filtered out, and so
+ * are URLs that would run script
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct HtmlAllowlist {
+    pub tags: Vec<String>,
+    pub attributes: Vec<String>,
+}
+
+impl Default for HtmlAllowlist {
+    fn default() -> Self {
+        let tags = [
+            "a",
+            "abbr",
+            "b",
+            "blockquote",
+            "br",
+            "code",
+            "dd",
+            "del",
+            "details",
+            "div",
+            "dl",
+            "dt",
+            "em",
+            "h1",
+            "h2",
+            "h3",
+            "h4",
+            "h5",
+            "h6",
+            "hr",
+            "i",
+            "img",
+            "ins",
+            "kbd",
+            "li",
+            "mark",
+            "ol",
+            "p",
+            "pre",
+            "q",
+            "s",
+            "samp",
+            "small",
+            "span",
+            "strong",
+            "sub",
+            "summary",
+            "sup",
+            "table",
+            "tbody",
+            "td",
+            "tfoot",
+            "th",
+            "thead",
+            "tr",
+            "u",
+            "ul",
+            "var",
+        ];
+        let attributes = [
+            "align", "alt", "colspan", "height", "href", "open", "reversed", "rowspan", "src",
+            "start", "title", "width",
+        ];
+        HtmlAllowlist {
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            attributes: attributes.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub extensions: Extensions,
+    pub html_style: HtmlStyle,
+    // Filter raw HTML through the allowlist, and drop links which would run script
+    pub safe_mode: bool,
+    pub html_filter: HtmlFilter,
+    pub html_allowlist: HtmlAllowlist,
+    // Add a data-sourcepos attribute to every block, giving the lines it came from
+    pub sourcepos: bool,
+    // Follow every heading that has an id with a ¶ link to itself
+    pub heading_permalinks: bool,
+    // The heading levels listed in a table of contents
+    pub toc_levels: RangeInclusive<u8>,
+    // The kinds of admonition, in lower case. Any other kind is left as it is
+    pub admonition_types: Vec<String>,
+    // Emoji shortcodes of the site's own, without the colons, and the URLs of their images
+    pub custom_emoji: HashMap<String, String>,
+    // The pages wiki links can go to, by name, and their URLs
+    pub wiki_pages: HashMap<String, String>,
+}
+
+// The callouts GitHub supports
+fn default_admonition_types() -> Vec<String> {
+    ["note", "tip", "important", "warning", "caution"]
+        .iter()
+        .map(|kind| kind.to_string())
+        .collect()
+}
+
+impl Options {
+    // Settings for content we do not trust, such as comments submitted by users
+    pub fn safe() -> Self {
+        Options {
+            extensions: Extensions::default(),
+            html_style: HtmlStyle::Html5,
+            safe_mode: true,
+            html_filter: HtmlFilter::Escape,
+            html_allowlist: HtmlAllowlist::default(),
+            sourcepos: false,
+            heading_permalinks: false,
+            toc_levels: 1..=6,
+            admonition_types: default_admonition_types(),
+            custom_emoji: HashMap::new(),
+            wiki_pages: HashMap::new(),
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            extensions: Extensions::default(),
+            html_style: HtmlStyle::Html5,
+            safe_mode: false,
+            html_filter: HtmlFilter::Escape,
+            html_allowlist: HtmlAllowlist::default(),
+            sourcepos: false,
+            heading_permalinks: false,
+            toc_levels: 1..=6,
+            admonition_types: default_admonition_types(),
+            custom_emoji: HashMap::new(),
+            wiki_pages: HashMap::new(),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+/*
+ * Page data.
+ *
+ * What a template needs to know about a page besides its HTML, collected while it is parsed.
+ */
+use crate::types::elements::TocEntry;
+
+// The task list items on a page, by whether they have been ticked off
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TaskCounts {
+    pub open: usize,
+    pub completed: usize,
+}
+
+impl TaskCounts {
+    pub fn total(&self) -> usize {
+        self.open + self.completed
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PageData {
+    // The title given in the front matter
+    pub title: Option<String>,
+    // The headings, nested as they are in a table of contents
+    pub toc: Vec<TocEntry>,
+    // The id of every heading, in the order they appear
+    pub heading_ids: Vec<String>,
+    pub tasks: TaskCounts,
+    // Problems found in the Markdown, such as footnotes which are never defined
+    pub warnings: Vec<String>,
+}
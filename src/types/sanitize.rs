@@ -0,0 +1,173 @@
+/*
+ * Safe mode HTML.
+ *
+ * Raw HTML from an untrusted document is taken apart tag by tag. Tags on the allowlist are
+ * written out again with only the allowed attributes, everything else is escaped or stripped
+ * depending on the options.
+ */
+use crate::parser::entities;
+use crate::parser::html::{self, Tag};
+use crate::types::elements::{escape_html, runs_script};
+use crate::types::options::{HtmlFilter, Options};
+
+// Tags whose content goes with them when they are stripped
+const STRIP_CONTENT: [&str; 2] = ["script", "style"];
+
+// Attributes holding a URL
+pub const URL_ATTRIBUTES: [&str; 2] = ["href", "src"];
+
+pub fn sanitize(html: &str, options: &Options) -> String {
+    let allowlist = &options.html_allowlist;
+    let mut master = String::new();
+    let mut rest = html;
+    while let Some(open) = rest.find('<') {
+        master.push_str(&rest[..open]);
+        rest = &rest[open..];
+        if let Some(tag) = html::tag(rest) {
+            let name = tag.name.to_ascii_lowercase();
+            if allowlist.tags.contains(&name) {
+                master.push_str(&rebuild(&tag, &name, options));
+            } else if options.html_filter == HtmlFilter::Escape {
+                master.push_str(&escape_html(&rest[..tag.length]));
+            } else if !tag.closing && STRIP_CONTENT.contains(&name.as_str()) {
+                // Skip over the script itself, not just the tags around it
+                let lower = rest.to_ascii_lowercase();
+                let closing = format!("</{}", name);
+                rest = match lower.find(&closing) {
+                    Some(end) => &rest[end..],
+                    None => "",
+                };
+                continue;
+            }
+            rest = &rest[tag.length..];
+        } else if let Some(length) = html::special(rest) {
+            // Comments, processing instructions and the like are never allowed
+            if options.html_filter == HtmlFilter::Escape {
+                master.push_str(&escape_html(&rest[..length]));
+            }
+            rest = &rest[length..];
+        } else {
+            master.push_str("&lt;");
+            rest = &rest[1..];
+        }
+    }
+    master.push_str(rest);
+    master
+}
+
+// Write an allowed tag out again, keeping only the allowed attributes
+fn rebuild(tag: &Tag, name: &str, options: &Options) -> String {
+    if tag.closing {
+        return format!("</{}>", name);
+    }
+    let mut master = format!("<{}", name);
+    for (attribute, value) in &tag.attributes {
+        let attribute = attribute.to_ascii_lowercase();
+        if !options.html_allowlist.attributes.contains(&attribute) {
+            continue;
+        }
+        match value {
+            Some(value) => {
+                let value = decode_entities(value);
+                if URL_ATTRIBUTES.contains(&attribute.as_str()) && runs_script(&value) {
+                    continue;
+                }
+                master.push_str(&format!(" {}=\"{}\"", attribute, escape_html(&value)));
+            }
+            None => master.push_str(&format!(" {}", attribute)),
+        }
+    }
+    if tag.self_closing {
+        master.push_str(" /");
+    }
+    master.push('>');
+    master
+}
+
+// Replace the entities in an attribute value with the characters they stand for
+fn decode_entities(value: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference =
+            rest[1..]
+                .find(';')
+                .map(|end| &rest[1..end + 1])
+                .and_then(|name| match name.strip_prefix('#') {
+                    Some(number) => entities::decode_numeric(number).map(|c| (name, c.to_string())),
+                    None => entities::lookup(name).map(|text| (name, text.to_string())),
+                });
+        match reference {
+            Some((name, text)) => {
+                decoded.push_str(&text);
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn safe(html: &str) -> String {
+        sanitize(html, &Options::safe())
+    }
+
+    #[test]
+    fn allowed_tags() {
+        assert_eq!(
+            safe("<details open><summary>More</summary>text</details>"),
+            "<details open><summary>More</summary>text</details>"
+        );
+        assert_eq!(
+            safe("<a HREF='/page' onclick=\"steal()\" title=x>link</A>"),
+            "<a href=\"/page\" title=\"x\">link</a>"
+        );
+        assert_eq!(
+            safe("<br/><img src=\"a.png\" alt=\"&quot;a&quot;\">"),
+            "<br /><img src=\"a.png\" alt=\"&quot;a&quot;\">"
+        );
+    }
+
+    #[test]
+    fn script_urls() {
+        assert_eq!(safe("<a href=\"javascript:alert(1)\">x</a>"), "<a>x</a>");
+        assert_eq!(
+            safe("<a href=\"java&#09;script&#58;alert(1)\">x</a>"),
+            "<a>x</a>"
+        );
+        assert_eq!(safe("<img src=\" JAVASCRIPT:x\">"), "<img>");
+        assert_eq!(
+            safe("<a href=\"https://example.com/?a=1&amp;b=2\">x</a>"),
+            "<a href=\"https://example.com/?a=1&amp;b=2\">x</a>"
+        );
+    }
+
+    #[test]
+    fn disallowed_tags() {
+        assert_eq!(
+            safe("<iframe src=\"/x\"></iframe><!-- note --> 1 < 2"),
+            "&lt;iframe src=&quot;/x&quot;&gt;&lt;/iframe&gt;&lt;!-- note --&gt; 1 &lt; 2"
+        );
+        let options = Options {
+            html_filter: HtmlFilter::Strip,
+            ..Options::safe()
+        };
+        assert_eq!(
+            sanitize(
+                "<p>a<script>alert('<b>')</script>b<iframe></iframe><!-- c --></p>",
+                &options
+            ),
+            "<p>ab</p>"
+        );
+    }
+}
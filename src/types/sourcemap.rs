@@ -0,0 +1,108 @@
+/*
+ * Source maps.
+ *
+ * Record which part of the rendered HTML came from which part of the Markdown, so that an editor
+ * can keep its preview in step with the cursor.
+ */
+use std::ops::Range;
+
+use crate::parser::lexer::Span;
+
+/*
+ * Format a span the way the data-sourcepos attribute expects it: "line:column-line:column",
+ * counting from 1 and including the last character
+ */
+pub fn sourcepos(span: &Span) -> String {
+    format!(
+        "{}:{}-{}:{}",
+        span.line + 1,
+        span.column + 1,
+        span.end_line + 1,
+        span.end_column
+    )
+}
+
+// A range of bytes in the HTML, and the Markdown it was rendered from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub output: Range<usize>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceMap {
+    pub mappings: Vec<Mapping>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap { mappings: vec![] }
+    }
+
+    pub fn add(&mut self, output: Range<usize>, span: Span) {
+        self.mappings.push(Mapping { output, span });
+    }
+
+    /*
+     * The mapping as JSON, e.g.
+     * {"mappings":[{"output":[0,21],"source":[0,13],"sourcepos":"1:1-1:13"}]}
+     */
+    pub fn to_json(&self) -> String {
+        let mappings: Vec<String> = self
+            .mappings
+            .iter()
+            .map(|mapping| {
+                format!(
+                    "{{\"output\":[{},{}],\"source\":[{},{}],\"sourcepos\":\"{}\"}}",
+                    mapping.output.start,
+                    mapping.output.end,
+                    mapping.span.start,
+                    mapping.span.end,
+                    sourcepos(&mapping.span)
+                )
+            })
+            .collect();
+        format!("{{\"mappings\":[{}]}}", mappings.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::options::Options;
+    use crate::{markdown_to_html, markdown_to_html_with_source_map};
+
+    #[test]
+    fn sourcepos_attributes() {
+        let options = Options {
+            sourcepos: true,
+            ..Options::default()
+        };
+        let html = markdown_to_html("# Title\n\nSome text\n```\ncode\n```\n", &options);
+        assert_eq!(
+            html,
+            "<h1 id=\"title\" data-sourcepos=\"1:1-1:7\">Title</h1>\n\
+             <p data-sourcepos=\"3:1-3:9\">Some text</p>\n\
+             <pre data-sourcepos=\"4:1-6:3\"><code>\ncode\n</code></pre>\n"
+        );
+        let html = markdown_to_html("Some text\n", &Options::default());
+        assert_eq!(html, "<p>Some text</p>\n");
+    }
+
+    #[test]
+    fn source_map() {
+        let source = "# Title\nSome text\n";
+        let (html, map) = markdown_to_html_with_source_map(source, &Options::default());
+        assert_eq!(map.mappings.len(), 2);
+        let paragraph = &map.mappings[1];
+        assert_eq!(&html[paragraph.output.clone()], "<p>Some text</p>");
+        assert_eq!(
+            &source[paragraph.span.start..paragraph.span.end],
+            "Some text"
+        );
+        assert_eq!(
+            map.to_json(),
+            "{\"mappings\":[{\"output\":[0,25],\"source\":[0,7],\"sourcepos\":\"1:1-1:7\"},\
+             {\"output\":[26,42],\"source\":[8,17],\"sourcepos\":\"2:1-2:9\"}]}"
+        );
+    }
+}